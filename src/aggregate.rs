@@ -0,0 +1,327 @@
+//! `count`, `sum`, `min` and `max` over a table, folded as the leaves are
+//! walked. Each row is decoded, added to the running totals and dropped, so
+//! memory stays the same whatever the table size. A `select` whose list is
+//! aggregate calls, such as `select count(*), max(a) from t`, runs here.
+
+use std::{cmp::Ordering, fmt::Display};
+
+use crate::{
+    datatype::{Collation, DataType, ScalarValue, Schema},
+    errors::Error,
+    expr::Expr,
+    table::Table,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Count,
+    Sum,
+    Min,
+    Max,
+}
+
+impl Function {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "count" => Some(Function::Count),
+            "sum" => Some(Function::Sum),
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Function::Count => "count",
+            Function::Sum => "sum",
+            Function::Min => "min",
+            Function::Max => "max",
+        };
+        f.write_str(name)
+    }
+}
+
+/// An aggregate call such as `count(*)` or `sum(a + 1)`. Only `count` can
+/// go without an argument, written `*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aggregate {
+    pub function: Function,
+    pub arg: Option<Expr>,
+}
+
+impl Display for Aggregate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.arg {
+            Some(arg) => write!(f, "{}({arg})", self.function),
+            None => write!(f, "{}(*)", self.function),
+        }
+    }
+}
+
+impl Aggregate {
+    pub fn parse(s: &str, schema: &Schema) -> Result<Self, Error> {
+        let (name, arg) = s
+            .trim()
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or(Error::ParseError)?;
+        let name = name.trim_end();
+        let function =
+            Function::from_name(name).ok_or_else(|| Error::NotFound(format!("function {name}")))?;
+        let arg = match arg.trim() {
+            "*" if function == Function::Count => None,
+            arg => Some(Expr::parse(arg, schema)?),
+        };
+        Ok(Self { function, arg })
+    }
+
+    /// The type of the value the aggregate gives.
+    pub fn data_type(&self, schema: &Schema) -> DataType {
+        match (self.function, &self.arg) {
+            (Function::Min | Function::Max, Some(arg)) => arg.data_type(schema),
+            _ => DataType::Number,
+        }
+    }
+}
+
+/// An aggregate of a select list with the name of its column: the name
+/// given with `as`, or the aggregate as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamedAggregate {
+    pub aggregate: Aggregate,
+    pub name: String,
+}
+
+impl Display for NamedAggregate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let aggregate = self.aggregate.to_string();
+        match aggregate == self.name {
+            true => f.write_str(&aggregate),
+            false => write!(f, "{aggregate} as {}", self.name),
+        }
+    }
+}
+
+// The running value of one aggregate
+enum State {
+    Count(i64),
+    Sum(Option<i64>),
+    // The smallest or largest value so far, and the collation that orders
+    // its argument
+    Extreme(Option<ScalarValue>, Collation),
+}
+
+/// Computes every aggregate in one pass over `table`. Nulls are skipped:
+/// `count(x)` counts the rows where `x` is not null, and `sum`, `min` and
+/// `max` are null when no row gives a value. `sum` of text is an error.
+pub fn aggregate(table: &Table, aggregates: &[Aggregate]) -> Result<Vec<ScalarValue>, Error> {
+    let schema = table.schema();
+    let mut states: Vec<State> = aggregates
+        .iter()
+        .map(|aggregate| match aggregate.function {
+            Function::Count => State::Count(0),
+            Function::Sum => State::Sum(None),
+            Function::Min | Function::Max => {
+                let collation = aggregate.arg.as_ref().and_then(|arg| arg.collation(schema));
+                State::Extreme(None, collation.unwrap_or_default())
+            }
+        })
+        .collect();
+
+    table.for_each(|row| {
+        for (aggregate, state) in aggregates.iter().zip(&mut states) {
+            let value = match &aggregate.arg {
+                Some(arg) => arg.eval(&row)?,
+                // count(*) counts every row
+                None => ScalarValue::Number(1),
+            };
+            if value == ScalarValue::Null {
+                continue;
+            }
+            match state {
+                State::Count(n) => *n += 1,
+                State::Sum(sum) => {
+                    let ScalarValue::Number(x) = value else {
+                        return Err(Error::InvalidOperand {
+                            op: Function::Sum.to_string(),
+                            got: value.type_name(),
+                        });
+                    };
+                    let total = sum.unwrap_or(0);
+                    *sum = Some(
+                        total
+                            .checked_add(x)
+                            .ok_or_else(|| Error::NumberOutOfRange(format!("{total} + {x}")))?,
+                    );
+                }
+                State::Extreme(extreme, collation) => {
+                    let keep = match aggregate.function {
+                        Function::Min => Ordering::Less,
+                        _ => Ordering::Greater,
+                    };
+                    let replace = match extreme {
+                        Some(current) => collation.compare(&value, current) == keep,
+                        None => true,
+                    };
+                    if replace {
+                        *extreme = Some(value);
+                    }
+                }
+            }
+        }
+        Ok(())
+    })?;
+
+    Ok(states
+        .into_iter()
+        .map(|state| match state {
+            State::Count(n) => ScalarValue::Number(n),
+            State::Sum(sum) => sum.map_or(ScalarValue::Null, ScalarValue::Number),
+            State::Extreme(extreme, _) => extreme.unwrap_or(ScalarValue::Null),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        datatype::{Collation, DataType, ScalarValue, Schema},
+        errors::Error,
+        statement::InsertStatement,
+        table::{Table, MEMORY_PATH},
+    };
+
+    use super::{aggregate, Aggregate};
+
+    fn table() -> Table {
+        let mut schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        schema.options[1].collation = Collation::NoCase;
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        let count = table.rows_per_page() as i64 * 3;
+        for key in 0..count {
+            let a = match key % 10 {
+                0 => ScalarValue::Null,
+                _ => ScalarValue::Number(key * 37 % 101 - 50),
+            };
+            let b = ScalarValue::String(match key {
+                7 => "Zed".to_string(),
+                8 => "apple".to_string(),
+                _ => format!("m{key}"),
+            });
+            let values = vec![a, b];
+            let statement = InsertStatement {
+                key,
+                values,
+                returning: None,
+            };
+            table.insert(statement).unwrap();
+        }
+        table
+    }
+
+    fn run(table: &Table, aggregates: &[&str]) -> Result<Vec<ScalarValue>, Error> {
+        let aggregates: Vec<Aggregate> = aggregates
+            .iter()
+            .map(|s| Aggregate::parse(s, table.schema()).unwrap())
+            .collect();
+        aggregate(table, &aggregates)
+    }
+
+    #[test]
+    fn aggregates_over_leaves() {
+        let table = table();
+        let a: Vec<i64> = table
+            .scan()
+            .unwrap()
+            .iter()
+            .filter_map(|row| match row[0] {
+                ScalarValue::Number(x) => Some(x),
+                _ => None,
+            })
+            .collect();
+
+        let res = run(
+            &table,
+            &[
+                "count(*)",
+                "count(a)",
+                "sum(a)",
+                "min(a)",
+                "max(a)",
+                "sum(a * 2 + 1)",
+                "min(b)",
+                "max(b)",
+            ],
+        )
+        .unwrap();
+        let number = ScalarValue::Number;
+        let text = |x: &str| ScalarValue::String(x.to_string());
+        assert_eq!(
+            res,
+            [
+                number(table.header.num_rows as i64),
+                number(a.len() as i64),
+                number(a.iter().sum()),
+                number(*a.iter().min().unwrap()),
+                number(*a.iter().max().unwrap()),
+                number(a.iter().map(|x| x * 2 + 1).sum()),
+                // NoCase puts "apple" first and "Zed" last
+                text("apple"),
+                text("Zed"),
+            ]
+        );
+    }
+
+    #[test]
+    fn one_pass_over_the_leaves() {
+        let mut table = table();
+        let stats = table.stats().unwrap();
+        assert!(stats.leaf_pages > 1);
+
+        // The descent to the first leaf, then a walk over every leaf once,
+        // are all the pages that are looked at
+        table.pages.reset_stats();
+        run(&table, &["count(*)", "sum(a)", "max(b)"]).unwrap();
+        let pager = table.pages.stats();
+        assert_eq!(pager.reads + pager.hits, stats.height + stats.leaf_pages);
+    }
+
+    #[test]
+    fn empty_and_invalid() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        let res = run(&table, &["count(*)", "sum(a)", "min(a)"]).unwrap();
+        assert_eq!(
+            res,
+            [ScalarValue::Number(0), ScalarValue::Null, ScalarValue::Null]
+        );
+
+        let table = self::table();
+        assert!(matches!(
+            run(&table, &["sum(b)"]),
+            Err(Error::InvalidOperand { op, got: "text" }) if op == "sum"
+        ));
+        assert!(matches!(
+            Aggregate::parse("avg(a)", table.schema()),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            Aggregate::parse("sum(*)", table.schema()),
+            Err(Error::UnexpectedToken { .. })
+        ));
+        assert_eq!(
+            Aggregate::parse("count( * )", table.schema())
+                .unwrap()
+                .to_string(),
+            "count(*)"
+        );
+    }
+}
@@ -0,0 +1,410 @@
+//! Several tables stored in one file, as sqlite keeps a whole database.
+//! Each table gets a region of the file to itself (see `table::Region`),
+//! holding its header and pages just as a file of its own would. The file
+//! starts with a catalog listing every table's name, schema and root page.
+//! A dropped table leaves the catalog, and its region goes to the next
+//! table created.
+
+use std::{
+    fs::OpenOptions,
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{
+    datatype::Schema,
+    errors::Error,
+    table::{
+        check_schema, Backend, Region, Table, TableOptions, FORMAT_VERSION, HEADER_SPACE,
+        MEMORY_PATH,
+    },
+};
+
+/// Marks a file as holding a catalog of tables rather than a single table.
+const MAGIC: [u8; 4] = *b"SCDM";
+// The magic bytes, the format version and the length of the serialized
+// entries that follow
+const CATALOG_PREFIX: usize = 12;
+
+/// A table as the catalog lists it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CatalogEntry {
+    pub name: String,
+    pub schema: Schema,
+    /// The page of the file the table's b-tree is rooted at.
+    pub root_page: u32,
+}
+
+/// A file of tables. Each table should have at most one open handle, as
+/// with a file per table. The catalog is read from the file each time, as
+/// the tables' handles rename and drop their entries themselves.
+#[derive(Debug)]
+pub struct SharedFile {
+    file: Arc<Mutex<Backend>>,
+    path: PathBuf,
+}
+
+impl SharedFile {
+    /// Opens the file at `path`, starting an empty catalog in a new file.
+    /// The path `:memory:` keeps the tables in memory.
+    ///
+    /// A table renamed or altered since the catalog was written shows up
+    /// under its new name and schema, which are written back to the catalog.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut file = if path == Path::new(MEMORY_PATH) {
+            Backend::memory()
+        } else {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?
+                .into()
+        };
+        let mut entries = match file.is_empty()? {
+            true => Vec::new(),
+            false => read_catalog(&mut file)?,
+        };
+        let shared = Self {
+            file: Arc::new(Mutex::new(file)),
+            path: path.to_path_buf(),
+        };
+
+        let mut changed = entries.is_empty();
+        for entry in &mut entries {
+            let table = shared.table_at(entry.root_page)?;
+            if entry.name != table.header.name || entry.schema != table.header.schema {
+                entry.name = table.header.name;
+                entry.schema = table.header.schema;
+                changed = true;
+            }
+        }
+        if changed {
+            write_catalog(&mut shared.lock(), &entries)?;
+        }
+        Ok(shared)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every table in the file, in the order they were created.
+    pub fn entries(&self) -> Result<Vec<CatalogEntry>, Error> {
+        read_catalog(&mut self.lock())
+    }
+
+    /// Opens table `name`, creating it with `schema` in the first region no
+    /// table holds if the catalog has no table by that name.
+    pub fn open_table(&mut self, name: &str, schema: Schema) -> Result<Table, Error> {
+        let mut entries = self.entries()?;
+        if let Some(entry) = entries.iter().find(|entry| entry.name == name) {
+            return self.table_at(entry.root_page);
+        }
+
+        check_schema(name, &schema)?;
+        let taken: Vec<u64> = entries
+            .iter()
+            .map(|entry| Region::start_of(entry.root_page))
+            .collect();
+        let start = (0..)
+            .map(|i| HEADER_SPACE as u64 + i * Region::SIZE)
+            .find(|start| !taken.contains(start))
+            .expect("fewer tables than regions");
+        let region = Region::open(self.file.clone(), start)?;
+        let root_page = region.root_page();
+        let table = Table::open_backend(
+            name.to_string(),
+            schema.clone(),
+            Backend::Region(region),
+            &self.path,
+            TableOptions::default(),
+        )?;
+        entries.push(CatalogEntry {
+            name: name.to_string(),
+            schema,
+            root_page,
+        });
+        // Left out of the catalog, the region is taken by the next table
+        write_catalog(&mut self.lock(), &entries)?;
+        Ok(table)
+    }
+
+    fn table_at(&self, root_page: u32) -> Result<Table, Error> {
+        let region = Region::open(self.file.clone(), Region::start_of(root_page))?;
+        Table::open(Backend::Region(region), &self.path)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Backend> {
+        self.file.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Creates table `name` in the first free region of `file`, the file at
+/// `path`, failing when the catalog already has a table by that name.
+pub(crate) fn create_entry(
+    file: Arc<Mutex<Backend>>,
+    path: &Path,
+    name: &str,
+    schema: Schema,
+) -> Result<Table, Error> {
+    let mut shared = SharedFile {
+        file,
+        path: path.to_path_buf(),
+    };
+    if shared.entries()?.iter().any(|entry| entry.name == name) {
+        return Err(Error::AlreadyExists(format!("table {name}")));
+    }
+    shared.open_table(name, schema)
+}
+
+/// Renames the entry of the table rooted at `root_page`, failing when
+/// another table in the file has the name.
+pub(crate) fn rename_entry(file: &mut Backend, root_page: u32, name: &str) -> Result<(), Error> {
+    let mut entries = read_catalog(file)?;
+    if entries
+        .iter()
+        .any(|entry| entry.name == name && entry.root_page != root_page)
+    {
+        return Err(Error::AlreadyExists(format!("table {name}")));
+    }
+    if let Some(entry) = entries
+        .iter_mut()
+        .find(|entry| entry.root_page == root_page)
+    {
+        entry.name = name.to_string();
+    }
+    write_catalog(file, &entries)
+}
+
+/// Takes the table rooted at `root_page` out of the catalog.
+pub(crate) fn remove_entry(file: &mut Backend, root_page: u32) -> Result<(), Error> {
+    let mut entries = read_catalog(file)?;
+    entries.retain(|entry| entry.root_page != root_page);
+    write_catalog(file, &entries)
+}
+
+fn write_catalog(file: &mut Backend, entries: &[CatalogEntry]) -> Result<(), Error> {
+    let body = bincode::serialize(entries)?;
+    if body.len() > HEADER_SPACE - CATALOG_PREFIX {
+        return Err(Error::HeaderTooLarge {
+            size: body.len(),
+            limit: HEADER_SPACE - CATALOG_PREFIX,
+        });
+    }
+    let mut space = vec![0u8; HEADER_SPACE];
+    space[0..4].copy_from_slice(&MAGIC);
+    space[4..8].copy_from_slice(&FORMAT_VERSION.to_ne_bytes());
+    space[8..12].copy_from_slice(&(body.len() as u32).to_ne_bytes());
+    space[CATALOG_PREFIX..CATALOG_PREFIX + body.len()].copy_from_slice(&body);
+
+    file.seek(io::SeekFrom::Start(0))?;
+    file.write_all(&space)?;
+    file.flush()?;
+    Ok(())
+}
+
+fn read_catalog(file: &mut Backend) -> Result<Vec<CatalogEntry>, Error> {
+    if file.len()? < HEADER_SPACE as u64 {
+        return Err(Error::BadMagic);
+    }
+    let mut space = vec![0u8; HEADER_SPACE];
+    file.seek(io::SeekFrom::Start(0))?;
+    file.read_exact(&mut space)?;
+    if space[0..4] != MAGIC {
+        return Err(Error::BadMagic);
+    }
+    let version = u32::from_ne_bytes(space[4..8].try_into().unwrap());
+    if version != FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion {
+            found: version,
+            supported: FORMAT_VERSION,
+        });
+    }
+    let len = u32::from_ne_bytes(space[8..12].try_into().unwrap()) as usize;
+    let body = space
+        .get(CATALOG_PREFIX..CATALOG_PREFIX + len)
+        .ok_or(Error::BadMagic)?;
+    Ok(bincode::deserialize(body)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::Path};
+
+    use crate::{
+        datatype::{DataType, ScalarValue, Schema},
+        errors::Error,
+        statement::InsertStatement,
+        table::{Table, MEMORY_PATH},
+    };
+
+    use super::SharedFile;
+
+    fn insert(table: &mut Table, key: i64, values: Vec<ScalarValue>) {
+        let statement = InsertStatement {
+            key,
+            values,
+            returning: None,
+        };
+        table.insert(statement).unwrap();
+    }
+
+    #[test]
+    fn two_tables_in_one_file() {
+        let path = env::temp_dir().join("two_tables_in_one_file.db");
+        let _ = fs::remove_file(&path);
+        let numbers = Schema::new(vec![("n".to_string(), DataType::Number)]);
+        let names = Schema::new(vec![("name".to_string(), DataType::String(12))]);
+
+        let mut shared = SharedFile::open(&path).unwrap();
+        let mut first = shared.open_table("first", numbers.clone()).unwrap();
+        let mut second = shared.open_table("second", names.clone()).unwrap();
+        // Enough rows for the first table to split its root, interleaved
+        // with the second's writes
+        for key in 0..600 {
+            insert(&mut first, key, vec![ScalarValue::Number(key * 2)]);
+            if key % 100 == 0 {
+                let name = ScalarValue::String(format!("name {key}"));
+                insert(&mut second, key, vec![name]);
+            }
+        }
+        first.pages.flush_all().unwrap();
+        first.flush_table_header().unwrap();
+        second.pages.flush_all().unwrap();
+        second.flush_table_header().unwrap();
+        drop((first, second));
+
+        let mut shared = SharedFile::open(&path).unwrap();
+        let entries = shared.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            (entries[0].name.as_str(), &entries[0].schema),
+            ("first", &numbers)
+        );
+        assert_eq!(
+            (entries[1].name.as_str(), &entries[1].schema),
+            ("second", &names)
+        );
+        assert_ne!(entries[0].root_page, entries[1].root_page);
+
+        let first = shared.open_table("first", numbers.clone()).unwrap();
+        let second = shared.open_table("second", names).unwrap();
+        assert_eq!(first.len(), 600);
+        assert_eq!(second.len(), 6);
+        first.check_invariants().unwrap();
+        for key in [0, 299, 599] {
+            assert_eq!(first.read(key).unwrap()["n"], ScalarValue::Number(key * 2));
+        }
+        assert_eq!(
+            second.read(300).unwrap()["name"],
+            ScalarValue::String("name 300".to_string())
+        );
+        assert!(matches!(second.read(1), Err(Error::NotFound(_))));
+
+        // The file is no single table
+        assert!(matches!(Table::open_read_only(&path), Err(Error::BadMagic)));
+        drop((first, second));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn clone_stays_in_file() {
+        let path = env::temp_dir().join("clone_stays_in_file.db");
+        let clone_path = path.with_file_name("copy.db");
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&clone_path);
+        let schema = Schema::new(vec![("n".to_string(), DataType::Number)]);
+        let mut shared = SharedFile::open(&path).unwrap();
+        let mut table = shared.open_table("original", schema.clone()).unwrap();
+        for key in 0..300 {
+            insert(&mut table, key, vec![ScalarValue::Number(key)]);
+        }
+        let mut clone = table.clone_to("copy".to_string()).unwrap();
+        insert(&mut clone, 300, vec![ScalarValue::Number(300)]);
+        assert!(matches!(
+            table.clone_to("copy".to_string()),
+            Err(Error::AlreadyExists(_))
+        ));
+        drop((table, clone));
+        // No file of its own
+        assert!(!clone_path.exists());
+
+        let mut shared = SharedFile::open(&path).unwrap();
+        let entries = shared.entries().unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["original", "copy"]);
+        assert_ne!(entries[0].root_page, entries[1].root_page);
+
+        let table = shared.open_table("original", schema.clone()).unwrap();
+        let clone = shared.open_table("copy", schema).unwrap();
+        assert_eq!((table.len(), clone.len()), (300, 301));
+        clone.check_invariants().unwrap();
+        assert_eq!(clone.read(299).unwrap()["n"], ScalarValue::Number(299));
+        drop((table, clone));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn renamed_table_updates_catalog() {
+        let path = env::temp_dir().join("renamed_table_updates_catalog.db");
+        let _ = fs::remove_file(&path);
+        let schema = Schema::new(vec![("n".to_string(), DataType::Number)]);
+        let mut shared = SharedFile::open(&path).unwrap();
+        let mut table = shared.open_table("before", schema.clone()).unwrap();
+        insert(&mut table, 1, vec![ScalarValue::Number(1)]);
+        table.rename("after".to_string()).unwrap();
+        table.truncate().unwrap();
+        drop(table);
+        // Renaming a table in the file leaves the file where it was
+        assert!(path.is_file());
+
+        let mut shared = SharedFile::open(&path).unwrap();
+        assert_eq!(shared.entries().unwrap()[0].name, "after");
+        let table = shared.open_table("after", schema).unwrap();
+        assert!(table.is_empty());
+        fs::remove_file(path).unwrap();
+
+        let mut memory = SharedFile::open(Path::new(MEMORY_PATH)).unwrap();
+        let empty = Schema::new(Vec::new());
+        assert!(matches!(
+            memory.open_table("t", empty),
+            Err(Error::InvalidSchema(_))
+        ));
+        assert!(memory.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn dropped_table_leaves_catalog() {
+        let mut shared = SharedFile::open(Path::new(MEMORY_PATH)).unwrap();
+        let schema = Schema::new(vec![("n".to_string(), DataType::Number)]);
+        let mut first = shared.open_table("first", schema.clone()).unwrap();
+        let mut second = shared.open_table("second", schema.clone()).unwrap();
+        insert(&mut first, 1, vec![ScalarValue::Number(1)]);
+
+        assert!(matches!(
+            second.rename("first".to_string()),
+            Err(Error::AlreadyExists(name)) if name == "table first"
+        ));
+        assert_eq!(second.name(), "second");
+
+        let root_page = shared.entries().unwrap()[0].root_page;
+        first.drop_table().unwrap();
+        let entries = shared.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "second");
+        // The name is free again, and the new table takes the dropped one's
+        // region without its rows
+        second.rename("first".to_string()).unwrap();
+        let third = shared.open_table("third", schema).unwrap();
+        assert!(third.is_empty());
+        let entries = shared.entries().unwrap();
+        assert_eq!(entries[0].name, "first");
+        assert_eq!(
+            (entries[1].name.as_str(), entries[1].root_page),
+            ("third", root_page)
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Binary encoding of a single value, shared by anything that stores rows.
+//! Values take the fixed width of their type, except compact numbers, which
+//! are varints, and dictionary strings, which are the id of the string in
+//! the table's dictionary. NULL is not encoded here; callers track it
+//! separately (leaf cells use a null bitmap) and the bytes of a null value
+//! are zeroed.
+
+use crate::{
+    datatype::{DataType, ScalarValue},
+    dictionary::Dictionary,
+};
+
+/// Writes `value` into the first `ty.size()` bytes of `bytes`.
+///
+/// Strings are stored after a one byte length. Strings longer than the
+/// rest of the column, or than 255 bytes, are truncated to fit, without
+/// splitting a character. Char strings take the whole column with no length,
+/// truncated the same way and padded with spaces. Custom types are handed
+/// the text form of their value and encode it themselves.
+pub fn encode_value(value: &ScalarValue, ty: &DataType, bytes: &mut [u8]) {
+    let bytes = &mut bytes[..ty.size()];
+    match (ty, value) {
+        (_, ScalarValue::Null) => bytes.fill(0),
+        (DataType::String(_), ScalarValue::String(value)) => {
+            let Some((len, bytes)) = bytes.split_first_mut() else {
+                return;
+            };
+            let mut written = value.len().min(bytes.len()).min(u8::MAX as usize);
+            while !value.is_char_boundary(written) {
+                written -= 1;
+            }
+            bytes[..written].copy_from_slice(&value.as_bytes()[..written]);
+            *len = written as u8;
+        }
+        (DataType::Char(_), ScalarValue::String(value)) => {
+            let mut written = value.len().min(bytes.len());
+            while !value.is_char_boundary(written) {
+                written -= 1;
+            }
+            let (value_bytes, padding) = bytes.split_at_mut(written);
+            value_bytes.copy_from_slice(&value.as_bytes()[..written]);
+            padding.fill(b' ');
+        }
+        (DataType::Number, ScalarValue::Number(value)) => {
+            bytes.copy_from_slice(&value.to_ne_bytes());
+        }
+        (DataType::Custom(ty), ScalarValue::String(text)) if ty.encode(text, bytes) => {}
+        _ => panic!("{value:?} can not be encoded as {ty}"),
+    }
+}
+
+/// Reads a value of type `ty` from the start of `bytes`, returning it along
+/// with the number of bytes it took.
+pub fn decode_value(bytes: &[u8], ty: &DataType) -> (ScalarValue, usize) {
+    let bytes = &bytes[..ty.size()];
+    let value = match ty {
+        DataType::String(_) => {
+            // A zero width column has no room for the length, so is always empty
+            let (len, bytes) = bytes.split_first().unwrap_or((&0, &[]));
+            ScalarValue::String(String::from_utf8(bytes[..*len as usize].to_owned()).unwrap())
+        }
+        // The padding can't be told apart from trailing spaces in the value,
+        // so both are dropped
+        DataType::Char(_) => {
+            let value = std::str::from_utf8(bytes).unwrap();
+            ScalarValue::String(value.trim_end_matches(' ').to_string())
+        }
+        DataType::Number => ScalarValue::Number(i64::from_ne_bytes(bytes.try_into().unwrap())),
+        DataType::Custom(ty) => ScalarValue::String(ty.decode(bytes)),
+    };
+    (value, ty.size())
+}
+
+/// Bytes a dictionary column takes: the id of its string.
+pub const STRING_ID_SIZE: usize = 4;
+
+/// The string a dictionary column of type `ty` keeps for `value`, cut the
+/// same way as in a plain column so both read back alike.
+pub fn dictionary_string(value: &str, ty: &DataType) -> String {
+    let mut bytes = vec![0; ty.size()];
+    encode_value(&ScalarValue::String(value.to_string()), ty, &mut bytes);
+    match decode_value(&bytes, ty).0 {
+        ScalarValue::String(s) => s,
+        _ => unreachable!("text columns decode to strings"),
+    }
+}
+
+/// Writes the id `dictionary` gives the string in `value` into the first
+/// `STRING_ID_SIZE` bytes of `bytes`. The string must already be interned.
+pub fn encode_string_id(
+    value: &ScalarValue,
+    ty: &DataType,
+    dictionary: &Dictionary,
+    bytes: &mut [u8],
+) {
+    let id = match value {
+        ScalarValue::Null => 0,
+        ScalarValue::String(value) => dictionary
+            .id(&dictionary_string(value, ty))
+            .unwrap_or_else(|| panic!("{value:?} is not in the dictionary")),
+        _ => panic!("{value:?} can not be encoded as {ty}"),
+    };
+    bytes[..STRING_ID_SIZE].copy_from_slice(&id.to_ne_bytes());
+}
+
+/// Reads a string id from the start of `bytes`, returning the string it
+/// stands for along with the number of bytes it took.
+pub fn decode_string_id(bytes: &[u8], dictionary: &Dictionary) -> (ScalarValue, usize) {
+    let id = u32::from_ne_bytes(bytes[..STRING_ID_SIZE].try_into().unwrap());
+    let value = dictionary.get(id).unwrap_or_default();
+    (ScalarValue::String(value.to_string()), STRING_ID_SIZE)
+}
+
+/// The longest string that can be a row key, in bytes.
+pub const MAX_STRING_KEY_SIZE: usize = 8;
+
+/// Packs a string of up to `MAX_STRING_KEY_SIZE` bytes into a row key that
+/// sorts like the string: its bytes big endian, zero padded, with the top
+/// bit flipped so the order holds for signed keys too. Returns `None` for a
+/// longer string, or one holding a NUL, which would read back cut short.
+pub fn encode_string_key(s: &str) -> Option<i64> {
+    if s.len() > MAX_STRING_KEY_SIZE || s.contains('\0') {
+        return None;
+    }
+    let mut bytes = [0; MAX_STRING_KEY_SIZE];
+    bytes[..s.len()].copy_from_slice(s.as_bytes());
+    Some((u64::from_be_bytes(bytes) ^ 1 << 63) as i64)
+}
+
+/// The string `encode_string_key` packed into `key`.
+pub fn decode_string_key(key: i64) -> String {
+    let bytes = (key as u64 ^ 1 << 63).to_be_bytes();
+    let len = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+/// The most bytes a varint takes, for the numbers furthest from zero.
+pub const MAX_VARINT_SIZE: usize = 10;
+
+/// Appends `value` as a LEB128 varint: seven bits per byte, low bits first,
+/// with the top bit set on every byte but the last. The sign is zigzag
+/// encoded first so small negative numbers stay short too.
+pub fn encode_varint(value: i64, bytes: &mut Vec<u8>) {
+    let mut x = ((value << 1) ^ (value >> 63)) as u64;
+    while x >= 0x80 {
+        bytes.push(x as u8 | 0x80);
+        x >>= 7;
+    }
+    bytes.push(x as u8);
+}
+
+/// Reads a varint from the start of `bytes`, returning it along with the
+/// number of bytes it took.
+pub fn decode_varint(bytes: &[u8]) -> (i64, usize) {
+    let mut x = 0u64;
+    let mut len = 0;
+    for (i, byte) in bytes.iter().take(MAX_VARINT_SIZE).enumerate() {
+        x |= ((byte & 0x7f) as u64) << (7 * i);
+        len = i + 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    (((x >> 1) as i64) ^ -((x & 1) as i64), len)
+}
+
+/// Number of bytes `encode_varint` writes for `value`.
+pub fn varint_size(value: i64) -> usize {
+    let x = ((value << 1) ^ (value >> 63)) as u64;
+    (64 - x.leading_zeros() as usize).max(1).div_ceil(7)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datatype::{DataType, ScalarValue};
+
+    use super::{
+        decode_string_key, decode_value, decode_varint, encode_string_key, encode_value,
+        encode_varint, varint_size, MAX_VARINT_SIZE,
+    };
+
+    #[test]
+    fn string_keys_keep_order() {
+        let strings = ["", "\u{1}", "a", "ab", "abc", "b", "zzzzzzzz", "é"];
+        let keys: Vec<i64> = strings
+            .iter()
+            .map(|s| encode_string_key(s).unwrap())
+            .collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]), "{keys:?}");
+        for (s, key) in strings.iter().zip(keys) {
+            assert_eq!(decode_string_key(key), *s);
+        }
+        assert_eq!(encode_string_key("123456789"), None);
+        assert_eq!(encode_string_key("a\0"), None);
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for (x, size) in [
+            (0, 1),
+            (5, 1),
+            (-5, 1),
+            (63, 1),
+            (-64, 1),
+            (64, 2),
+            (1 << 20, 4),
+            (i64::MIN, MAX_VARINT_SIZE),
+            (i64::MAX, MAX_VARINT_SIZE),
+        ] {
+            let mut bytes = vec![0xff];
+            encode_varint(x, &mut bytes);
+            assert_eq!(bytes.len(), 1 + size, "{x}");
+            assert_eq!(varint_size(x), size, "{x}");
+            bytes.push(0xff);
+            assert_eq!(decode_varint(&bytes[1..]), (x, size));
+        }
+    }
+
+    #[test]
+    fn number_round_trip() {
+        let ty = DataType::Number;
+        let mut bytes = [0u8; 8];
+        for x in [0, 1, -1, i64::MIN, i64::MAX] {
+            encode_value(&ScalarValue::Number(x), &ty, &mut bytes);
+            assert_eq!(decode_value(&bytes, &ty), (ScalarValue::Number(x), 8));
+        }
+    }
+
+    #[test]
+    fn string_round_trip() {
+        let ty = DataType::String(6);
+        let mut bytes = [0xffu8; 10];
+        encode_value(&ScalarValue::String("abc".to_string()), &ty, &mut bytes);
+        assert_eq!(
+            decode_value(&bytes, &ty),
+            (ScalarValue::String("abc".to_string()), 6)
+        );
+        // Bytes past the column are left alone
+        assert_eq!(&bytes[6..], &[0xff; 4]);
+
+        encode_value(&ScalarValue::String(String::new()), &ty, &mut bytes);
+        assert_eq!(
+            decode_value(&bytes, &ty),
+            (ScalarValue::String(String::new()), 6)
+        );
+    }
+
+    #[test]
+    fn long_string_is_truncated() {
+        let ty = DataType::String(4);
+        let mut bytes = [0u8; 4];
+        encode_value(&ScalarValue::String("abcdef".to_string()), &ty, &mut bytes);
+        assert_eq!(
+            decode_value(&bytes, &ty),
+            (ScalarValue::String("abc".to_string()), 4)
+        );
+    }
+
+    #[test]
+    fn truncation_keeps_whole_characters() {
+        // "é" takes two bytes, so only the first fits in 3 bytes after the length
+        let ty = DataType::String(4);
+        let mut bytes = [0u8; 4];
+        encode_value(&ScalarValue::String("éé".to_string()), &ty, &mut bytes);
+        assert_eq!(
+            decode_value(&bytes, &ty).0,
+            ScalarValue::String("é".to_string())
+        );
+
+        // The length byte caps strings at 255 bytes
+        let ty = DataType::String(300);
+        let mut bytes = [0u8; 300];
+        encode_value(&ScalarValue::String("a".repeat(299)), &ty, &mut bytes);
+        assert_eq!(
+            decode_value(&bytes, &ty).0,
+            ScalarValue::String("a".repeat(255))
+        );
+    }
+
+    #[test]
+    fn char_is_padded() {
+        let ty = DataType::Char(5);
+        let mut bytes = [0u8; 5];
+        encode_value(&ScalarValue::String("ab".to_string()), &ty, &mut bytes);
+        assert_eq!(&bytes, b"ab   ");
+        assert_eq!(
+            decode_value(&bytes, &ty),
+            (ScalarValue::String("ab".to_string()), 5)
+        );
+
+        // No length byte, so a full width value keeps every byte
+        encode_value(&ScalarValue::String("abcdefg".to_string()), &ty, &mut bytes);
+        assert_eq!(&bytes, b"abcde");
+    }
+
+    #[test]
+    fn null_is_zeroed() {
+        let ty = DataType::Number;
+        let mut bytes = [0xffu8; 8];
+        encode_value(&ScalarValue::Null, &ty, &mut bytes);
+        assert_eq!(bytes, [0; 8]);
+    }
+}
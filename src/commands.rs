@@ -1,13 +1,300 @@
-use crate::errors::Error;
+use std::{
+    fs::File,
+    io::{self, BufReader, Write},
+    path::Path,
+};
 
-pub fn do_meta_commands(command: Command) -> Result<(), Error> {
+use crate::{
+    codec::{MAX_VARINT_SIZE, STRING_ID_SIZE},
+    datatype::Schema,
+    errors::Error,
+    execution::execution,
+    repl::{next_statement, Mode, Repl},
+    statement::{prepare_statement, strip_comments},
+    table::{Table, FORMAT_VERSION},
+    tree::{InternalNode, LeafNode},
+};
+
+pub fn do_meta_commands(command: Command, repl: &mut Repl, table: &mut Table) -> Result<(), Error> {
     match command {
         Command::Exit => std::process::exit(0),
+        Command::Check(sql) => {
+            repl.write_line(check(&sql, table))?;
+            Ok(())
+        }
+        Command::Stats(name) => {
+            if name != table.header.name {
+                return Err(Error::NotFound(format!("table {name}")));
+            }
+            repl.write_line(table.stats()?)?;
+            Ok(())
+        }
+        Command::Layout(name) => {
+            if name != table.header.name {
+                return Err(Error::NotFound(format!("table {name}")));
+            }
+            repl.write_line(layout(table.schema()))?;
+            Ok(())
+        }
+        Command::Clone { src, dst } => {
+            if src != table.header.name {
+                return Err(Error::NotFound(format!("table {src}")));
+            }
+            let clone = table.clone_to(dst)?;
+            repl.write_line(format!("{} rows copied", clone.header.num_rows))?;
+            Ok(())
+        }
+        Command::Save(path) => table.save(Path::new(&path)),
+        Command::Backup(path) => table.backup(Path::new(&path)),
+        Command::Load(path) => {
+            *table = Table::load(Path::new(&path))?;
+            Ok(())
+        }
+        Command::Reindex(name) => {
+            if name != table.header.name {
+                return Err(Error::NotFound(format!("table {name}")));
+            }
+            repl.write_line(format!("{} rows", table.reindex()?))?;
+            Ok(())
+        }
+        Command::Output(path) => Ok(repl.set_output(path.as_deref().map(Path::new))?),
+        Command::Constants => {
+            repl.write_line(constants(table.schema()))?;
+            Ok(())
+        }
+        Command::Pragma { name, value } => {
+            repl.write_line(pragma(table, &name, value.as_deref())?)?;
+            Ok(())
+        }
+        Command::Separator(separator) => {
+            repl.separator = separator;
+            Ok(())
+        }
+        Command::NullValue(null_value) => {
+            repl.null_value = null_value;
+            Ok(())
+        }
+        Command::Mode(mode) => {
+            repl.mode = mode;
+            Ok(())
+        }
+        Command::Width(widths) => {
+            repl.widths = widths;
+            Ok(())
+        }
+        Command::Echo(echo) => {
+            repl.echo = echo;
+            Ok(())
+        }
+        Command::Read(path) => read(Path::new(&path), repl, table),
+        Command::Version => {
+            repl.write_line(version())?;
+            Ok(())
+        }
+        Command::Trace(on) => {
+            let trace: Option<Box<dyn Write + Send>> = match on {
+                true => Some(Box::new(io::stdout())),
+                false => None,
+            };
+            table.pages.set_trace(trace);
+            Ok(())
+        }
+        Command::Indexes(name) => {
+            if name != table.header.name {
+                return Err(Error::NotFound(format!("table {name}")));
+            }
+            for index in table.indexes() {
+                let columns = index.columns.join(", ");
+                repl.write_line(format!("{} on {name}({columns})", index.name))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Runs the meta commands and statements in the file at `path` as if they
+/// were typed at the prompt. A statement or meta command that fails prints
+/// its error and the rest of the file still runs.
+fn read(path: &Path, repl: &mut Repl, table: &mut Table) -> Result<(), Error> {
+    let mut input = BufReader::new(File::open(path)?);
+    while let Some(line) = next_statement(&mut input, false) {
+        repl.echo_line(&line)?;
+        if line.starts_with('.') {
+            let result = line
+                .parse()
+                .and_then(|command| do_meta_commands(command, repl, table));
+            if let Err(err) = result {
+                repl.print_error(&line, &err);
+            }
+            continue;
+        }
+        if strip_comments(&line).trim().is_empty() {
+            continue;
+        }
+
+        let statement = repl.plans.prepare(&line, table);
+        match statement.and_then(|statement| execution(statement, table)) {
+            Ok(result) => repl.print_result(&result)?,
+            Err(err) => repl.print_error(&line, &err),
+        }
+    }
+    Ok(())
+}
+
+// Turns the `\t` and `\n` escapes typed at the prompt into the actual characters
+fn unescape(s: &str) -> String {
+    s.replace("\\t", "\t").replace("\\n", "\n")
+}
+
+/// Describes where each part of a row sits within a leaf cell.
+pub fn layout(schema: &Schema) -> String {
+    let key = LeafNode::KEY_SIZE;
+    let mut lines = vec![
+        format!("offset 0, width {key}: key"),
+        format!(
+            "offset {key}, width {}: null bitmap",
+            schema.null_bitmap_size()
+        ),
+    ];
+    // Past a compact column, a column starts anywhere from `offset` to
+    // `max_offset`
+    let mut offset = key + schema.null_bitmap_size();
+    let mut max_offset = offset;
+    for (i, (name, ty)) in schema.feilds.iter().enumerate() {
+        let start = match max_offset - offset {
+            0 => offset.to_string(),
+            _ => format!("{offset} to {max_offset}"),
+        };
+        let line = if schema.is_compact(i) {
+            format!("offset {start}, width 0 to {MAX_VARINT_SIZE}: {name} {ty} compact")
+        } else if schema.is_dictionary(i) {
+            format!("offset {start}, width {STRING_ID_SIZE}: {name} {ty} dict")
+        } else {
+            format!("offset {start}, width {}: {name} {ty}", ty.size())
+        };
+        lines.push(line);
+        if !schema.is_compact(i) {
+            offset += schema.column_size(i);
+        }
+        max_offset += schema.column_size(i);
+    }
+    lines.join("\n")
+}
+
+/// Lists the node layout constants, with the leaf cell figures worked out
+/// for the rows of `schema`.
+pub fn constants(schema: &Schema) -> String {
+    let row_size = schema.row_size();
+    [
+        ("page_size", crate::PAGE_SIZE),
+        ("table_max_pages", crate::TABLE_MAX_PAGE),
+        ("leaf_header_size", LeafNode::HEADER_SIZE),
+        ("leaf_space_for_cells", LeafNode::SPACE_FOR_CELLS),
+        ("leaf_key_size", LeafNode::KEY_SIZE),
+        ("leaf_slot_size", LeafNode::SLOT_SIZE),
+        ("row_size", row_size),
+        ("leaf_cell_size", LeafNode::KEY_SIZE + row_size),
+        ("leaf_max_cells", LeafNode::capacity(row_size)),
+        ("internal_header_size", InternalNode::NODE_HEADER_SIZE),
+        ("internal_cell_size", InternalNode::NODE_CELL_SIZE),
+        ("internal_max_cells", InternalNode::NODE_MAX_CELLS),
+    ]
+    .map(|(name, value)| format!("{name}: {value}"))
+    .join("\n")
+}
+
+/// The build's crate version and the file format version it reads and writes.
+pub fn version() -> String {
+    format!(
+        "{} (file format {FORMAT_VERSION})",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// Reads the setting `name`, first setting it to `value` when one is given.
+pub fn pragma(table: &mut Table, name: &str, value: Option<&str>) -> Result<String, Error> {
+    match (name, value) {
+        ("cache_size", Some(value)) => {
+            let size = value.parse().map_err(|_| Error::ParseError)?;
+            table.pages.set_cache_size(size)?;
+        }
+        ("coerce", Some(value)) => table.coerce = flag(value)?,
+        ("sync", Some(value)) => table.pages.set_sync(flag(value)?),
+        ("max_scanned_rows", Some(value)) => {
+            let limit = value.parse().map_err(|_| Error::ParseError)?;
+            table.max_scanned_rows = Some(limit).filter(|&limit| limit > 0);
+        }
+        ("sort_buffer_rows", Some(value)) => {
+            let rows: usize = value.parse().map_err(|_| Error::ParseError)?;
+            table.sort_buffer_rows = rows.max(1);
+        }
+        (
+            "cache_size" | "coerce" | "max_scanned_rows" | "page_count" | "row_size"
+            | "sort_buffer_rows" | "sync",
+            None,
+        ) => {}
+        ("page_count" | "row_size", Some(_)) => return Err(Error::ReadOnly),
+        _ => return Err(Error::NotFound(format!("pragma {name}"))),
+    }
+
+    let value = match name {
+        "cache_size" => table.pages.cache_size(),
+        "coerce" => table.coerce as usize,
+        "sync" => table.pages.sync() as usize,
+        // 0 means no limit
+        "max_scanned_rows" => table.max_scanned_rows.unwrap_or(0),
+        "page_count" => table.pages.pages(),
+        "sort_buffer_rows" => table.sort_buffer_rows,
+        _ => table.schema().row_size(),
+    };
+    Ok(value.to_string())
+}
+
+fn flag(value: &str) -> Result<bool, Error> {
+    match value {
+        "on" | "true" | "1" => Ok(true),
+        "off" | "false" | "0" => Ok(false),
+        _ => Err(Error::ParseError),
+    }
+}
+
+/// Parses and type checks `sql` against the table without executing it.
+pub fn check(sql: &str, table: &Table) -> String {
+    match prepare_statement(sql, table) {
+        Ok(statement) => format!("ok: {statement}"),
+        Err(err) => format!("error: {err}"),
     }
 }
 
 pub enum Command {
     Exit,
+    Check(String),
+    Stats(String),
+    Layout(String),
+    Pragma {
+        name: String,
+        value: Option<String>,
+    },
+    Clone {
+        src: String,
+        dst: String,
+    },
+    Constants,
+    Output(Option<String>),
+    Reindex(String),
+    Save(String),
+    Backup(String),
+    Load(String),
+    Separator(String),
+    NullValue(String),
+    Mode(Mode),
+    Width(Vec<usize>),
+    Echo(bool),
+    Read(String),
+    Version,
+    /// Prints every page the table reads, finds cached or writes.
+    Trace(bool),
+    Indexes(String),
 }
 
 impl std::str::FromStr for Command {
@@ -18,11 +305,328 @@ impl std::str::FromStr for Command {
             return Err(Error::UnrecognizedCommand);
         }
 
-        let command = match &s[1..] {
-            "exit" => Command::Exit,
+        let (command, args) = s[1..].split_once(' ').unwrap_or((&s[1..], ""));
+        let command = match (command, args.trim()) {
+            ("exit", "") => Command::Exit,
+            ("check", sql) if !sql.is_empty() => Command::Check(sql.to_string()),
+            ("stats", name) if !name.is_empty() => Command::Stats(name.to_string()),
+            ("layout", name) if !name.is_empty() => Command::Layout(name.to_string()),
+            ("pragma", args) if !args.is_empty() => {
+                let (name, value) = match args.split_once('=') {
+                    Some((name, value)) => (name.trim(), Some(value.trim().to_string())),
+                    None => (args, None),
+                };
+                Command::Pragma {
+                    name: name.to_string(),
+                    value,
+                }
+            }
+            ("constants", "") => Command::Constants,
+            ("output", "" | "stdout") => Command::Output(None),
+            ("output", path) => Command::Output(Some(path.to_string())),
+            ("reindex", name) if !name.is_empty() => Command::Reindex(name.to_string()),
+            ("save", path) if !path.is_empty() => Command::Save(path.to_string()),
+            ("backup", path) if !path.is_empty() => Command::Backup(path.to_string()),
+            ("load", path) if !path.is_empty() => Command::Load(path.to_string()),
+            ("clone", args) => match args.split_whitespace().collect::<Vec<_>>()[..] {
+                [src, dst] => Command::Clone {
+                    src: src.to_string(),
+                    dst: dst.to_string(),
+                },
+                _ => return Err(Error::UnrecognizedCommand),
+            },
+            ("separator", separator) if !separator.is_empty() => {
+                Command::Separator(unescape(separator))
+            }
+            ("nullvalue", null_value) => Command::NullValue(unescape(null_value)),
+            ("mode", "list") => Command::Mode(Mode::List),
+            ("mode", "column") => Command::Mode(Mode::Column),
+            ("mode", "json") => Command::Mode(Mode::Json),
+            ("width", widths) => Command::Width(
+                widths
+                    .split_whitespace()
+                    .map(|x| x.parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| Error::UnrecognizedCommand)?,
+            ),
+            ("echo", "on") => Command::Echo(true),
+            ("echo", "off") => Command::Echo(false),
+            ("read", path) if !path.is_empty() => Command::Read(path.to_string()),
+            ("version", "") => Command::Version,
+            ("trace", "on") => Command::Trace(true),
+            ("trace", "off") => Command::Trace(false),
+            ("indexes", name) if !name.is_empty() => Command::Indexes(name.to_string()),
             _ => return Err(Error::UnrecognizedCommand),
         };
 
         Ok(command)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use crate::{
+        datatype::{DataType, ScalarValue, Schema},
+        errors::Error,
+        execution::execution,
+        repl::Repl,
+        statement::{prepare_statement, InsertStatement},
+        table::{Table, MEMORY_PATH},
+        tree::LeafNode,
+    };
+
+    use super::{check, constants, do_meta_commands, layout, pragma, version, Command};
+
+    fn table(schema: Schema) -> Table {
+        Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap()
+    }
+
+    #[test]
+    fn check_has_no_side_effects() {
+        let path = std::env::temp_dir().join("check_has_no_side_effects.db");
+        let _ = fs::remove_file(&path);
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let table = Table::new("t".to_string(), schema, &path).unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+
+        assert_eq!(
+            check("insert 0 1 \"x\"", &table),
+            "ok: insert 0 values (1, \"x\")"
+        );
+        assert!(check("insert 0 \"x\" 1", &table).starts_with("error: "));
+        assert!(check("insert 0 1 \"x\" 2", &table).starts_with("error: "));
+
+        assert_eq!(table.header.num_rows, 0);
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn layout_offsets() {
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+            ("c".to_string(), DataType::Number),
+        ]);
+        // 8 byte key, 1 byte bitmap, then 8 + 10 + 8 bytes of columns
+        assert_eq!(
+            layout(&schema),
+            "offset 0, width 8: key\n\
+             offset 8, width 1: null bitmap\n\
+             offset 9, width 8: a number\n\
+             offset 17, width 10: b text(10)\n\
+             offset 27, width 8: c number"
+        );
+        assert_eq!(8 + schema.row_size(), 27 + 8);
+
+        // A compact column can take anywhere up to 10 bytes, so the columns
+        // after it move by as much
+        let mut schema = schema;
+        schema.options[0].compact = true;
+        assert_eq!(
+            layout(&schema),
+            "offset 0, width 8: key\n\
+             offset 8, width 1: null bitmap\n\
+             offset 9, width 0 to 10: a number compact\n\
+             offset 9 to 19, width 10: b text(10)\n\
+             offset 19 to 29, width 8: c number"
+        );
+    }
+
+    #[test]
+    fn constants_match_leaf_capacity() {
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let constants = constants(&schema);
+        let max_cells = format!("leaf_max_cells: {}", LeafNode::capacity(schema.row_size()));
+        assert!(constants.lines().any(|line| line == max_cells));
+        assert!(constants.lines().any(|line| line == "row_size: 19"));
+        assert!(matches!(".constants".parse(), Ok(Command::Constants)));
+    }
+
+    #[test]
+    fn pragma_cache_size() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let path = Path::new(MEMORY_PATH);
+        let mut table = Table::new("t".to_string(), schema, path).unwrap();
+        assert_eq!(pragma(&mut table, "cache_size", None).unwrap(), "100");
+
+        let command: Command = ".pragma cache_size = 2".parse().unwrap();
+        do_meta_commands(command, &mut Repl::new(), &mut table).unwrap();
+        assert_eq!(pragma(&mut table, "cache_size", None).unwrap(), "2");
+
+        // Enough rows for several leaves, all through a two page cache
+        let count = table.rows_per_page() as i64 * 3;
+        for key in 0..count {
+            let values = vec![ScalarValue::Number(key)];
+            table
+                .insert(InsertStatement {
+                    key,
+                    values,
+                    returning: None,
+                })
+                .unwrap();
+            assert!(table.pages.cached_pages() <= 2);
+        }
+        assert_eq!(table.scan().unwrap().len(), count as usize);
+        assert_eq!(table.read(count - 1).unwrap().key(), count - 1);
+
+        let pages = table.pages.pages().to_string();
+        assert_eq!(pragma(&mut table, "page_count", None).unwrap(), pages);
+        assert!(matches!(
+            pragma(&mut table, "page_count", Some("3")),
+            Err(Error::ReadOnly)
+        ));
+        assert!(matches!(
+            pragma(&mut table, "unknown", None),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn read_script_with_echo() {
+        let dir = std::env::temp_dir();
+        let (script, output) = (dir.join("read_script.sql"), dir.join("read_script.txt"));
+        fs::write(
+            &script,
+            "insert 0 1 \"x\";\n-- comment\ninsert 1\n  2 \"y\";\ninsert 0 3 \"z\";\n.echo off\nread 1;\n",
+        )
+        .unwrap();
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let mut table = table(schema);
+        let mut repl = Repl::new();
+        repl.set_output(Some(&output)).unwrap();
+
+        for line in [
+            ".echo on".to_string(),
+            format!(".read {}", script.display()),
+        ] {
+            do_meta_commands(line.parse().unwrap(), &mut repl, &mut table).unwrap();
+        }
+        repl.set_output(None).unwrap();
+        // The duplicate key fails without stopping the script, and the
+        // script turns echo off before the last statement
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "insert 0 1 \"x\";\n1 row affected\n-- comment\n\
+             insert 1\n  2 \"y\";\n1 row affected\n\
+             insert 0 3 \"z\";\n.echo off\n2|y\n1 row\n"
+        );
+        assert_eq!(table.header.num_rows, 2);
+        assert!(!repl.echo);
+
+        assert!(matches!(
+            ".read missing.sql".parse(),
+            Ok(Command::Read(path)) if path == "missing.sql"
+        ));
+        assert!(matches!(
+            do_meta_commands(
+                Command::Read(dir.join("missing.sql").display().to_string()),
+                &mut repl,
+                &mut table
+            ),
+            Err(Error::IOError(_))
+        ));
+        assert!(matches!(
+            ".echo maybe".parse::<Command>(),
+            Err(Error::UnrecognizedCommand)
+        ));
+        fs::remove_file(script).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn read_script_past_bad_meta_command() {
+        let script = std::env::temp_dir().join("read_script_past_bad_meta_command.sql");
+        fs::write(
+            &script,
+            ".foo\ninsert 0 1 \"x\";\n.clone other copy\ninsert 1 2 \"y\";\n",
+        )
+        .unwrap();
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let mut table = table(schema);
+        let mut repl = Repl::new();
+
+        // Neither the unknown command nor the one that fails stops the script
+        let command = Command::Read(script.display().to_string());
+        do_meta_commands(command, &mut repl, &mut table).unwrap();
+        assert_eq!(table.header.num_rows, 2);
+        fs::remove_file(script).unwrap();
+    }
+
+    #[test]
+    fn version_is_printed() {
+        let output = std::env::temp_dir().join("version_is_printed.txt");
+        let mut table = table(Schema::new(vec![("a".to_string(), DataType::Number)]));
+        let mut repl = Repl::new();
+        repl.set_output(Some(&output)).unwrap();
+        // Returning at all shows it didn't exit like `.exit`
+        do_meta_commands(".version".parse().unwrap(), &mut repl, &mut table).unwrap();
+        repl.set_output(None).unwrap();
+
+        let printed = fs::read_to_string(&output).unwrap();
+        assert_eq!(printed.trim_end(), version());
+        assert!(printed.starts_with(env!("CARGO_PKG_VERSION")));
+        assert!(matches!(
+            ".version 2".parse::<Command>(),
+            Err(Error::UnrecognizedCommand)
+        ));
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn indexes_are_listed() {
+        let output = std::env::temp_dir().join("indexes_are_listed.txt");
+        let mut table = table(Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]));
+        let mut repl = Repl::new();
+        repl.set_output(Some(&output)).unwrap();
+        let run = |line: &str, table: &mut Table| {
+            let statement = prepare_statement(line, &*table).unwrap();
+            execution(statement, table).unwrap();
+        };
+        do_meta_commands(".indexes t".parse().unwrap(), &mut repl, &mut table).unwrap();
+        run("create index idx on t(b)", &mut table);
+        run("create index pair on t (a, b)", &mut table);
+        do_meta_commands(".indexes t".parse().unwrap(), &mut repl, &mut table).unwrap();
+        repl.set_output(None).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "idx on t(b)\npair on t(a, b)\n"
+        );
+
+        assert!(matches!(
+            execution(
+                prepare_statement("create index idx on t(a)", &table).unwrap(),
+                &mut table
+            ),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            prepare_statement("create index other on t(c)", &table),
+            Err(Error::UnknownColumn(column)) if column == "c"
+        ));
+        assert!(matches!(
+            do_meta_commands(".indexes u".parse().unwrap(), &mut repl, &mut table),
+            Err(Error::NotFound(_))
+        ));
+        table.rename_column("b", "name".to_string()).unwrap();
+        assert_eq!(table.indexes()[0].columns, ["name"]);
+        fs::remove_file(output).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+//! Column types defined outside the crate. A type implements `ColumnType`
+//! and is registered once under its name; after that `DataType::Custom`
+//! columns of it can be declared, and tables using it can be opened. Values
+//! travel through statements and rows as text and are kept in their binary
+//! form only on the page, so a uuid takes 16 bytes rather than 36.
+
+use std::{
+    fmt::{Debug, Display},
+    marker::PhantomData,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+/// A fixed width type that can be stored in a column.
+pub trait ColumnType: Display + Sized + Send + Sync + 'static {
+    /// The name the type goes by in schemas and statements.
+    const NAME: &'static str;
+
+    /// Bytes every value takes up in a row.
+    fn size() -> usize;
+
+    /// Writes the value into the first `size()` bytes of `bytes`.
+    fn encode(&self, bytes: &mut [u8]);
+
+    /// Reads a value back from the first `size()` bytes of `bytes`.
+    fn decode(bytes: &[u8]) -> Self;
+
+    /// Reads a value from its text form, the one `Display` writes.
+    fn parse(s: &str) -> Option<Self>;
+}
+
+// `ColumnType` with the value type erased, so schemas can hold any of them
+trait ErasedType: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn size(&self) -> usize;
+    fn encode(&self, text: &str, bytes: &mut [u8]) -> bool;
+    fn decode(&self, bytes: &[u8]) -> String;
+}
+
+struct Erased<T>(PhantomData<fn() -> T>);
+
+impl<T: ColumnType> ErasedType for Erased<T> {
+    fn name(&self) -> &'static str {
+        T::NAME
+    }
+
+    fn size(&self) -> usize {
+        T::size()
+    }
+
+    fn encode(&self, text: &str, bytes: &mut [u8]) -> bool {
+        match T::parse(text) {
+            Some(value) => {
+                value.encode(&mut bytes[..T::size()]);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> String {
+        T::decode(&bytes[..T::size()]).to_string()
+    }
+}
+
+/// A registered `ColumnType`, as held by `DataType::Custom`. Two are equal
+/// when they have the same name, and a schema stores only the name.
+#[derive(Clone)]
+pub struct CustomType(Arc<dyn ErasedType>);
+
+static TYPES: RwLock<Vec<CustomType>> = RwLock::new(Vec::new());
+
+impl CustomType {
+    /// Registers `T` under its name, replacing any type registered under
+    /// the same name before, and returns it.
+    pub fn register<T: ColumnType>() -> CustomType {
+        let ty = CustomType(Arc::new(Erased::<T>(PhantomData)));
+        let mut types = TYPES.write().unwrap_or_else(PoisonError::into_inner);
+        types.retain(|other| other.name() != T::NAME);
+        types.push(ty.clone());
+        ty
+    }
+
+    /// The type registered under `name`, if any.
+    pub fn lookup(name: &str) -> Option<CustomType> {
+        let types = TYPES.read().unwrap_or_else(PoisonError::into_inner);
+        types.iter().find(|ty| ty.name() == name).cloned()
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    pub fn size(&self) -> usize {
+        self.0.size()
+    }
+
+    /// Whether `text` is the text form of a value of this type.
+    pub fn accepts(&self, text: &str) -> bool {
+        self.0.encode(text, &mut vec![0; self.size()])
+    }
+
+    /// Writes the value `text` stands for into the first `size()` bytes of
+    /// `bytes`. Returns false, leaving them unchanged, when it stands for
+    /// none.
+    pub fn encode(&self, text: &str, bytes: &mut [u8]) -> bool {
+        self.0.encode(text, bytes)
+    }
+
+    /// The text form of the value in the first `size()` bytes of `bytes`.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        self.0.decode(bytes)
+    }
+}
+
+impl Debug for CustomType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CustomType").field(&self.name()).finish()
+    }
+}
+
+impl Display for CustomType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl PartialEq for CustomType {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl Eq for CustomType {}
+
+impl serde::Serialize for CustomType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for CustomType {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        CustomType::lookup(&name).ok_or_else(|| {
+            serde::de::Error::custom(format!("column type {name} is not registered"))
+        })
+    }
+}
+
+/// A uuid, written as 32 hex digits in groups of 8-4-4-4-12.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Uuid(pub [u8; 16]);
+
+impl Display for Uuid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                f.write_str("-")?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl ColumnType for Uuid {
+    const NAME: &'static str = "uuid";
+
+    fn size() -> usize {
+        16
+    }
+
+    fn encode(&self, bytes: &mut [u8]) {
+        bytes[..16].copy_from_slice(&self.0);
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        Uuid(bytes[..16].try_into().unwrap())
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        let groups: Vec<&str> = s.split('-').collect();
+        let lens: Vec<usize> = groups.iter().map(|group| group.len()).collect();
+        if lens != [8, 4, 4, 4, 12] {
+            return None;
+        }
+        let digits = groups.concat();
+        let mut bytes = [0; 16];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(digits.get(i * 2..i * 2 + 2)?, 16).ok()?;
+        }
+        Some(Uuid(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        datatype::{DataType, ScalarValue, Schema},
+        dictionary::Dictionary,
+        tree::LeafNode,
+    };
+
+    use super::{ColumnType, CustomType, Uuid};
+
+    #[test]
+    fn uuid_text_form() {
+        let text = "123e4567-e89b-12d3-a456-426614174000";
+        let uuid = Uuid::parse(text).unwrap();
+        assert_eq!(uuid.0[..2], [0x12, 0x3e]);
+        assert_eq!(uuid.to_string(), text);
+        assert_eq!(
+            Uuid::parse("123E4567-E89B-12D3-A456-426614174000"),
+            Some(uuid)
+        );
+        for bad in [
+            "",
+            "123e4567e89b12d3a456426614174000",
+            "123e4567-e89b-12d3-a456-42661417400g",
+        ] {
+            assert_eq!(Uuid::parse(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn uuid_column_round_trip() {
+        let uuid = CustomType::register::<Uuid>();
+        assert_eq!(CustomType::lookup("uuid"), Some(uuid.clone()));
+        let schema = Schema::new(vec![
+            ("id".to_string(), DataType::Custom(uuid.clone())),
+            ("name".to_string(), DataType::String(10)),
+        ]);
+        assert_eq!(schema.row_size(), 1 + 16 + 10);
+
+        let text = "00112233-4455-6677-8899-aabbccddeeff";
+        let values = vec![
+            ScalarValue::String(text.to_string()),
+            ScalarValue::String("a".to_string()),
+        ];
+        schema.validate_row(&values).unwrap();
+        assert!(schema
+            .validate_row(&[ScalarValue::String("x".to_string()), ScalarValue::Null])
+            .is_err());
+
+        let dictionary = Dictionary::default();
+        let mut leaf = LeafNode::new();
+        leaf.leaf_node_split_and_insert(1, values.clone(), &schema, &dictionary);
+        leaf.leaf_node_split_and_insert(2, vec![ScalarValue::Null; 2], &schema, &dictionary);
+        assert_eq!(
+            leaf.read_row(0, &schema, &dictionary).unwrap().values(),
+            values
+        );
+        assert_eq!(
+            leaf.read_row(1, &schema, &dictionary).unwrap().values(),
+            [ScalarValue::Null, ScalarValue::Null]
+        );
+
+        // A schema keeps the type's name and finds the type again by it
+        let bytes = bincode::serialize(&schema).unwrap();
+        assert_eq!(bincode::deserialize::<Schema>(&bytes).unwrap(), schema);
+    }
+}
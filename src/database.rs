@@ -0,0 +1,96 @@
+//! A data directory holding one `<table>.db` file per table.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{datatype::Schema, errors::Error, table::Table};
+
+/// Tables stored together in one directory. Renamed and cloned tables stay
+/// next to the file they came from, so they land here too.
+#[derive(Debug, Clone)]
+pub struct Database {
+    dir: PathBuf,
+}
+
+impl Database {
+    /// Keeps table files in `dir`, creating it if it doesn't exist.
+    pub fn open_dir(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where table `name` is stored, whether it exists or not.
+    pub fn table_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.db"))
+    }
+
+    /// Opens table `name`, creating it with `schema` if it doesn't exist.
+    pub fn open_table(&self, name: &str, schema: Schema) -> Result<Table, Error> {
+        Table::new(name.to_string(), schema, &self.table_path(name))
+    }
+
+    /// Names of the tables in the directory, sorted. The name comes from each
+    /// file's header. `.db` files that aren't tables are skipped.
+    pub fn table_names(&self) -> Result<Vec<String>, Error> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "db") || !path.is_file() {
+                continue;
+            }
+            match Table::open_read_only(&path) {
+                Ok(table) => names.push(table.header.name),
+                Err(Error::BadMagic) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use crate::{
+        datatype::{DataType, Schema},
+        errors::Error,
+    };
+
+    use super::Database;
+
+    #[test]
+    fn open_dir_apart_from_cwd() {
+        let dir = env::temp_dir().join("open_dir_apart_from_cwd");
+        let _ = fs::remove_dir_all(&dir);
+        let database = Database::open_dir(&dir).unwrap();
+        assert_ne!(env::current_dir().unwrap(), dir);
+
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = database.open_table("first", schema.clone()).unwrap();
+        database.open_table("second", schema).unwrap();
+        assert!(dir.join("first.db").is_file());
+        assert!(!env::current_dir().unwrap().join("first.db").exists());
+
+        table.rename("third".to_string()).unwrap();
+        fs::write(dir.join("notes.db"), "not a table").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        assert_eq!(database.table_names().unwrap(), ["second", "third"]);
+
+        assert!(matches!(
+            Database::open_dir(dir.join("notes.txt")),
+            Err(Error::IOError(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
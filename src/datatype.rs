@@ -1,39 +1,500 @@
-use std::fmt::Display;
+use std::{borrow::Cow, cmp::Ordering, fmt::Display, ops::Index};
 
-#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+use crate::{codec, custom::CustomType, errors::Error};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub enum ScalarValue {
     String(String),
     Number(i64),
+    Null,
+}
+
+impl ScalarValue {
+    /// Name of the value's type, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ScalarValue::String(_) => "text",
+            ScalarValue::Number(_) => "number",
+            ScalarValue::Null => "null",
+        }
+    }
+
+    /// Converts the value to one `ty` accepts: numbers become their decimal
+    /// text and text holding a whole number becomes that number. Null and
+    /// values that already fit are returned as they are. The error names no
+    /// column, so callers that know it should report their own.
+    pub fn coerce(&self, ty: &DataType) -> Result<ScalarValue, Error> {
+        if ty.accepts(self) {
+            return Ok(self.clone());
+        }
+        let value = match (ty, self) {
+            (DataType::String(_) | DataType::Char(_), ScalarValue::Number(x)) => {
+                Some(ScalarValue::String(x.to_string()))
+            }
+            (DataType::Number, ScalarValue::String(x)) => {
+                x.trim().parse().ok().map(ScalarValue::Number)
+            }
+            _ => None,
+        };
+        value.ok_or_else(|| Error::TypeMismatch {
+            column: String::new(),
+            expected: ty.clone(),
+            got: self.type_name(),
+        })
+    }
 }
 
 impl Display for ScalarValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ScalarValue::String(x) => f.write_str(&x),
+            ScalarValue::String(x) => f.write_str(x),
             ScalarValue::Number(x) => write!(f, "{}", x),
+            ScalarValue::Null => f.write_str("null"),
         }
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
 pub enum DataType {
     String(usize),
     Number,
+    /// Text stored in exactly this many bytes, padded with spaces.
+    Char(usize),
+    /// A type registered from outside the crate. Its values are text.
+    Custom(CustomType),
+}
+
+impl Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataType::String(size) => write!(f, "text({size})"),
+            DataType::Char(size) => write!(f, "char({size})"),
+            DataType::Number => f.write_str("number"),
+            DataType::Custom(ty) => write!(f, "{ty}"),
+        }
+    }
+}
+
+impl DataType {
+    /// Whether a value can be stored in a column of this type. Null is
+    /// accepted for any type.
+    pub fn accepts(&self, value: &ScalarValue) -> bool {
+        if let (DataType::Custom(ty), ScalarValue::String(text)) = (self, value) {
+            return ty.accepts(text);
+        }
+        matches!(
+            (self, value),
+            (
+                DataType::String(_) | DataType::Char(_),
+                ScalarValue::String(_)
+            ) | (DataType::Number, ScalarValue::Number(_))
+                | (_, ScalarValue::Null)
+        )
+    }
+
+    /// Number of bytes a value of this type takes up in a row.
+    pub fn size(&self) -> usize {
+        match self {
+            DataType::String(size) | DataType::Char(size) => *size,
+            DataType::Number => 8,
+            DataType::Custom(ty) => ty.size(),
+        }
+    }
+
+    /// Whether the type holds plain text, as opposed to numbers or a custom
+    /// type's text form.
+    pub fn is_text(&self) -> bool {
+        matches!(self, DataType::String(_) | DataType::Char(_))
+    }
+}
+
+/// What a table's row keys are. Text keys are packed into the same `i64`
+/// the tree orders by, so they are limited to
+/// `codec::MAX_STRING_KEY_SIZE` bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum KeyType {
+    #[default]
+    Number,
+    Text,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+impl Display for KeyType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyType::Number => f.write_str("number"),
+            KeyType::Text => write!(f, "text({})", codec::MAX_STRING_KEY_SIZE),
+        }
+    }
+}
+
+impl KeyType {
+    /// The row key `value` stands for.
+    pub fn encode(&self, value: &ScalarValue) -> Result<i64, Error> {
+        match (self, value) {
+            (KeyType::Number, ScalarValue::Number(key)) => Ok(*key),
+            (KeyType::Text, ScalarValue::String(key)) => {
+                codec::encode_string_key(key).ok_or_else(|| {
+                    Error::ConstraintViolation(format!(
+                        "key {key:?} is longer than {} bytes or holds a NUL",
+                        codec::MAX_STRING_KEY_SIZE
+                    ))
+                })
+            }
+            (_, value) => Err(Error::TypeMismatch {
+                column: "key".to_string(),
+                expected: self.data_type(),
+                got: value.type_name(),
+            }),
+        }
+    }
+
+    /// The value a row key stands for, as `encode` was given it.
+    pub fn decode(&self, key: i64) -> ScalarValue {
+        match self {
+            KeyType::Number => ScalarValue::Number(key),
+            KeyType::Text => ScalarValue::String(codec::decode_string_key(key)),
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            KeyType::Number => DataType::Number,
+            KeyType::Text => DataType::String(codec::MAX_STRING_KEY_SIZE),
+        }
+    }
+}
+
+/// How string values of a column are compared.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Collation {
+    /// Byte by byte comparison.
+    #[default]
+    Binary,
+    /// Ignores ASCII case.
+    NoCase,
+}
+
+impl Collation {
+    /// Orders two values, with null first, then numbers, then strings.
+    pub fn compare(&self, a: &ScalarValue, b: &ScalarValue) -> Ordering {
+        match (a, b) {
+            (ScalarValue::String(a), ScalarValue::String(b)) => match self {
+                Collation::Binary => a.cmp(b),
+                Collation::NoCase => a
+                    .bytes()
+                    .map(|x| x.to_ascii_lowercase())
+                    .cmp(b.bytes().map(|x| x.to_ascii_lowercase())),
+            },
+            (ScalarValue::Number(a), ScalarValue::Number(b)) => a.cmp(b),
+            (ScalarValue::Null, ScalarValue::Null) => Ordering::Equal,
+            (ScalarValue::Null, _) => Ordering::Less,
+            (_, ScalarValue::Null) => Ordering::Greater,
+            (ScalarValue::Number(_), ScalarValue::String(_)) => Ordering::Less,
+            (ScalarValue::String(_), ScalarValue::Number(_)) => Ordering::Greater,
+        }
+    }
+
+    pub fn equals(&self, a: &ScalarValue, b: &ScalarValue) -> bool {
+        self.compare(a, b) == Ordering::Equal
+    }
+}
+
+/// Per column settings, stored alongside `Schema::feilds` at the same index.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ColumnOptions {
+    pub not_null: bool,
+    pub unique: bool,
+    /// Value used when an insert leaves the column out.
+    pub default: Option<ScalarValue>,
+    pub collation: Collation,
+    /// Stores numbers as varints, so small ones take fewer bytes. Only
+    /// applies to number columns.
+    pub compact: bool,
+    /// Stores strings once in the table's dictionary, leaving their id in
+    /// the row. Only applies to text columns.
+    pub dictionary: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Schema {
     pub feilds: Vec<(String, DataType)>,
+    pub options: Vec<ColumnOptions>,
 }
 
 impl Schema {
+    pub fn new(feilds: Vec<(String, DataType)>) -> Self {
+        let options = vec![ColumnOptions::default(); feilds.len()];
+        Self { feilds, options }
+    }
+
+    /// Starts a schema built column by column, checked when it is built.
+    pub fn builder() -> SchemaBuilder {
+        SchemaBuilder::default()
+    }
+
+    /// Every row starts with a bitmap holding one null flag per column.
+    pub fn null_bitmap_size(&self) -> usize {
+        self.feilds.len().div_ceil(8)
+    }
+
+    /// Whether column `index` holds numbers stored as varints.
+    pub fn is_compact(&self, index: usize) -> bool {
+        self.options[index].compact && self.feilds[index].1 == DataType::Number
+    }
+
+    /// Whether column `index` holds strings stored in the dictionary.
+    pub fn is_dictionary(&self, index: usize) -> bool {
+        self.options[index].dictionary && self.feilds[index].1.is_text()
+    }
+
+    /// The most bytes column `index` takes up in a row.
+    pub fn column_size(&self, index: usize) -> usize {
+        if self.is_compact(index) {
+            codec::MAX_VARINT_SIZE
+        } else if self.is_dictionary(index) {
+            codec::STRING_ID_SIZE
+        } else {
+            self.feilds[index].1.size()
+        }
+    }
+
+    /// The most bytes a row takes up. Only rows with compact columns can
+    /// take less.
     pub fn row_size(&self) -> usize {
+        self.null_bitmap_size()
+            + (0..self.feilds.len())
+                .map(|i| self.column_size(i))
+                .sum::<usize>()
+    }
+
+    /// Completes a row given in schema order, replacing every column that
+    /// wasn't supplied with its default. Columns without a default become
+    /// null unless they are `not_null`.
+    pub fn fill_defaults(
+        &self,
+        values: Vec<Option<ScalarValue>>,
+    ) -> Result<Vec<ScalarValue>, Error> {
+        self.feilds
+            .iter()
+            .zip(&self.options)
+            .zip(values)
+            .map(
+                |(((name, _), options), value)| match (value, &options.default) {
+                    (Some(value), _) => Ok(value),
+                    (None, Some(default)) => Ok(default.clone()),
+                    (None, None) if !options.not_null => Ok(ScalarValue::Null),
+                    (None, None) => Err(Error::ConstraintViolation(format!(
+                        "column {name} has no default value"
+                    ))),
+                },
+            )
+            .collect()
+    }
+
+    /// Checks that a complete row has one value per column, each matching the
+    /// column's type. Null is accepted for any type.
+    pub fn validate_row(&self, values: &[ScalarValue]) -> Result<(), Error> {
+        if self.feilds.len() != values.len() {
+            return Err(Error::ArityMismatch {
+                expected: self.feilds.len(),
+                got: values.len(),
+            });
+        }
+
+        for ((name, ty), value) in self.feilds.iter().zip(values) {
+            if !ty.accepts(value) {
+                return Err(Error::TypeMismatch {
+                    column: name.clone(),
+                    expected: ty.clone(),
+                    got: value.type_name(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts every value that can be converted to its column's type. The
+    /// rest are left for `validate_row` to reject.
+    pub fn coerce_row(&self, values: Vec<ScalarValue>) -> Vec<ScalarValue> {
         self.feilds
             .iter()
-            .map(|(_, x)| match x {
-                DataType::String(size) => *size,
-                DataType::Number => 8,
-            })
-            .sum()
+            .zip(values)
+            .map(|((_, ty), value)| value.coerce(ty).unwrap_or(value))
+            .collect()
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.feilds.iter().position(|(field, _)| field == name)
+    }
+}
+
+/// Builds a `Schema` one column at a time:
+/// `Schema::builder().number("a").text("b", 10).build()`.
+#[derive(Debug, Clone, Default)]
+pub struct SchemaBuilder {
+    feilds: Vec<(String, DataType)>,
+}
+
+impl SchemaBuilder {
+    pub fn column(mut self, name: &str, ty: DataType) -> Self {
+        self.feilds.push((name.to_string(), ty));
+        self
+    }
+
+    pub fn number(self, name: &str) -> Self {
+        self.column(name, DataType::Number)
+    }
+
+    /// A text column holding up to `size` bytes.
+    pub fn text(self, name: &str, size: usize) -> Self {
+        self.column(name, DataType::String(size))
+    }
+
+    /// A text column always stored as `size` bytes.
+    pub fn char(self, name: &str, size: usize) -> Self {
+        self.column(name, DataType::Char(size))
+    }
+
+    /// Fails with `InvalidSchema` when there are no columns or two share a
+    /// name.
+    pub fn build(self) -> Result<Schema, Error> {
+        if self.feilds.is_empty() {
+            return Err(Error::InvalidSchema("no columns".to_string()));
+        }
+        for (i, (name, _)) in self.feilds.iter().enumerate() {
+            if self.feilds[..i].iter().any(|(other, _)| other == name) {
+                return Err(Error::InvalidSchema(format!("duplicate column {name}")));
+            }
+        }
+        Ok(Schema::new(self.feilds))
+    }
+}
+
+/// A decoded row along with the schema it was decoded with, so columns can be
+/// looked up by name as well as by position.
+#[derive(Debug, Clone)]
+pub struct Row<'a> {
+    key: i64,
+    values: Vec<ScalarValue>,
+    schema: Cow<'a, Schema>,
+}
+
+impl<'a> Row<'a> {
+    pub fn new(key: i64, values: Vec<ScalarValue>, schema: &'a Schema) -> Self {
+        Self {
+            key,
+            values,
+            schema: Cow::Borrowed(schema),
+        }
+    }
+
+    /// A row that owns its schema, for rows made up rather than read, such
+    /// as those of a select list.
+    pub fn with_schema(key: i64, values: Vec<ScalarValue>, schema: Schema) -> Self {
+        Self {
+            key,
+            values,
+            schema: Cow::Owned(schema),
+        }
+    }
+
+    pub fn key(&self) -> i64 {
+        self.key
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ScalarValue> {
+        self.schema
+            .index_of(name)
+            .and_then(|index| self.values.get(index))
+    }
+
+    pub fn get_index(&self, index: usize) -> Option<&ScalarValue> {
+        self.values.get(index)
+    }
+
+    pub fn values(&self) -> &[ScalarValue] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<ScalarValue> {
+        self.values
+    }
+
+    /// The row cut down to the columns at `indexes`, in that order. Its
+    /// schema names only those columns.
+    pub fn project(&self, indexes: &[usize]) -> Row<'a> {
+        let schema = Schema {
+            feilds: indexes
+                .iter()
+                .map(|&i| self.schema.feilds[i].clone())
+                .collect(),
+            options: indexes
+                .iter()
+                .map(|&i| self.schema.options[i].clone())
+                .collect(),
+        };
+        Row {
+            key: self.key,
+            values: indexes.iter().map(|&i| self.values[i].clone()).collect(),
+            schema: Cow::Owned(schema),
+        }
+    }
+}
+
+impl Index<usize> for Row<'_> {
+    type Output = ScalarValue;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.values[index]
+    }
+}
+
+impl Index<&str> for Row<'_> {
+    type Output = ScalarValue;
+
+    fn index(&self, name: &str) -> &Self::Output {
+        self.get(name)
+            .unwrap_or_else(|| panic!("no column named {name}"))
+    }
+}
+
+impl Display for Row<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for value in &self.values {
+            write!(f, " {} ", value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::Error;
+
+    use super::{DataType, Schema};
+
+    #[test]
+    fn schema_builder() {
+        let schema = Schema::builder().number("a").text("b", 10).build().unwrap();
+        assert_eq!(
+            schema,
+            Schema::new(vec![
+                ("a".to_string(), DataType::Number),
+                ("b".to_string(), DataType::String(10)),
+            ])
+        );
+
+        assert!(matches!(
+            Schema::builder().number("a").char("a", 2).build(),
+            Err(Error::InvalidSchema(message)) if message == "duplicate column a"
+        ));
+        assert!(matches!(
+            Schema::builder().build(),
+            Err(Error::InvalidSchema(_))
+        ));
     }
 }
@@ -0,0 +1,153 @@
+//! Strings shared between rows. A dictionary column stores the id of its
+//! string instead of the string, so a value repeated across many rows is
+//! kept once. The strings live in a chain of dictionary pages and are read
+//! into memory when the table is opened.
+
+use std::collections::HashMap;
+
+use crate::{tree::NODE_TYPE_OFFSET, PAGE_SIZE};
+
+/// Every string stored by a table's dictionary columns, numbered in the
+/// order they were first stored.
+#[derive(Debug, Clone, Default)]
+pub struct Dictionary {
+    strings: Vec<String>,
+    ids: HashMap<String, u32>,
+}
+
+impl Dictionary {
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    pub fn get(&self, id: u32) -> Option<&str> {
+        self.strings.get(id as usize).map(String::as_str)
+    }
+
+    pub fn id(&self, s: &str) -> Option<u32> {
+        self.ids.get(s).copied()
+    }
+
+    /// Returns the id of `s`, adding it first if it is new.
+    pub fn intern(&mut self, s: &str) -> u32 {
+        if let Some(id) = self.id(s) {
+            return id;
+        }
+        let id = self.strings.len() as u32;
+        self.strings.push(s.to_string());
+        self.ids.insert(s.to_string(), id);
+        id
+    }
+}
+
+/// A page of dictionary strings, each stored as a two byte length and its
+/// bytes. The table header points at the page holding the newest strings,
+/// and each page points back at the one filled before it.
+#[derive(Debug)]
+pub struct DictionaryPage {
+    pub bytes: Box<[u8; PAGE_SIZE]>,
+}
+
+impl DictionaryPage {
+    pub const NODE_TYPE: u8 = 2;
+    const PREV_PAGE_OFFSET: usize = 8;
+    const NUM_ENTRIES_OFFSET: usize = 12;
+    // Where the free space after the last entry begins
+    const END_OFFSET: usize = 16;
+    pub const HEADER_SIZE: usize = 18;
+    const LEN_SIZE: usize = 2;
+    /// Bytes in the longest string a page can hold.
+    pub const MAX_ENTRY_SIZE: usize = PAGE_SIZE - Self::HEADER_SIZE - Self::LEN_SIZE;
+    /// Bytes a new page has free for entries.
+    pub const SPACE: usize = PAGE_SIZE - Self::HEADER_SIZE;
+
+    pub fn new() -> Self {
+        let mut page = Self::new_with_bytes(vec![0u8; PAGE_SIZE].try_into().unwrap());
+        page.bytes[NODE_TYPE_OFFSET] = Self::NODE_TYPE;
+        page.set_end(Self::HEADER_SIZE);
+        page
+    }
+
+    pub fn new_with_bytes(bytes: Box<[u8; PAGE_SIZE]>) -> Self {
+        Self { bytes }
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_ne_bytes(self.bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// The page filled before this one, 0 when this is the first.
+    pub fn prev_page(&self) -> u32 {
+        self.u32_at(Self::PREV_PAGE_OFFSET)
+    }
+
+    pub fn set_prev_page(&mut self, page: u32) {
+        self.bytes[Self::PREV_PAGE_OFFSET..Self::PREV_PAGE_OFFSET + 4]
+            .copy_from_slice(&page.to_ne_bytes());
+    }
+
+    pub fn num_entries(&self) -> u32 {
+        self.u32_at(Self::NUM_ENTRIES_OFFSET)
+    }
+
+    fn end(&self) -> usize {
+        let bytes = self.bytes[Self::END_OFFSET..Self::HEADER_SIZE]
+            .try_into()
+            .unwrap();
+        u16::from_ne_bytes(bytes) as usize
+    }
+
+    fn set_end(&mut self, end: usize) {
+        self.bytes[Self::END_OFFSET..Self::HEADER_SIZE]
+            .copy_from_slice(&(end as u16).to_ne_bytes());
+    }
+
+    /// Bytes `s` takes on a page, its length included.
+    pub fn entry_size(s: &str) -> usize {
+        Self::LEN_SIZE + s.len()
+    }
+
+    pub fn free_space(&self) -> usize {
+        PAGE_SIZE - self.end()
+    }
+
+    pub fn fits(&self, s: &str) -> bool {
+        Self::entry_size(s) <= self.free_space()
+    }
+
+    /// Appends `s` after the last entry. The caller checks that it `fits`.
+    pub fn push(&mut self, s: &str) {
+        let start = self.end() + Self::LEN_SIZE;
+        self.bytes[start - Self::LEN_SIZE..start].copy_from_slice(&(s.len() as u16).to_ne_bytes());
+        self.bytes[start..start + s.len()].copy_from_slice(s.as_bytes());
+        self.set_end(start + s.len());
+        let num_entries = self.num_entries() + 1;
+        self.bytes[Self::NUM_ENTRIES_OFFSET..Self::NUM_ENTRIES_OFFSET + 4]
+            .copy_from_slice(&num_entries.to_ne_bytes());
+    }
+
+    /// The strings on the page in the order they were pushed.
+    pub fn entries(&self) -> impl Iterator<Item = &str> + '_ {
+        let mut offset = Self::HEADER_SIZE;
+        (0..self.num_entries()).map(move |_| {
+            let len = u16::from_ne_bytes(
+                self.bytes[offset..offset + Self::LEN_SIZE]
+                    .try_into()
+                    .unwrap(),
+            ) as usize;
+            let start = offset + Self::LEN_SIZE;
+            offset = start + len;
+            std::str::from_utf8(&self.bytes[start..offset]).unwrap()
+        })
+    }
+}
+
+impl Default for DictionaryPage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
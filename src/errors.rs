@@ -1,13 +1,224 @@
+use crate::datatype::DataType;
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Unrecognized Command")]
     UnrecognizedCommand,
     #[error("Parse Error")]
     ParseError,
+    #[error("Expected {expected} values, got {got}")]
+    ArityMismatch { expected: usize, got: usize },
+    #[error("Column {column} is {expected}, got a {got} value")]
+    TypeMismatch {
+        column: String,
+        expected: DataType,
+        got: &'static str,
+    },
+    #[error("Unexpected token {token:?} at position {position}")]
+    UnexpectedToken { token: String, position: usize },
+    #[error("A row of {row_size} bytes does not fit in a {page_size} byte page")]
+    RowTooLarge { row_size: usize, page_size: usize },
+    #[error("A header of {size} bytes does not fit in its {limit} bytes of space")]
+    HeaderTooLarge { size: usize, limit: usize },
     #[error("Max number of rows for this table is reached")]
     RowLimit,
+    #[error("Scan stopped after the limit of {0} rows")]
+    RowScanLimitExceeded(usize),
+    #[error("Constraint violation: {0}")]
+    ConstraintViolation(String),
+    #[error("Number out of range: {0}")]
+    NumberOutOfRange(String),
+    #[error("Division by zero")]
+    DivisionByZero,
+    #[error("Cannot apply {op} to a {got} value")]
+    InvalidOperand { op: String, got: &'static str },
+    #[error("Schema mismatch: {0}")]
+    SchemaMismatch(String),
+    #[error("Invalid schema: {0}")]
+    InvalidSchema(String),
+    #[error("Unknown column: {0}")]
+    UnknownColumn(String),
+    #[error("Not found: {0}")]
+    NotFound(String),
+    #[error("Already exists: {0}")]
+    AlreadyExists(String),
+    #[error("Page {0} is not a {1} node")]
+    WrongNodeType(usize, &'static str),
+    #[error("B-tree invariant violated: {0}")]
+    InvariantViolation(String),
+    #[error("Table is read only")]
+    ReadOnly,
+    #[error("Not a table file")]
+    BadMagic,
+    #[error("Table header is corrupt")]
+    CorruptHeader,
+    #[error("File holds {pages} pages, more than the {limit} a table may have")]
+    TooManyPages { pages: usize, limit: usize },
+    #[error("Unsupported file format version {found}, this build reads version {supported}")]
+    UnsupportedVersion { found: u32, supported: u32 },
     #[error("IO Error: {0}")]
     IOError(#[from] std::io::Error),
     #[error("bincode: {0}")]
     Bincode(#[from] bincode::Error),
 }
+
+/// Broad kinds of failure, for callers that handle a whole group alike.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// The statement or command could not be read.
+    Syntax,
+    /// A value had the wrong type or an operation on it failed.
+    Value,
+    /// A row or name broke a rule of the table.
+    Constraint,
+    /// The table or a row outgrew what it can hold.
+    Limit,
+    /// A schema or column did not fit the request.
+    Schema,
+    /// Something named was not there.
+    NotFound,
+    /// The table may not be written.
+    Access,
+    /// The file is not a table this build can read, or is damaged.
+    Storage,
+    /// The operating system failed a read or write.
+    Io,
+}
+
+impl Error {
+    /// A name for the variant that stays the same across releases, unlike
+    /// the message, so callers can branch on it.
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            Error::UnrecognizedCommand => "unrecognized_command",
+            Error::ParseError => "parse_error",
+            Error::ArityMismatch { .. } => "arity_mismatch",
+            Error::TypeMismatch { .. } => "type_mismatch",
+            Error::UnexpectedToken { .. } => "unexpected_token",
+            Error::RowTooLarge { .. } => "row_too_large",
+            Error::HeaderTooLarge { .. } => "header_too_large",
+            Error::RowLimit => "row_limit",
+            Error::RowScanLimitExceeded(_) => "row_scan_limit_exceeded",
+            Error::ConstraintViolation(_) => "constraint_violation",
+            Error::NumberOutOfRange(_) => "number_out_of_range",
+            Error::DivisionByZero => "division_by_zero",
+            Error::InvalidOperand { .. } => "invalid_operand",
+            Error::SchemaMismatch(_) => "schema_mismatch",
+            Error::InvalidSchema(_) => "invalid_schema",
+            Error::UnknownColumn(_) => "unknown_column",
+            Error::NotFound(_) => "not_found",
+            Error::AlreadyExists(_) => "already_exists",
+            Error::WrongNodeType(..) => "wrong_node_type",
+            Error::InvariantViolation(_) => "invariant_violation",
+            Error::ReadOnly => "read_only",
+            Error::BadMagic => "bad_magic",
+            Error::CorruptHeader => "corrupt_header",
+            Error::TooManyPages { .. } => "too_many_pages",
+            Error::UnsupportedVersion { .. } => "unsupported_version",
+            Error::IOError(_) => "io_error",
+            Error::Bincode(_) => "bincode",
+        }
+    }
+
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Error::UnrecognizedCommand
+            | Error::ParseError
+            | Error::ArityMismatch { .. }
+            | Error::UnexpectedToken { .. } => ErrorCategory::Syntax,
+            Error::TypeMismatch { .. }
+            | Error::NumberOutOfRange(_)
+            | Error::DivisionByZero
+            | Error::InvalidOperand { .. } => ErrorCategory::Value,
+            Error::ConstraintViolation(_) | Error::AlreadyExists(_) => ErrorCategory::Constraint,
+            Error::RowTooLarge { .. }
+            | Error::HeaderTooLarge { .. }
+            | Error::RowLimit
+            | Error::RowScanLimitExceeded(_) => ErrorCategory::Limit,
+            Error::SchemaMismatch(_) | Error::InvalidSchema(_) | Error::UnknownColumn(_) => {
+                ErrorCategory::Schema
+            }
+            Error::NotFound(_) => ErrorCategory::NotFound,
+            Error::ReadOnly => ErrorCategory::Access,
+            Error::WrongNodeType(..)
+            | Error::InvariantViolation(_)
+            | Error::BadMagic
+            | Error::CorruptHeader
+            | Error::TooManyPages { .. }
+            | Error::UnsupportedVersion { .. }
+            | Error::Bincode(_) => ErrorCategory::Storage,
+            Error::IOError(_) => ErrorCategory::Io,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, io};
+
+    use crate::datatype::DataType;
+
+    use super::{Error, ErrorCategory};
+
+    #[test]
+    fn every_variant_has_its_own_code() {
+        let s = String::new;
+        let errors = [
+            Error::UnrecognizedCommand,
+            Error::ParseError,
+            Error::ArityMismatch {
+                expected: 1,
+                got: 2,
+            },
+            Error::TypeMismatch {
+                column: s(),
+                expected: DataType::Number,
+                got: "text",
+            },
+            Error::UnexpectedToken {
+                token: s(),
+                position: 0,
+            },
+            Error::RowTooLarge {
+                row_size: 0,
+                page_size: 0,
+            },
+            Error::HeaderTooLarge { size: 0, limit: 0 },
+            Error::RowLimit,
+            Error::RowScanLimitExceeded(0),
+            Error::ConstraintViolation(s()),
+            Error::NumberOutOfRange(s()),
+            Error::DivisionByZero,
+            Error::InvalidOperand {
+                op: s(),
+                got: "text",
+            },
+            Error::SchemaMismatch(s()),
+            Error::InvalidSchema(s()),
+            Error::UnknownColumn(s()),
+            Error::NotFound(s()),
+            Error::AlreadyExists(s()),
+            Error::WrongNodeType(0, "leaf"),
+            Error::InvariantViolation(s()),
+            Error::ReadOnly,
+            Error::BadMagic,
+            Error::CorruptHeader,
+            Error::TooManyPages { pages: 0, limit: 0 },
+            Error::UnsupportedVersion {
+                found: 0,
+                supported: 0,
+            },
+            Error::IOError(io::Error::other("x")),
+            Error::Bincode(Box::new(bincode::ErrorKind::SizeLimit)),
+        ];
+        let codes: HashSet<&str> = errors.iter().map(Error::error_code).collect();
+        assert_eq!(codes.len(), errors.len());
+        assert!(codes
+            .iter()
+            .all(|code| code.chars().all(|c| c.is_ascii_lowercase() || c == '_')));
+
+        let categories: HashSet<ErrorCategory> = errors.iter().map(Error::category).collect();
+        assert_eq!(categories.len(), 9);
+        assert_eq!(Error::ReadOnly.category(), ErrorCategory::Access);
+    }
+}
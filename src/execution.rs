@@ -1,10 +1,194 @@
+use crate::aggregate::{aggregate, Aggregate};
+use crate::datatype::{Collation, Row, ScalarValue};
 use crate::errors::Error;
-use crate::statement::Statement;
+use crate::expr::Expr;
+use crate::sort::{ExternalSort, SortEntry, SortKey};
+use crate::statement::{AlterTableStatement, DeleteStatement, SelectStatement, Statement};
 use crate::table::Table;
 
-pub fn execution(statement: Statement, table: &mut Table) -> Result<(), Error> {
+/// What running a statement produced.
+#[derive(Debug)]
+pub enum QueryResult<'a> {
+    /// Rows read, or handed back by `returning`.
+    Rows(Vec<Row<'a>>),
+    /// How many rows a statement inserted or removed.
+    Affected(usize),
+}
+
+impl<'a> QueryResult<'a> {
+    /// The rows produced, none for a statement that only changed the table.
+    pub fn into_rows(self) -> Vec<Row<'a>> {
+        match self {
+            QueryResult::Rows(rows) => rows,
+            QueryResult::Affected(_) => Vec::new(),
+        }
+    }
+}
+
+/// Runs a statement, returning the rows it produced or how many it changed.
+/// Schema changes affect no rows.
+pub fn execution(statement: Statement, table: &mut Table) -> Result<QueryResult<'_>, Error> {
+    let affected = match statement {
+        Statement::Insert(mut insert_statement) => {
+            let key = insert_statement.key;
+            let returning = insert_statement.returning.take();
+            table.insert(insert_statement)?;
+            if let Some(columns) = returning {
+                return returning_row(table, key, &columns).map(QueryResult::Rows);
+            }
+            1
+        }
+        Statement::Delete(DeleteStatement::Key(key)) => {
+            table.delete(key)?;
+            1
+        }
+        Statement::Delete(DeleteStatement::Where(predicate)) => {
+            table.delete_where(|row| match &predicate {
+                Some(predicate) => predicate.matches(row),
+                None => Ok(true),
+            })?
+        }
+        Statement::Read(_) | Statement::ReadKeys(_) | Statement::Select(_) => {
+            return query(statement, table)
+        }
+        Statement::AlterTable(AlterTableStatement::RenameTable(name)) => {
+            table.rename(name)?;
+            0
+        }
+        Statement::AlterTable(AlterTableStatement::RenameColumn { column, name }) => {
+            table.rename_column(&column, name)?;
+            0
+        }
+        Statement::AlterTable(AlterTableStatement::AddColumn { name, ty, options }) => {
+            table.add_column(name, ty, options)?;
+            0
+        }
+        Statement::CreateIndex { name, columns } => {
+            table.create_index(name, columns)?;
+            0
+        }
+        Statement::CreateTable { name, schema } => {
+            table.create_table(name, schema)?;
+            0
+        }
+        Statement::Truncate => {
+            let rows = table.header.num_rows;
+            table.truncate()?;
+            rows
+        }
+        Statement::DropTable { name, .. } => {
+            // `if exists` on another name is a no-op; without it the name
+            // was already checked when the statement was prepared.
+            if name == table.header.name {
+                table.drop_table()?
+            }
+            0
+        }
+    };
+    Ok(QueryResult::Affected(affected))
+}
+
+// Reads the row back, so it comes out as stored
+fn returning_row<'a>(
+    table: &'a Table,
+    key: i64,
+    columns: &[String],
+) -> Result<Vec<Row<'a>>, Error> {
+    let schema = table.schema();
+    let indexes = columns
+        .iter()
+        .map(|column| {
+            schema
+                .index_of(column)
+                .ok_or_else(|| Error::UnknownColumn(column.clone()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(vec![table.read(key)?.project(&indexes)])
+}
+
+/// Runs a statement that only reads, so it can share the table with other
+/// readers. Statements that write return `Error::ReadOnly`.
+pub fn query(statement: Statement, table: &Table) -> Result<QueryResult<'_>, Error> {
     match statement {
-        Statement::Insert(insert_statement) => table.insert(insert_statement),
-        Statement::Read(index) => table.read(index),
+        Statement::Read(key) => Ok(QueryResult::Rows(vec![table.read(key)?])),
+        Statement::ReadKeys(keys) => Ok(QueryResult::Rows(table.read_keys(&keys)?)),
+        Statement::Select(select) => select_rows(&select, table).map(QueryResult::Rows),
+        _ => Err(Error::ReadOnly),
+    }
+}
+
+fn select_rows<'a>(select: &SelectStatement, table: &Table) -> Result<Vec<Row<'a>>, Error> {
+    let schema = select.schema(table.schema());
+    if !select.aggregates.is_empty() {
+        let aggregates: Vec<Aggregate> = select
+            .aggregates
+            .iter()
+            .map(|named| named.aggregate.clone())
+            .collect();
+        let values = aggregate(table, &aggregates)?;
+        return Ok(vec![Row::with_schema(0, values, schema)]);
+    }
+    let project = |row: &Row| -> Result<Vec<ScalarValue>, Error> {
+        select
+            .projection
+            .iter()
+            .map(|projection| projection.expr.eval(row))
+            .collect()
+    };
+    let matches = |row: &Row| match &select.filter {
+        Some(filter) => filter.matches(row),
+        None => Ok(true),
+    };
+    if select.order_by.is_empty() {
+        let rows = match &select.keys {
+            Some(keys) => table.read_keys(keys)?,
+            None => table.scan()?,
+        };
+        let mut selected = Vec::new();
+        for row in &rows {
+            if matches(row)? {
+                selected.push(Row::with_schema(row.key(), project(row)?, schema.clone()));
+            }
+        }
+        return Ok(selected);
+    }
+
+    let keys = select
+        .order_by
+        .iter()
+        .map(|order_by| SortKey {
+            collation: match order_by.expr {
+                Expr::Column { index, .. } => table.schema().options[index].collation,
+                _ => Collation::default(),
+            },
+            descending: order_by.descending,
+        })
+        .collect();
+    let mut sort = ExternalSort::new(keys, table.sort_buffer_rows);
+    let push = |row: Row| {
+        if !matches(&row)? {
+            return Ok(());
+        }
+        let sort_values = select
+            .order_by
+            .iter()
+            .map(|order_by| order_by.expr.eval(&row))
+            .collect::<Result<_, _>>()?;
+        sort.push(SortEntry {
+            sort_values,
+            key: row.key(),
+            values: project(&row)?,
+        })
+    };
+    // Rows are read one at a time, so only the sort holds them
+    match &select.keys {
+        Some(keys) => table.read_keys(keys)?.into_iter().try_for_each(push)?,
+        None => table.for_each(push)?,
     }
+    sort.finish()?
+        .map(|entry| {
+            let entry = entry?;
+            Ok(Row::with_schema(entry.key, entry.values, schema.clone()))
+        })
+        .collect()
 }
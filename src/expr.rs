@@ -0,0 +1,1000 @@
+//! Expressions over a row's columns, such as `a + b`, `a * 2`, `a > 5` or
+//! `typeof(a)`, as written in a select list or a where clause.
+
+use std::{cmp::Ordering, fmt::Display};
+
+use crate::{
+    aggregate::{Aggregate, Function, NamedAggregate},
+    datatype::{Collation, DataType, Row, ScalarValue, Schema},
+    errors::Error,
+    statement::{literal, string_literal},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+impl Display for BinaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Eq => "=",
+            BinaryOp::NotEq => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::LtEq => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::GtEq => ">=",
+        };
+        f.write_str(op)
+    }
+}
+
+impl BinaryOp {
+    fn apply(self, x: i64, y: i64) -> Result<i64, Error> {
+        let res = match self {
+            BinaryOp::Add => x.checked_add(y),
+            BinaryOp::Sub => x.checked_sub(y),
+            BinaryOp::Mul => x.checked_mul(y),
+            BinaryOp::Div if y == 0 => return Err(Error::DivisionByZero),
+            BinaryOp::Div => x.checked_div(y),
+            _ => unreachable!("{self} is a comparison"),
+        };
+        res.ok_or_else(|| Error::NumberOutOfRange(format!("{x} {self} {y}")))
+    }
+
+    // Whether `ordering` of the left operand against the right satisfies
+    // the comparison
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            BinaryOp::Eq => ordering.is_eq(),
+            BinaryOp::NotEq => ordering.is_ne(),
+            BinaryOp::Lt => ordering.is_lt(),
+            BinaryOp::LtEq => ordering.is_le(),
+            BinaryOp::Gt => ordering.is_gt(),
+            BinaryOp::GtEq => ordering.is_ge(),
+            _ => unreachable!("{self} is arithmetic"),
+        }
+    }
+
+    fn is_comparison(self) -> bool {
+        self.precedence() == 1
+    }
+
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Add | BinaryOp::Sub => 2,
+            BinaryOp::Mul | BinaryOp::Div => 3,
+            _ => 1,
+        }
+    }
+}
+
+/// A function applied to each row's values, as opposed to an aggregate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarFunction {
+    TypeOf,
+    Length,
+    Upper,
+    Lower,
+    Substr,
+    Abs,
+    Round,
+    Min,
+    Max,
+}
+
+impl Display for ScalarFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ScalarFunction::TypeOf => "typeof",
+            ScalarFunction::Length => "length",
+            ScalarFunction::Upper => "upper",
+            ScalarFunction::Lower => "lower",
+            ScalarFunction::Substr => "substr",
+            ScalarFunction::Abs => "abs",
+            ScalarFunction::Round => "round",
+            ScalarFunction::Min => "min",
+            ScalarFunction::Max => "max",
+        };
+        f.write_str(name)
+    }
+}
+
+impl ScalarFunction {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "typeof" => Some(ScalarFunction::TypeOf),
+            "length" => Some(ScalarFunction::Length),
+            "upper" => Some(ScalarFunction::Upper),
+            "lower" => Some(ScalarFunction::Lower),
+            "substr" => Some(ScalarFunction::Substr),
+            "abs" => Some(ScalarFunction::Abs),
+            "round" => Some(ScalarFunction::Round),
+            "min" => Some(ScalarFunction::Min),
+            "max" => Some(ScalarFunction::Max),
+            _ => None,
+        }
+    }
+
+    // Number of arguments the function takes
+    fn arity(self) -> usize {
+        match self {
+            ScalarFunction::Substr => 3,
+            ScalarFunction::Min | ScalarFunction::Max => 2,
+            _ => 1,
+        }
+    }
+
+    // Null arguments give null, other than to `typeof`. The string
+    // functions take text and `substr` numbers after it, and the numeric
+    // functions take numbers; any other type is an error.
+    fn apply(self, args: Vec<ScalarValue>) -> Result<ScalarValue, Error> {
+        if self == ScalarFunction::TypeOf {
+            return Ok(ScalarValue::String(args[0].type_name().to_string()));
+        }
+        if args.contains(&ScalarValue::Null) {
+            return Ok(ScalarValue::Null);
+        }
+
+        let value = match self {
+            ScalarFunction::Length => {
+                ScalarValue::Number(self.text_arg(&args[0])?.chars().count() as i64)
+            }
+            ScalarFunction::Upper => ScalarValue::String(self.text_arg(&args[0])?.to_uppercase()),
+            ScalarFunction::Lower => ScalarValue::String(self.text_arg(&args[0])?.to_lowercase()),
+            // Up to `len` characters from the 1 based position `start`.
+            // Positions before the first character count towards `len`.
+            ScalarFunction::Substr => {
+                let s = self.text_arg(&args[0])?;
+                let (start, len) = (self.number_arg(&args[1])?, self.number_arg(&args[2])?);
+                let end = start.saturating_add(len.max(0));
+                let chars = s.chars().zip(1..).filter(|&(_, i)| start <= i && i < end);
+                ScalarValue::String(chars.map(|(c, _)| c).collect())
+            }
+            ScalarFunction::Abs => {
+                let x = self.number_arg(&args[0])?;
+                let abs = x
+                    .checked_abs()
+                    .ok_or_else(|| Error::NumberOutOfRange(format!("abs({x})")))?;
+                ScalarValue::Number(abs)
+            }
+            // Numbers are whole, so already round
+            ScalarFunction::Round => ScalarValue::Number(self.number_arg(&args[0])?),
+            ScalarFunction::Min => {
+                let (x, y) = (self.number_arg(&args[0])?, self.number_arg(&args[1])?);
+                ScalarValue::Number(x.min(y))
+            }
+            ScalarFunction::Max => {
+                let (x, y) = (self.number_arg(&args[0])?, self.number_arg(&args[1])?);
+                ScalarValue::Number(x.max(y))
+            }
+            ScalarFunction::TypeOf => unreachable!("typeof takes any value"),
+        };
+        Ok(value)
+    }
+
+    fn invalid(self, value: &ScalarValue) -> Error {
+        Error::InvalidOperand {
+            op: self.to_string(),
+            got: value.type_name(),
+        }
+    }
+
+    fn number_arg(self, value: &ScalarValue) -> Result<i64, Error> {
+        match value {
+            ScalarValue::Number(x) => Ok(*x),
+            value => Err(self.invalid(value)),
+        }
+    }
+
+    fn text_arg(self, value: &ScalarValue) -> Result<&str, Error> {
+        match value {
+            ScalarValue::String(s) => Ok(s),
+            value => Err(self.invalid(value)),
+        }
+    }
+}
+
+/// An expression tree. Columns are resolved to their index when parsed, so
+/// evaluating one never looks a name up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Column { index: usize, name: String },
+    Literal(ScalarValue),
+    Negate(Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+    Call(ScalarFunction, Vec<Expr>),
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Operands that bind looser than their operator need parentheses.
+        // Operators group left to right, so a right operand of the same
+        // precedence does too.
+        fn operand(expr: &Expr, precedence: u8, right: bool) -> String {
+            match expr {
+                Expr::Binary(_, op, _)
+                    if op.precedence() < precedence || (right && op.precedence() == precedence) =>
+                {
+                    format!("({expr})")
+                }
+                _ => expr.to_string(),
+            }
+        }
+
+        match self {
+            Expr::Column { name, .. } => f.write_str(name),
+            Expr::Literal(value) => f.write_str(&literal(value)),
+            Expr::Negate(expr) => write!(f, "-{}", operand(expr, u8::MAX, false)),
+            Expr::Binary(lhs, op, rhs) => {
+                let precedence = op.precedence();
+                let lhs = operand(lhs, precedence, false);
+                write!(f, "{lhs} {op} {}", operand(rhs, precedence, true))
+            }
+            Expr::Call(function, args) => {
+                let args: Vec<String> = args.iter().map(Expr::to_string).collect();
+                write!(f, "{function}({})", args.join(", "))
+            }
+        }
+    }
+}
+
+impl Expr {
+    /// Parses one expression of number and string literals, `null`, column
+    /// names, parentheses, unary `-`, `+ - * /`, the comparisons
+    /// `= != <> < <= > >=` and function calls, with the usual precedence.
+    /// Double quotes make a string and backticks quote a column name.
+    pub fn parse(s: &str, schema: &Schema) -> Result<Expr, Error> {
+        let mut parser = Parser::new(s, schema);
+        let expr = parser.expr(0)?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(_) => Err(parser.unexpected()),
+        }
+    }
+
+    /// Evaluates the expression against `row`. Arithmetic with a null gives
+    /// null, and text operands, division by zero and overflow are errors.
+    /// A comparison gives 1 or 0, or null when either side is null. Text is
+    /// compared with the collation of a column on either side. `typeof`
+    /// gives the type name of its argument's value. `length`, `upper`,
+    /// `lower` and `substr` work on text, `abs`, `round` and the two
+    /// argument `min` and `max` on numbers; each gives null for a null
+    /// argument.
+    pub fn eval(&self, row: &Row) -> Result<ScalarValue, Error> {
+        match self {
+            Expr::Column { index, .. } => Ok(row[*index].clone()),
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Negate(expr) => match expr.eval(row)? {
+                ScalarValue::Number(x) => x
+                    .checked_neg()
+                    .map(ScalarValue::Number)
+                    .ok_or_else(|| Error::NumberOutOfRange(format!("-{x}"))),
+                ScalarValue::Null => Ok(ScalarValue::Null),
+                value => Err(Error::InvalidOperand {
+                    op: "-".to_string(),
+                    got: value.type_name(),
+                }),
+            },
+            Expr::Binary(lhs, op, rhs) if op.is_comparison() => {
+                let (x, y) = (lhs.eval(row)?, rhs.eval(row)?);
+                if x == ScalarValue::Null || y == ScalarValue::Null {
+                    return Ok(ScalarValue::Null);
+                }
+                let collation = lhs
+                    .collation(row.schema())
+                    .or_else(|| rhs.collation(row.schema()))
+                    .unwrap_or_default();
+                let holds = op.holds(collation.compare(&x, &y));
+                Ok(ScalarValue::Number(holds as i64))
+            }
+            Expr::Binary(lhs, op, rhs) => match (lhs.eval(row)?, rhs.eval(row)?) {
+                (ScalarValue::Number(x), ScalarValue::Number(y)) => {
+                    op.apply(x, y).map(ScalarValue::Number)
+                }
+                (value @ ScalarValue::String(_), _) | (_, value @ ScalarValue::String(_)) => {
+                    Err(Error::InvalidOperand {
+                        op: op.to_string(),
+                        got: value.type_name(),
+                    })
+                }
+                _ => Ok(ScalarValue::Null),
+            },
+            Expr::Call(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(row))
+                    .collect::<Result<_, _>>()?;
+                function.apply(args)
+            }
+        }
+    }
+
+    /// The collation text from this expression compares with: its column's,
+    /// when it is a bare column.
+    pub fn collation(&self, schema: &Schema) -> Option<Collation> {
+        match self {
+            Expr::Column { index, .. } => Some(schema.options[*index].collation),
+            _ => None,
+        }
+    }
+
+    /// Whether `row` satisfies the expression used as a condition. Only a
+    /// non-zero number does, so null and text never match.
+    pub fn matches(&self, row: &Row) -> Result<bool, Error> {
+        Ok(matches!(self.eval(row)?, ScalarValue::Number(x) if x != 0))
+    }
+
+    /// The type of the values the expression gives: a bare column's own
+    /// type, text for string literals and functions that return their
+    /// text argument changed, and a number for everything else.
+    pub fn data_type(&self, schema: &Schema) -> DataType {
+        match self {
+            Expr::Column { index, .. } => schema.feilds[*index].1.clone(),
+            Expr::Literal(ScalarValue::String(x)) => DataType::String(x.len()),
+            Expr::Call(
+                ScalarFunction::Upper | ScalarFunction::Lower | ScalarFunction::Substr,
+                args,
+            ) => args[0].data_type(schema),
+            Expr::Call(ScalarFunction::TypeOf, _) => DataType::String("number".len()),
+            _ => DataType::Number,
+        }
+    }
+}
+
+/// An expression of a select list along with the name its result column
+/// goes by: the alias given with `as`, or else the expression's text, which
+/// for a bare column is its name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Projection {
+    pub expr: Expr,
+    pub name: String,
+}
+
+impl Display for Projection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let expr = self.expr.to_string();
+        match expr == self.name {
+            true => f.write_str(&expr),
+            false => write!(f, "{expr} as {}", self.name),
+        }
+    }
+}
+
+/// An expression of an `order by` clause.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderBy {
+    pub expr: Expr,
+    pub descending: bool,
+}
+
+impl Display for OrderBy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.expr)?;
+        if self.descending {
+            f.write_str(" desc")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the expressions after `order by`, each optionally followed by
+/// `asc` or `desc`.
+pub fn parse_order_by(s: &str, schema: &Schema) -> Result<Vec<OrderBy>, Error> {
+    Parser::new(s, schema).order_by()
+}
+
+/// Parses a select list, where each expression may be followed by
+/// `as <name>`, up to the first token that doesn't continue it. Returns the
+/// list and the rest of `s`, starting at that token.
+pub fn parse_select_list<'a>(
+    s: &'a str,
+    schema: &Schema,
+) -> Result<(Vec<Projection>, &'a str), Error> {
+    let mut parser = Parser::new(s, schema);
+    let projection = parser.select_list()?;
+    parser.peek();
+    let end = s.len() - parser.rest.len();
+    Ok((projection, &s[end..]))
+}
+
+/// Parses an expression up to the first token that doesn't continue it, as
+/// a `where` clause followed by `order by`. Returns the expression and the
+/// rest of `s`, starting at that token.
+pub fn parse_predicate<'a>(s: &'a str, schema: &Schema) -> Result<(Expr, &'a str), Error> {
+    let mut parser = Parser::new(s, schema);
+    let predicate = parser.expr(0)?;
+    parser.peek();
+    let end = s.len() - parser.rest.len();
+    Ok((predicate, &s[end..]))
+}
+
+/// Parses a select list of aggregate calls, as `parse_select_list` does a
+/// list of expressions. `None` when the list does not start with one, as
+/// in `select a` or `select min(a, b)`.
+pub fn parse_aggregate_list<'a>(
+    s: &'a str,
+    schema: &Schema,
+) -> Result<Option<(Vec<NamedAggregate>, &'a str)>, Error> {
+    let mut parser = Parser::new(s, schema);
+    let Some(aggregates) = parser.aggregate_list()? else {
+        return Ok(None);
+    };
+    parser.peek();
+    let end = s.len() - parser.rest.len();
+    Ok(Some((aggregates, &s[end..])))
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    rest: &'a str,
+    schema: &'a Schema,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str, schema: &'a Schema) -> Self {
+        Self {
+            input,
+            rest: input,
+            schema,
+        }
+    }
+
+    // Precedence climbing: parses operators that bind tighter than
+    // `min_precedence`, left to right
+    fn expr(&mut self, min_precedence: u8) -> Result<Expr, Error> {
+        let mut lhs = self.operand()?;
+        while let Some(op) = self.peek().and_then(binary_op) {
+            if op.precedence() <= min_precedence {
+                break;
+            }
+            self.next();
+            let rhs = self.expr(op.precedence())?;
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn operand(&mut self) -> Result<Expr, Error> {
+        let Some(token) = self.peek() else {
+            return Err(self.unexpected());
+        };
+
+        if token == "-" {
+            self.next();
+            return Ok(Expr::Negate(Box::new(self.operand()?)));
+        }
+        if token == "(" {
+            self.next();
+            let expr = self.expr(0)?;
+            if self.peek() != Some(")") {
+                return Err(self.unexpected());
+            }
+            self.next();
+            return Ok(expr);
+        }
+        if let Some((x, _)) = string_literal(token) {
+            self.next();
+            return Ok(Expr::Literal(ScalarValue::String(x)));
+        }
+        if token.starts_with(|c: char| c.is_ascii_digit()) {
+            if !token.chars().all(|c| c.is_ascii_digit()) {
+                return Err(self.unexpected());
+            }
+            // All digits, so it can only fail to parse by overflowing
+            let x = token
+                .parse()
+                .map_err(|_| Error::NumberOutOfRange(token.to_string()))?;
+            self.next();
+            return Ok(Expr::Literal(ScalarValue::Number(x)));
+        }
+        if token == "null" {
+            self.next();
+            return Ok(Expr::Literal(ScalarValue::Null));
+        }
+
+        let name = token
+            .strip_prefix('`')
+            .and_then(|name| name.strip_suffix('`'))
+            .unwrap_or(token);
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(self.unexpected());
+        }
+        // A bare name followed by `(` calls a function
+        let rest = self.rest;
+        self.next();
+        if name == token && self.peek() == Some("(") {
+            return self.call(name);
+        }
+        self.rest = rest;
+
+        let index = self
+            .schema
+            .index_of(name)
+            .ok_or_else(|| Error::UnknownColumn(name.to_string()))?;
+        self.next();
+        Ok(Expr::Column {
+            index,
+            name: name.to_string(),
+        })
+    }
+
+    fn order_by(&mut self) -> Result<Vec<OrderBy>, Error> {
+        let mut order_by = Vec::new();
+        loop {
+            let expr = self.expr(0)?;
+            let descending = match self.peek() {
+                Some(token @ ("asc" | "desc")) => {
+                    self.next();
+                    token == "desc"
+                }
+                _ => false,
+            };
+            order_by.push(OrderBy { expr, descending });
+            match self.peek() {
+                Some(",") => self.next(),
+                Some(_) => return Err(self.unexpected()),
+                None => return Ok(order_by),
+            }
+        }
+    }
+
+    fn select_list(&mut self) -> Result<Vec<Projection>, Error> {
+        let mut projection = Vec::new();
+        loop {
+            let expr = self.expr(0)?;
+            let name = self.alias()?.unwrap_or_else(|| expr.to_string());
+            projection.push(Projection { expr, name });
+            if self.peek() != Some(",") {
+                return Ok(projection);
+            }
+            self.next();
+        }
+    }
+
+    // Parses the list only if its first entry is an aggregate, after which
+    // every entry must be one
+    fn aggregate_list(&mut self) -> Result<Option<Vec<NamedAggregate>>, Error> {
+        let mut aggregates = Vec::new();
+        loop {
+            let aggregate = match self.aggregate()? {
+                Some(aggregate) => aggregate,
+                None if aggregates.is_empty() => return Ok(None),
+                None => return Err(self.unexpected()),
+            };
+            let name = self.alias()?.unwrap_or_else(|| aggregate.to_string());
+            aggregates.push(NamedAggregate { aggregate, name });
+            if self.peek() != Some(",") {
+                return Ok(Some(aggregates));
+            }
+            self.next();
+        }
+    }
+
+    // Parses `count(*)` or an aggregate call on one expression. Anything
+    // else, such as the two argument `min(a, b)`, leaves the parser where it
+    // was.
+    fn aggregate(&mut self) -> Result<Option<Aggregate>, Error> {
+        let start = self.rest;
+        let Some(function) = self.peek().and_then(Function::from_name) else {
+            return Ok(None);
+        };
+        self.next();
+        if self.peek() != Some("(") {
+            self.rest = start;
+            return Ok(None);
+        }
+        self.next();
+        let arg = match self.peek() {
+            Some("*") if function == Function::Count => {
+                self.next();
+                None
+            }
+            _ => Some(self.expr(0)?),
+        };
+        if self.peek() != Some(")") {
+            self.rest = start;
+            return Ok(None);
+        }
+        self.next();
+        Ok(Some(Aggregate { function, arg }))
+    }
+
+    // Parses `as <name>` when it follows, the name bare or in backticks
+    fn alias(&mut self) -> Result<Option<String>, Error> {
+        if self.peek() != Some("as") {
+            return Ok(None);
+        }
+        self.next();
+        let alias = self.peek().unwrap_or_default();
+        let name = alias
+            .strip_prefix('`')
+            .and_then(|name| name.strip_suffix('`'))
+            .unwrap_or(alias);
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(self.unexpected());
+        }
+        self.next();
+        Ok(Some(name.to_string()))
+    }
+
+    // Parses the arguments of a call to `name`, starting at its `(`
+    fn call(&mut self, name: &str) -> Result<Expr, Error> {
+        let function = ScalarFunction::from_name(name)
+            .ok_or_else(|| Error::NotFound(format!("function {name}")))?;
+        self.next();
+        let mut args = Vec::new();
+        if self.peek() != Some(")") {
+            args.push(self.expr(0)?);
+            while self.peek() == Some(",") {
+                self.next();
+                args.push(self.expr(0)?);
+            }
+        }
+        if self.peek() != Some(")") {
+            return Err(self.unexpected());
+        }
+        self.next();
+
+        if args.len() != function.arity() {
+            return Err(Error::ArityMismatch {
+                expected: function.arity(),
+                got: args.len(),
+            });
+        }
+        Ok(Expr::Call(function, args))
+    }
+
+    // The next token: a number, a name, a string, or an operator or other
+    // punctuation
+    fn peek(&mut self) -> Option<&'a str> {
+        self.rest = self.rest.trim_start();
+        let mut chars = self.rest.char_indices();
+        let (_, first) = chars.next()?;
+        let end = match first {
+            '"' => string_literal(self.rest).map(|(_, rest)| self.rest.len() - rest.len()),
+            '`' => chars
+                .find(|&(_, c)| c == '`')
+                .map(|(i, c)| i + c.len_utf8()),
+            c if c.is_alphanumeric() || c == '_' => chars
+                .find(|&(_, c)| !(c.is_alphanumeric() || c == '_'))
+                .map(|(i, _)| i),
+            _ => ["!=", "<>", "<=", ">="]
+                .into_iter()
+                .find(|op| self.rest.starts_with(op))
+                .map_or(Some(first.len_utf8()), |op| Some(op.len())),
+        };
+        Some(&self.rest[..end.unwrap_or(self.rest.len())])
+    }
+
+    fn next(&mut self) {
+        if let Some(token) = self.peek() {
+            self.rest = &self.rest[token.len()..];
+        }
+    }
+
+    fn unexpected(&mut self) -> Error {
+        let token = self.peek().unwrap_or_default();
+        Error::UnexpectedToken {
+            token: token.to_string(),
+            position: self.input.len() - self.rest.len(),
+        }
+    }
+}
+
+fn binary_op(token: &str) -> Option<BinaryOp> {
+    match token {
+        "+" => Some(BinaryOp::Add),
+        "-" => Some(BinaryOp::Sub),
+        "*" => Some(BinaryOp::Mul),
+        "/" => Some(BinaryOp::Div),
+        "=" => Some(BinaryOp::Eq),
+        "!=" | "<>" => Some(BinaryOp::NotEq),
+        "<" => Some(BinaryOp::Lt),
+        "<=" => Some(BinaryOp::LtEq),
+        ">" => Some(BinaryOp::Gt),
+        ">=" => Some(BinaryOp::GtEq),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        datatype::{DataType, Row, ScalarValue, Schema},
+        errors::Error,
+        execution::query,
+        statement::{prepare_statement, InsertStatement},
+        table::{Table, MEMORY_PATH},
+    };
+
+    use super::{BinaryOp, Expr};
+
+    fn schema() -> Schema {
+        Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::Number),
+            ("s".to_string(), DataType::String(10)),
+        ])
+    }
+
+    // The rows `line` selects from `table`, as lists of values
+    fn select(line: &str, table: &Table) -> Vec<Vec<ScalarValue>> {
+        let statement = prepare_statement(line, table).unwrap();
+        let rows = query(statement, table).unwrap().into_rows();
+        rows.into_iter().map(Row::into_values).collect()
+    }
+
+    fn eval(s: &str, values: Vec<ScalarValue>) -> Result<ScalarValue, Error> {
+        let schema = schema();
+        let row = Row::new(0, values, &schema);
+        Expr::parse(s, &schema)?.eval(&row)
+    }
+
+    #[test]
+    fn select_a_plus_one() {
+        let path = Path::new(MEMORY_PATH);
+        let mut table = Table::new("t".to_string(), schema(), path).unwrap();
+        for (key, a) in [3, -1, 10].into_iter().enumerate() {
+            let values = vec![
+                ScalarValue::Number(a),
+                ScalarValue::Number(2),
+                ScalarValue::Null,
+            ];
+            let key = key as i64;
+            table
+                .insert(InsertStatement {
+                    key,
+                    values,
+                    returning: None,
+                })
+                .unwrap();
+        }
+
+        let rows = select("select a + 1, a * 2 from t", &table);
+        let number = ScalarValue::Number;
+        assert_eq!(
+            rows,
+            vec![
+                vec![number(4), number(6)],
+                vec![number(0), number(-2)],
+                vec![number(11), number(20)],
+            ]
+        );
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        use ScalarValue::{Null, Number};
+        let values = || vec![Number(7), Number(2), Null];
+        assert_eq!(eval("a + b * 3", values()).unwrap(), Number(13));
+        assert_eq!(eval("(a + b) * 3", values()).unwrap(), Number(27));
+        assert_eq!(eval("a - b - 1", values()).unwrap(), Number(4));
+        assert_eq!(eval("a / b", values()).unwrap(), Number(3));
+        assert_eq!(eval("-a*-b", values()).unwrap(), Number(14));
+        assert_eq!(eval("a + null", values()).unwrap(), Null);
+
+        let schema = schema();
+        assert_eq!(
+            Expr::parse("`a`-1", &schema).unwrap(),
+            Expr::Binary(
+                Box::new(Expr::Column {
+                    index: 0,
+                    name: "a".to_string()
+                }),
+                BinaryOp::Sub,
+                Box::new(Expr::Literal(Number(1)))
+            )
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        use ScalarValue::{Null, Number, String};
+        let values = || vec![Number(7), Number(2), String("x".to_string())];
+        assert_eq!(eval("a > 5", values()).unwrap(), Number(1));
+        assert_eq!(eval("a + 1 <= b * 4", values()).unwrap(), Number(1));
+        assert_eq!(eval("a <> 7", values()).unwrap(), Number(0));
+        assert_eq!(eval("s = \"x\"", values()).unwrap(), Number(1));
+        assert_eq!(eval("s != \"y\"", values()).unwrap(), Number(1));
+        assert_eq!(eval("a = null", values()).unwrap(), Null);
+
+        let schema = schema();
+        let row = Row::new(0, vec![Null, Number(1), Null], &schema);
+        assert!(!Expr::parse("a > 0", &schema)
+            .unwrap()
+            .matches(&row)
+            .unwrap());
+        assert!(Expr::parse("b >= 1", &schema)
+            .unwrap()
+            .matches(&row)
+            .unwrap());
+
+        let expr = Expr::parse("(a - (b - 1)) * -(a + 2) >= \"q\"", &schema).unwrap();
+        assert_eq!(expr.to_string(), "(a - (b - 1)) * -(a + 2) >= \"q\"");
+    }
+
+    #[test]
+    fn evaluation_errors() {
+        use ScalarValue::{Number, String};
+        let values = || vec![Number(1), Number(0), String("x".to_string())];
+        assert!(matches!(
+            eval("a / b", values()),
+            Err(Error::DivisionByZero)
+        ));
+        assert!(matches!(
+            eval("a + s", values()),
+            Err(Error::InvalidOperand { op, got: "text" }) if op == "+"
+        ));
+        assert!(matches!(
+            eval("9223372036854775807 + a", values()),
+            Err(Error::NumberOutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let schema = schema();
+        assert!(matches!(
+            Expr::parse("a + c", &schema),
+            Err(Error::UnknownColumn(column)) if column == "c"
+        ));
+        assert!(matches!(
+            Expr::parse("a + ", &schema),
+            Err(Error::UnexpectedToken { position: 4, .. })
+        ));
+        assert!(matches!(
+            Expr::parse("(a + b", &schema),
+            Err(Error::UnexpectedToken { position: 6, .. })
+        ));
+        let table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        assert!(matches!(
+            prepare_statement("select a b from t", &table),
+            Err(Error::UnexpectedToken { token, position: 9 }) if token == "b"
+        ));
+    }
+
+    #[test]
+    fn typeof_mixed_values() {
+        use ScalarValue::{Null, Number, String};
+        let path = Path::new(MEMORY_PATH);
+        let mut table = Table::new("t".to_string(), schema(), path).unwrap();
+        let rows = [
+            vec![Number(1), Null, String("x".to_string())],
+            vec![Null, Number(2), Null],
+        ];
+        for (key, values) in rows.into_iter().enumerate() {
+            let key = key as i64;
+            table
+                .insert(InsertStatement {
+                    key,
+                    values,
+                    returning: None,
+                })
+                .unwrap();
+        }
+
+        let line = "select typeof(a), typeof(s), typeof(b * 2) from t";
+        assert_eq!(
+            prepare_statement(line, &table).unwrap().to_string(),
+            "select typeof(a), typeof(s), typeof(b * 2) from table"
+        );
+        let text = |s: &str| String(s.to_string());
+        assert_eq!(
+            select(line, &table),
+            vec![
+                vec![text("number"), text("text"), text("null")],
+                vec![text("null"), text("null"), text("number")],
+            ]
+        );
+
+        let schema = schema();
+        assert!(matches!(
+            Expr::parse("typeof(a, b)", &schema),
+            Err(Error::ArityMismatch {
+                expected: 1,
+                got: 2
+            })
+        ));
+        assert!(matches!(
+            Expr::parse("kind(a)", &schema),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            Expr::parse("typeof(a", &schema),
+            Err(Error::UnexpectedToken { position: 8, .. })
+        ));
+    }
+
+    #[test]
+    fn string_functions() {
+        use ScalarValue::{Null, Number, String};
+        let text = |s: &str| String(s.to_string());
+        let values = || vec![Number(3), Null, text("Héllo")];
+        assert_eq!(eval("length(s)", values()).unwrap(), Number(5));
+        assert_eq!(eval("upper(s)", values()).unwrap(), text("HÉLLO"));
+        assert_eq!(eval("lower(s)", values()).unwrap(), text("héllo"));
+        assert_eq!(eval("substr(s, 2, 3)", values()).unwrap(), text("éll"));
+        assert_eq!(eval("substr(s, a, 10)", values()).unwrap(), text("llo"));
+        assert_eq!(eval("substr(s, 0, 2)", values()).unwrap(), text("H"));
+        assert_eq!(eval("substr(s, 9, 2)", values()).unwrap(), text(""));
+        assert_eq!(eval("length(upper(s)) + 1", values()).unwrap(), Number(6));
+        assert_eq!(eval("substr(s, b, 1)", values()).unwrap(), Null);
+        assert_eq!(eval("length(b)", values()).unwrap(), Null);
+
+        assert!(matches!(
+            eval("length(a)", values()),
+            Err(Error::InvalidOperand { op, got: "number" }) if op == "length"
+        ));
+        assert!(matches!(
+            eval("upper(1)", values()),
+            Err(Error::InvalidOperand { op, .. }) if op == "upper"
+        ));
+        assert!(matches!(
+            eval("substr(s, \"1\", 2)", values()),
+            Err(Error::InvalidOperand { op, got: "text" }) if op == "substr"
+        ));
+        assert!(matches!(
+            Expr::parse("substr(s, 1)", &schema()),
+            Err(Error::ArityMismatch {
+                expected: 3,
+                got: 2
+            })
+        ));
+        assert_eq!(
+            Expr::parse("substr(lower(s),1,a+1)", &schema())
+                .unwrap()
+                .to_string(),
+            "substr(lower(s), 1, a + 1)"
+        );
+    }
+
+    #[test]
+    fn numeric_functions() {
+        use ScalarValue::{Null, Number, String};
+        let values = || vec![Number(-4), Null, String("x".to_string())];
+        assert_eq!(eval("abs(-5)", values()).unwrap(), Number(5));
+        assert_eq!(eval("abs(a)", values()).unwrap(), Number(4));
+        assert_eq!(eval("round(a)", values()).unwrap(), Number(-4));
+        assert_eq!(eval("max(3, 7)", values()).unwrap(), Number(7));
+        assert_eq!(eval("min(3, 7)", values()).unwrap(), Number(3));
+        assert_eq!(eval("max(a, abs(a)) * 2", values()).unwrap(), Number(8));
+        assert_eq!(eval("min(a, b)", values()).unwrap(), Null);
+
+        assert!(matches!(
+            eval("abs(s)", values()),
+            Err(Error::InvalidOperand { op, got: "text" }) if op == "abs"
+        ));
+        assert!(matches!(
+            eval("max(a, s)", values()),
+            Err(Error::InvalidOperand { op, got: "text" }) if op == "max"
+        ));
+        assert!(matches!(
+            eval("abs(-9223372036854775807 - 1)", values()),
+            Err(Error::NumberOutOfRange(_))
+        ));
+        // The aggregate takes one argument, the scalar function two
+        assert!(matches!(
+            Expr::parse("max(a)", &schema()),
+            Err(Error::ArityMismatch {
+                expected: 2,
+                got: 1
+            })
+        ));
+    }
+}
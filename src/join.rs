@@ -0,0 +1,325 @@
+//! Equality joins between two tables. Joins are only available from the
+//! library: statements run against a single table, so `select` has no
+//! `join` clause, and callers pick the tables and columns directly.
+
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{
+    datatype::{Collation, ScalarValue, Schema},
+    errors::Error,
+    table::Table,
+};
+
+/// Rows produced by a join. Every row holds the left table's values followed
+/// by the right table's, and the schema names its columns `<table>.<column>`.
+#[derive(Debug)]
+pub struct Joined {
+    pub schema: Schema,
+    pub rows: Vec<Vec<ScalarValue>>,
+    pub strategy: JoinStrategy,
+}
+
+/// How a join found its matching rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinStrategy {
+    /// Every row of one table compared against every row of the other.
+    NestedLoop,
+    /// The smaller table hashed on its join column, then probed with the
+    /// rows of the larger one.
+    Hash,
+}
+
+impl Display for JoinStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinStrategy::NestedLoop => f.write_str("nested loop join"),
+            JoinStrategy::Hash => f.write_str("hash join"),
+        }
+    }
+}
+
+// Below this many rows on the smaller side, comparing every pair costs less
+// than building a hash table
+const HASH_JOIN_MIN_ROWS: usize = 16;
+
+/// Joins on `left_column = right_column`, picking the strategy from the
+/// table sizes. The strategy used is reported in the result. Neither table
+/// has indexes to look rows up by, so an index join is never an option.
+pub fn join(
+    left: &Table,
+    left_column: &str,
+    right: &Table,
+    right_column: &str,
+) -> Result<Joined, Error> {
+    if left.header.num_rows.min(right.header.num_rows) < HASH_JOIN_MIN_ROWS {
+        nested_loop_join(left, left_column, right, right_column)
+    } else {
+        hash_join(left, left_column, right, right_column)
+    }
+}
+
+/// Pairs every row of `left` with every row of `right` whose `right_column`
+/// equals its `left_column`, compared with the left column's collation. Null
+/// never equals anything. Rows come out in left key order, then right key
+/// order.
+pub fn nested_loop_join(
+    left: &Table,
+    left_column: &str,
+    right: &Table,
+    right_column: &str,
+) -> Result<Joined, Error> {
+    let (left_index, right_index) = (
+        column_index(left, left_column)?,
+        column_index(right, right_column)?,
+    );
+    let collation = left.schema().options[left_index].collation;
+
+    let inner = right.scan()?;
+    let mut rows = Vec::new();
+    for outer in left.scan()? {
+        if outer[left_index] == ScalarValue::Null {
+            continue;
+        }
+        for inner in &inner {
+            if collation.equals(&outer[left_index], &inner[right_index]) {
+                rows.push([outer.values(), inner.values()].concat());
+            }
+        }
+    }
+
+    Ok(Joined {
+        schema: joined_schema(left, right),
+        rows,
+        strategy: JoinStrategy::NestedLoop,
+    })
+}
+
+/// Matches the same rows as `nested_loop_join`, by hashing the smaller
+/// table's rows on their join column and looking up each row of the larger
+/// one. Rows come out in the larger table's key order.
+pub fn hash_join(
+    left: &Table,
+    left_column: &str,
+    right: &Table,
+    right_column: &str,
+) -> Result<Joined, Error> {
+    let (left_index, right_index) = (
+        column_index(left, left_column)?,
+        column_index(right, right_column)?,
+    );
+    let collation = left.schema().options[left_index].collation;
+
+    let build_left = left.header.num_rows <= right.header.num_rows;
+    let (build, build_index, probe, probe_index) = match build_left {
+        true => (left, left_index, right, right_index),
+        false => (right, right_index, left, left_index),
+    };
+
+    let build_rows = build.scan()?;
+    let mut hashed: HashMap<ScalarValue, Vec<usize>> = HashMap::new();
+    for (i, row) in build_rows.iter().enumerate() {
+        if row[build_index] != ScalarValue::Null {
+            let key = hash_key(collation, &row[build_index]);
+            hashed.entry(key).or_default().push(i);
+        }
+    }
+
+    let mut rows = Vec::new();
+    for row in probe.scan()? {
+        if row[probe_index] == ScalarValue::Null {
+            continue;
+        }
+        let Some(matches) = hashed.get(&hash_key(collation, &row[probe_index])) else {
+            continue;
+        };
+        for &i in matches {
+            let (outer, inner) = match build_left {
+                true => (build_rows[i].values(), row.values()),
+                false => (row.values(), build_rows[i].values()),
+            };
+            rows.push([outer, inner].concat());
+        }
+    }
+
+    Ok(Joined {
+        schema: joined_schema(left, right),
+        rows,
+        strategy: JoinStrategy::Hash,
+    })
+}
+
+// Values equal under `collation` hash to the same key
+fn hash_key(collation: Collation, value: &ScalarValue) -> ScalarValue {
+    match (collation, value) {
+        (Collation::NoCase, ScalarValue::String(x)) => ScalarValue::String(x.to_ascii_lowercase()),
+        _ => value.clone(),
+    }
+}
+
+fn column_index(table: &Table, column: &str) -> Result<usize, Error> {
+    table
+        .schema()
+        .index_of(column)
+        .ok_or_else(|| Error::UnknownColumn(format!("{}.{column}", table.header.name)))
+}
+
+fn joined_schema(left: &Table, right: &Table) -> Schema {
+    let mut schema = Schema::new(Vec::new());
+    for table in [left, right] {
+        let name = &table.header.name;
+        let source = table.schema();
+        for ((column, ty), options) in source.feilds.iter().zip(&source.options) {
+            schema.feilds.push((format!("{name}.{column}"), ty.clone()));
+            schema.options.push(options.clone());
+        }
+    }
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        datatype::{DataType, ScalarValue, Schema},
+        errors::Error,
+        statement::InsertStatement,
+        table::{Table, MEMORY_PATH},
+    };
+
+    use super::{hash_join, join, nested_loop_join, JoinStrategy};
+
+    fn table(name: &str, feilds: Vec<(&str, DataType)>, rows: Vec<Vec<ScalarValue>>) -> Table {
+        let feilds = feilds
+            .into_iter()
+            .map(|(name, ty)| (name.to_string(), ty))
+            .collect();
+        let path = Path::new(MEMORY_PATH);
+        let mut table = Table::new(name.to_string(), Schema::new(feilds), path).unwrap();
+        for (key, values) in rows.into_iter().enumerate() {
+            let key = key as i64;
+            table
+                .insert(InsertStatement {
+                    key,
+                    values,
+                    returning: None,
+                })
+                .unwrap();
+        }
+        table
+    }
+
+    fn text(x: &str) -> ScalarValue {
+        ScalarValue::String(x.to_string())
+    }
+
+    #[test]
+    fn equality_join() {
+        use ScalarValue::{Null, Number};
+        let a = table(
+            "a",
+            vec![("id", DataType::Number), ("name", DataType::String(10))],
+            vec![
+                vec![Number(1), text("ann")],
+                vec![Number(2), text("bob")],
+                vec![Null, text("cy")],
+            ],
+        );
+        let b = table(
+            "b",
+            vec![("aid", DataType::Number), ("item", DataType::String(10))],
+            vec![
+                vec![Number(2), text("pen")],
+                vec![Number(1), text("cup")],
+                vec![Number(2), text("ink")],
+                vec![Null, text("box")],
+            ],
+        );
+
+        let joined = nested_loop_join(&a, "id", &b, "aid").unwrap();
+        let names: Vec<&str> = joined
+            .schema
+            .feilds
+            .iter()
+            .map(|(x, _)| x.as_str())
+            .collect();
+        assert_eq!(names, ["a.id", "a.name", "b.aid", "b.item"]);
+        assert_eq!(
+            joined.rows,
+            vec![
+                vec![Number(1), text("ann"), Number(1), text("cup")],
+                vec![Number(2), text("bob"), Number(2), text("pen")],
+                vec![Number(2), text("bob"), Number(2), text("ink")],
+            ]
+        );
+
+        assert_eq!(joined.strategy, JoinStrategy::NestedLoop);
+
+        assert!(matches!(
+            nested_loop_join(&a, "id", &b, "missing"),
+            Err(Error::UnknownColumn(column)) if column == "b.missing"
+        ));
+    }
+
+    #[test]
+    fn hash_join_matches_nested_loop() {
+        use ScalarValue::{Null, Number};
+        let a = table(
+            "a",
+            vec![("id", DataType::Number)],
+            (0..40)
+                .map(|i| vec![if i % 7 == 0 { Null } else { Number(i % 13) }])
+                .collect(),
+        );
+        let b = table(
+            "b",
+            vec![("aid", DataType::Number), ("n", DataType::Number)],
+            (0..25).map(|i| vec![Number(i % 9), Number(i)]).collect(),
+        );
+
+        let mut expected = nested_loop_join(&a, "id", &b, "aid").unwrap().rows;
+        expected.sort_by_key(|row| format!("{row:?}"));
+        assert!(!expected.is_empty());
+        // Either table may be the one hashed
+        for (left, lc, right, rc) in [(&a, "id", &b, "aid"), (&b, "aid", &a, "id")] {
+            let joined = join(left, lc, right, rc).unwrap();
+            assert_eq!(joined.strategy, JoinStrategy::Hash);
+            assert_eq!(joined.strategy.to_string(), "hash join");
+            let mut rows = joined.rows;
+            if lc == "aid" {
+                // Put the columns back in a then b order
+                rows = rows
+                    .into_iter()
+                    .map(|row| [&row[2..], &row[..2]].concat())
+                    .collect();
+            }
+            rows.sort_by_key(|row| format!("{row:?}"));
+            assert_eq!(rows, expected);
+        }
+    }
+
+    #[test]
+    fn hash_join_uses_collation() {
+        let mut a = table("a", vec![("x", DataType::String(5))], vec![]);
+        a.header.schema.options[0].collation = crate::datatype::Collation::NoCase;
+        for (key, x) in ["Ab", "cd"].into_iter().enumerate() {
+            let values = vec![text(x)];
+            a.insert(InsertStatement {
+                key: key as i64,
+                values,
+                returning: None,
+            })
+            .unwrap();
+        }
+        let b = table(
+            "b",
+            vec![("y", DataType::String(5))],
+            vec![vec![text("aB")]],
+        );
+
+        let nested = nested_loop_join(&a, "x", &b, "y").unwrap();
+        let hashed = hash_join(&a, "x", &b, "y").unwrap();
+        assert_eq!(nested.rows, vec![vec![text("Ab"), text("aB")]]);
+        assert_eq!(hashed.rows, nested.rows);
+    }
+}
@@ -0,0 +1,22 @@
+pub const PAGE_SIZE: usize = 4096;
+pub const TABLE_MAX_PAGE: usize = 100;
+
+pub mod aggregate;
+pub mod catalog;
+pub mod codec;
+pub mod commands;
+pub mod custom;
+pub mod database;
+pub mod datatype;
+pub mod dictionary;
+pub mod errors;
+pub mod execution;
+pub mod expr;
+pub mod join;
+pub mod plan_cache;
+pub mod pool;
+pub mod repl;
+pub mod sort;
+pub mod statement;
+pub mod table;
+pub mod tree;
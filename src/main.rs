@@ -1,65 +1,76 @@
 use std::{
-    env::current_dir,
+    env::{self, current_dir},
     ops::DerefMut,
-    sync::{Mutex, OnceLock},
+    path::PathBuf,
+    sync::RwLock,
 };
 
-use commands::Command;
-use execution::execution;
-use repl::Repl;
-use statement::prepare_statement;
-
-use crate::{
-    datatype::{DataType, Schema},
-    table::Table,
+use sqlite::{
+    commands::{self, Command},
+    database::Database,
+    datatype::Schema,
+    errors,
+    execution::{execution, query},
+    repl::Repl,
+    statement::strip_comments,
 };
 
-const PAGE_SIZE: usize = 4096;
-const TABLE_MAX_PAGE: usize = 100;
-
-mod commands;
-mod datatype;
-mod errors;
-mod execution;
-mod repl;
-mod statement;
-mod table;
-mod tree;
-
-fn global_table() -> &'static Mutex<table::Table> {
-    static TABLE: OnceLock<Mutex<Table>> = OnceLock::new();
-    TABLE.get_or_init(|| {
-        let schema = Schema {
-            feilds: vec![
-                ("a".to_string(), DataType::Number),
-                ("b".to_string(), DataType::String(10)),
-            ],
-        };
-
-        Mutex::new(
-            Table::new(
-                "global".to_string(),
-                schema,
-                &current_dir().unwrap().join("global.db"),
-            )
-            .unwrap(),
-        )
-    })
-}
-
 fn main() -> Result<(), errors::Error> {
+    // Tables live in the directory given as the first argument, or the
+    // current one
+    let dir = match env::args_os().nth(1) {
+        Some(dir) => PathBuf::from(dir),
+        None => current_dir()?,
+    };
+    let database = Database::open_dir(dir)?;
+    let schema = Schema::builder().number("a").text("b", 10).build()?;
+    let global_table = RwLock::new(database.open_table("global", schema)?);
+
     let mut repl = Repl::new();
     repl.init();
     while let Some(line) = repl.input() {
-        if line.chars().nth(0) == Some('.') {
-            let cmd: Command = line.parse()?;
-            commands::do_meta_commands(cmd)?;
+        repl.echo_line(&line)?;
+        if line.starts_with('.') {
+            let cmd: Command = match line.parse() {
+                Ok(cmd) => cmd,
+                Err(err) => {
+                    repl.print_error(&line, &err);
+                    continue;
+                }
+            };
+            let mut table = global_table.write().unwrap();
+            if let Err(err) = commands::do_meta_commands(cmd, &mut repl, &mut table) {
+                repl.print_error(&line, &err);
+            }
+            continue;
+        }
+
+        if strip_comments(&line).trim().is_empty() {
+            continue;
         }
 
-        let mut table = global_table().lock().unwrap();
-        let statement = prepare_statement(&line, &*table)?;
+        let statement = match repl.plans.prepare(&line, &global_table.read().unwrap()) {
+            Ok(statement) => statement,
+            Err(err) => {
+                repl.print_error(&line, &err);
+                continue;
+            }
+        };
 
-        execution(statement, table.deref_mut()).unwrap();
+        // Reads only need a shared lock
+        if statement.is_read_only() {
+            let table = global_table.read().unwrap();
+            match query(statement, &table) {
+                Ok(result) => repl.print_result(&result)?,
+                Err(err) => repl.print_error(&line, &err),
+            }
+        } else {
+            let mut table = global_table.write().unwrap();
+            match execution(statement, table.deref_mut()) {
+                Ok(result) => repl.print_result(&result)?,
+                Err(err) => repl.print_error(&line, &err),
+            }
+        }
     }
     Ok(())
 }
@@ -0,0 +1,165 @@
+//! Prepared statements kept by their text, so a line that is run again is
+//! not parsed again.
+
+use std::collections::VecDeque;
+
+use crate::{
+    datatype::{KeyType, Schema},
+    errors::Error,
+    statement::{prepare_statement, Statement},
+    table::Table,
+};
+
+// What a statement was prepared against. Parsing fills in defaults and
+// checks names against the table, so a statement is only reused while these
+// are unchanged.
+#[derive(PartialEq)]
+struct Fingerprint {
+    name: String,
+    schema: Schema,
+    key_type: KeyType,
+    coerce: bool,
+}
+
+impl Fingerprint {
+    fn of(table: &Table) -> Self {
+        Self {
+            name: table.header.name.clone(),
+            schema: table.header.schema.clone(),
+            key_type: table.key_type(),
+            coerce: table.coerce,
+        }
+    }
+}
+
+/// Holds up to `capacity` prepared statements, dropping the least recently
+/// used one to make room. A capacity of 0 turns caching off.
+pub struct PlanCache {
+    capacity: usize,
+    // Most recently used last
+    plans: VecDeque<(String, Fingerprint, Statement)>,
+    parses: usize,
+    hits: usize,
+}
+
+impl PlanCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            plans: VecDeque::new(),
+            parses: 0,
+            hits: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the capacity, dropping the least recently used statements
+    /// that no longer fit.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.plans.len() > capacity {
+            self.plans.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.plans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plans.is_empty()
+    }
+
+    /// How many statements were parsed rather than found.
+    pub fn parses(&self) -> usize {
+        self.parses
+    }
+
+    /// How many statements were found without parsing.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Like `prepare_statement`, reusing the statement prepared from the
+    /// same text against the same table if there is one. Statements that
+    /// fail to parse are not kept.
+    pub fn prepare(&mut self, sql: &str, table: &Table) -> Result<Statement, Error> {
+        let fingerprint = Fingerprint::of(table);
+        let found = self
+            .plans
+            .iter()
+            .position(|(text, of, _)| text == sql && *of == fingerprint);
+        if let Some(index) = found {
+            self.hits += 1;
+            let plan = self.plans.remove(index).unwrap();
+            let statement = plan.2.clone();
+            self.plans.push_back(plan);
+            return Ok(statement);
+        }
+
+        self.parses += 1;
+        let statement = prepare_statement(sql, table)?;
+        if self.capacity > 0 {
+            // An entry for an older version of the table is no use any more
+            self.plans.retain(|(text, _, _)| text != sql);
+            if self.plans.len() >= self.capacity {
+                self.plans.pop_front();
+            }
+            self.plans
+                .push_back((sql.to_string(), fingerprint, statement.clone()));
+        }
+        Ok(statement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        datatype::{ColumnOptions, DataType, Schema},
+        execution::execution,
+        table::{Table, MEMORY_PATH},
+    };
+
+    use super::PlanCache;
+
+    #[test]
+    fn repeated_select_parses_once() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        let mut cache = PlanCache::new(2);
+        let insert = cache.prepare("insert 1 10", &table).unwrap();
+        execution(insert, &mut table).unwrap();
+
+        let select = "select * from t where key in (1)";
+        for _ in 0..2 {
+            let statement = cache.prepare(select, &table).unwrap();
+            let rows = execution(statement, &mut table).unwrap().into_rows();
+            assert_eq!(rows[0].key(), 1);
+        }
+        assert_eq!((cache.parses(), cache.hits()), (2, 1));
+
+        // A schema change means parsing again, in place of the old entry
+        table
+            .add_column("b".to_string(), DataType::Number, ColumnOptions::default())
+            .unwrap();
+        cache.prepare(select, &table).unwrap();
+        assert_eq!((cache.parses(), cache.len()), (3, 2));
+
+        // The least recently used statement makes room, failures aren't kept
+        cache.prepare("read 1", &table).unwrap();
+        assert!(cache.prepare("read x", &table).is_err());
+        assert_eq!(cache.len(), 2);
+        cache.prepare("insert 1 10 20", &table).unwrap();
+        assert_eq!(cache.parses(), 6);
+
+        cache.set_capacity(0);
+        assert!(cache.is_empty());
+        cache.prepare(select, &table).unwrap();
+        assert!(cache.is_empty());
+    }
+}
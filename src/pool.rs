@@ -0,0 +1,154 @@
+//! Connections for sharing one table between threads. Reads run under a
+//! shared lock and writes under an exclusive one, so any number of readers
+//! proceed together while writes are serialized.
+
+use std::sync::{Arc, Condvar, Mutex, PoisonError, RwLock};
+
+use crate::{
+    datatype::ScalarValue,
+    errors::Error,
+    execution::{execution, query},
+    statement::prepare_statement,
+    table::Table,
+};
+
+struct Shared {
+    table: RwLock<Table>,
+    size: usize,
+    in_use: Mutex<usize>,
+    returned: Condvar,
+}
+
+/// Hands out up to `size` connections to a table at a time.
+#[derive(Clone)]
+pub struct Pool {
+    shared: Arc<Shared>,
+}
+
+impl Pool {
+    pub fn new(table: Table, size: usize) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                table: RwLock::new(table),
+                size,
+                in_use: Mutex::new(0),
+                returned: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Takes a connection, waiting for one to be returned if all are in use.
+    pub fn get(&self) -> Connection {
+        let mut in_use = self
+            .shared
+            .in_use
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        while *in_use >= self.shared.size {
+            in_use = self
+                .shared
+                .returned
+                .wait(in_use)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        *in_use += 1;
+        self.connection()
+    }
+
+    /// Takes a connection if one is free.
+    pub fn try_get(&self) -> Option<Connection> {
+        let mut in_use = self
+            .shared
+            .in_use
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if *in_use >= self.shared.size {
+            return None;
+        }
+        *in_use += 1;
+        Some(self.connection())
+    }
+
+    fn connection(&self) -> Connection {
+        Connection {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+/// A handle from a `Pool`, given back when dropped.
+pub struct Connection {
+    shared: Arc<Shared>,
+}
+
+impl Connection {
+    /// Prepares and runs `sql`, returning the values of any rows it read.
+    pub fn execute(&self, sql: &str) -> Result<Vec<Vec<ScalarValue>>, Error> {
+        let table = &self.shared.table;
+        let statement =
+            prepare_statement(sql, &*table.read().unwrap_or_else(PoisonError::into_inner))?;
+
+        let rows = if statement.is_read_only() {
+            let table = table.read().unwrap_or_else(PoisonError::into_inner);
+            let rows = query(statement, &table)?.into_rows();
+            rows.into_iter().map(|row| row.into_values()).collect()
+        } else {
+            let mut table = table.write().unwrap_or_else(PoisonError::into_inner);
+            let rows = execution(statement, &mut table)?.into_rows();
+            rows.into_iter().map(|row| row.into_values()).collect()
+        };
+        Ok(rows)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let mut in_use = self
+            .shared
+            .in_use
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *in_use -= 1;
+        self.shared.returned.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, thread};
+
+    use crate::{
+        datatype::{DataType, ScalarValue, Schema},
+        table::{Table, MEMORY_PATH},
+    };
+
+    use super::Pool;
+
+    #[test]
+    fn connections_share_the_table() {
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        let pool = Pool::new(table, 2);
+
+        let writer = pool.get();
+        let reader = pool.get();
+        assert!(pool.try_get().is_none());
+
+        thread::scope(|s| {
+            s.spawn(|| writer.execute("insert 1 10 \"x\"").unwrap());
+        });
+        assert_eq!(
+            reader.execute("read 1").unwrap(),
+            vec![vec![
+                ScalarValue::Number(10),
+                ScalarValue::String("x".to_string())
+            ]]
+        );
+
+        drop(writer);
+        assert!(pool.try_get().is_some());
+    }
+}
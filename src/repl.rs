@@ -1,14 +1,186 @@
-use std::io::Write;
+use std::{
+    fs::File,
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+use crate::{
+    datatype::{Row, ScalarValue},
+    errors::Error,
+    execution::QueryResult,
+    plan_cache::PlanCache,
+    statement::{is_complete, strip_comments},
+};
+
+const PROMPT: &str = "sqlite> ";
+// Shown while a statement is still missing its `;`. As wide as `PROMPT`, so
+// carets line up on either.
+const CONTINUATION_PROMPT: &str = "   ...> ";
+/// Statements kept parsed by a new `Repl`.
+pub const PLAN_CACHE_SIZE: usize = 32;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Values joined by the separator, one row per line.
+    #[default]
+    List,
+    /// Values padded into aligned columns under a header.
+    Column,
+    /// An array of objects keyed by column name.
+    Json,
+}
 
 pub struct Repl {
     history: Vec<String>,
+    pub mode: Mode,
+    /// Pinned column widths for column mode; 0 (or a missing entry) means auto.
+    pub widths: Vec<usize>,
+    /// Placed between the values of a row in list mode.
+    pub separator: String,
+    /// Printed in place of NULL values.
+    pub null_value: String,
+    /// Whether each line is written to the output before it runs.
+    pub echo: bool,
+    /// Where results go; stdout unless changed with `.output`.
+    output: Box<dyn Write>,
+    /// Statements already parsed, so lines run again skip parsing.
+    pub plans: PlanCache,
 }
 
 impl Repl {
     pub fn new() -> Self {
         Self {
             history: Vec::default(),
+            mode: Mode::default(),
+            widths: Vec::default(),
+            separator: "|".to_string(),
+            null_value: String::new(),
+            echo: false,
+            output: Box::new(io::stdout()),
+            plans: PlanCache::new(PLAN_CACHE_SIZE),
+        }
+    }
+
+    /// Sends results to the file at `path`, replacing its contents, or back
+    /// to stdout when `path` is `None`.
+    pub fn set_output(&mut self, path: Option<&Path>) -> Result<(), io::Error> {
+        self.output.flush()?;
+        self.output = match path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(io::stdout()),
+        };
+        Ok(())
+    }
+
+    /// Writes a line of results to the current output.
+    pub fn write_line(&mut self, line: impl std::fmt::Display) -> Result<(), io::Error> {
+        writeln!(self.output, "{line}")
+    }
+
+    /// Writes `line` to the output when echo is on.
+    pub fn echo_line(&mut self, line: &str) -> Result<(), io::Error> {
+        if self.echo {
+            self.write_line(line)?;
+        }
+        Ok(())
+    }
+
+    fn render_value(&self, value: &ScalarValue) -> String {
+        match value {
+            ScalarValue::Null => self.null_value.clone(),
+            x => x.to_string(),
+        }
+    }
+
+    /// Renders a row in list mode: every value joined by the separator.
+    pub fn render_row(&self, row: &Row) -> String {
+        let values: Vec<String> = row.values().iter().map(|x| self.render_value(x)).collect();
+        values.join(&self.separator)
+    }
+
+    /// Renders a result set in the current mode, one string per line.
+    pub fn render(&self, rows: &[Row]) -> Vec<String> {
+        match self.mode {
+            Mode::List => rows.iter().map(|row| self.render_row(row)).collect(),
+            Mode::Column => self.render_columns(rows),
+            Mode::Json => render_json(rows),
+        }
+    }
+
+    fn render_columns(&self, rows: &[Row]) -> Vec<String> {
+        let Some(first) = rows.first() else {
+            return Vec::new();
+        };
+        let names: Vec<&str> = first
+            .schema()
+            .feilds
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        let values: Vec<Vec<String>> = rows
+            .iter()
+            .map(|row| row.values().iter().map(|x| self.render_value(x)).collect())
+            .collect();
+
+        let widths: Vec<usize> = (0..names.len())
+            .map(|i| match self.widths.get(i) {
+                Some(&width) if width > 0 => width,
+                _ => values
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain(std::iter::once(names[i].chars().count()))
+                    .max()
+                    .unwrap_or(0),
+            })
+            .collect();
+
+        let line = |cells: Vec<String>| -> String {
+            let cells: Vec<String> = cells
+                .iter()
+                .zip(&widths)
+                .map(|(cell, &width)| {
+                    let cell: String = cell.chars().take(width).collect();
+                    format!("{cell:<width$}")
+                })
+                .collect();
+            cells.join("  ").trim_end().to_string()
+        };
+
+        let mut lines = vec![
+            line(names.iter().map(|x| x.to_string()).collect()),
+            line(widths.iter().map(|&width| "-".repeat(width)).collect()),
+        ];
+        lines.extend(values.into_iter().map(line));
+        lines
+    }
+
+    pub fn print_rows(&mut self, rows: &[Row]) -> Result<(), io::Error> {
+        for line in self.render(rows) {
+            self.write_line(line)?;
+        }
+        self.output.flush()
+    }
+
+    /// Prints a statement's rows followed by how many there were, or how
+    /// many rows it affected.
+    pub fn print_result(&mut self, result: &QueryResult) -> Result<(), io::Error> {
+        match result {
+            QueryResult::Rows(rows) => {
+                self.print_rows(rows)?;
+                self.write_line(row_count(rows.len()))?;
+            }
+            QueryResult::Affected(n) => self.write_line(format!("{} affected", row_count(*n)))?,
+        }
+        self.output.flush()
+    }
+
+    /// Prints a statement error. An unexpected token also gets a caret under
+    /// it, lined up with the line as it was typed after the prompt.
+    pub fn print_error(&self, line: &str, err: &Error) {
+        if let Error::UnexpectedToken { position, .. } = err {
+            println!("{}", caret(line, *position));
         }
+        println!("Error: {err}");
     }
 
     pub fn init(&self) {
@@ -17,20 +189,126 @@ impl Repl {
 
     // Returns None on exit
     pub fn input(&mut self) -> Option<String> {
-        print!("sqlite> ");
-        std::io::stdout().flush().expect("Failed to flush");
-        let mut line = String::new();
-        let read_bytes = std::io::stdin()
-            .read_line(&mut line)
-            .expect("Error reading from stdin");
-        if line.ends_with('\n') {
-            line.truncate(line.len() - 1)
+        self.read_statement(&mut io::stdin().lock())
+    }
+
+    /// Reads a meta command, which is always a single line, or a statement,
+    /// which takes lines until one ends it with `;`. Lines are joined with
+    /// newlines. Returns None at the end of input.
+    pub fn read_statement(&mut self, input: &mut impl BufRead) -> Option<String> {
+        let statement = next_statement(input, true)?;
+        self.history.push(statement.clone());
+        Some(statement)
+    }
+}
+
+/// Reads the next meta command or statement the way `Repl::read_statement`
+/// does, showing the prompts only when `prompts` is set.
+pub fn next_statement(input: &mut impl BufRead, prompts: bool) -> Option<String> {
+    let prompt = |prompt| prompts.then_some(prompt);
+    let mut statement = read_line(input, prompt(PROMPT))?;
+    let blank = strip_comments(&statement).trim().is_empty();
+    if !(statement.starts_with('.') || blank) {
+        while !is_complete(&statement) {
+            let Some(line) = read_line(input, prompt(CONTINUATION_PROMPT)) else {
+                break;
+            };
+            statement.push('\n');
+            statement.push_str(&line);
         }
-        if read_bytes == 0 {
-            std::process::exit(0)
+    }
+    Some(statement)
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn read_line(input: &mut impl BufRead, prompt: Option<&str>) -> Option<String> {
+    if let Some(prompt) = prompt {
+        print!("{prompt}");
+        io::stdout().flush().expect("Failed to flush");
+    }
+    let mut line = String::new();
+    let read_bytes = input
+        .read_line(&mut line)
+        .expect("Error reading from stdin");
+    if read_bytes == 0 {
+        return None;
+    }
+    if line.ends_with('\n') {
+        line.truncate(line.len() - 1)
+    }
+    Some(line)
+}
+
+// One object per row in a single array, as sqlite writes it: each row on
+// its own line, and null left as null whatever `null_value` says
+fn render_json(rows: &[Row]) -> Vec<String> {
+    let last = rows.len().saturating_sub(1);
+    rows.iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let members: Vec<String> = row
+                .schema()
+                .feilds
+                .iter()
+                .zip(row.values())
+                .map(|((name, _), value)| {
+                    let value = match value {
+                        ScalarValue::String(x) => json_string(x),
+                        ScalarValue::Number(x) => x.to_string(),
+                        ScalarValue::Null => "null".to_string(),
+                    };
+                    format!("{}:{value}", json_string(name))
+                })
+                .collect();
+            let open = if i == 0 { "[" } else { "" };
+            let close = if i == last { "]" } else { "," };
+            format!("{open}{{{}}}{close}", members.join(","))
+        })
+        .collect()
+}
+
+fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for char in s.chars() {
+        match char {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if c.is_control() => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
         }
-        self.history.push(line.clone());
-        Some(line)
+    }
+    res.push('"');
+    res
+}
+
+// "1 row", "2 rows"
+fn row_count(n: usize) -> String {
+    match n {
+        1 => "1 row".to_string(),
+        n => format!("{n} rows"),
+    }
+}
+
+/// A line with a `^` under the character at byte offset `position` of
+/// `statement`, allowing for the prompt in front of it. When that character
+/// is not on the last line typed, its line is repeated above the caret.
+fn caret(statement: &str, position: usize) -> String {
+    let before = statement.get(..position).unwrap_or_default();
+    let start = before.rfind('\n').map_or(0, |i| i + 1);
+    let column = before[start..].chars().count();
+    let caret = format!("{}^", " ".repeat(PROMPT.len() + column));
+    match statement[start..].split_once('\n') {
+        Some((line, _)) => format!("{}{line}\n{caret}", " ".repeat(PROMPT.len())),
+        None => caret,
     }
 }
 
@@ -38,3 +316,256 @@ fn welcome() -> String {
     "Welcome to Sqlite".to_string()
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::{
+        commands::{do_meta_commands, Command},
+        datatype::{DataType, Row, ScalarValue, Schema},
+        execution::{execution, QueryResult},
+        statement::prepare_statement,
+        table::Table,
+    };
+
+    use super::{caret, Mode, Repl};
+
+    #[test]
+    fn list_mode_separator() {
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let row = Row::new(
+            0,
+            vec![ScalarValue::Number(1), ScalarValue::String("x".to_string())],
+            &schema,
+        );
+        let mut repl = Repl::new();
+        assert_eq!(repl.render_row(&row), "1|x");
+
+        let mut table = Table::new(
+            "t".to_string(),
+            schema.clone(),
+            std::path::Path::new(crate::table::MEMORY_PATH),
+        )
+        .unwrap();
+        let command: Command = ".separator \\t".parse().unwrap();
+        do_meta_commands(command, &mut repl, &mut table).unwrap();
+        assert_eq!(repl.render_row(&row), "1\tx");
+    }
+
+    #[test]
+    fn null_value_placeholder() {
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::String(10)),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let row = Row::new(
+            0,
+            vec![ScalarValue::Null, ScalarValue::String(String::new())],
+            &schema,
+        );
+        let mut repl = Repl::new();
+        assert_eq!(repl.render_row(&row), "|");
+
+        let mut table = Table::new(
+            "t".to_string(),
+            schema.clone(),
+            std::path::Path::new(crate::table::MEMORY_PATH),
+        )
+        .unwrap();
+        let command: Command = ".nullvalue NULL".parse().unwrap();
+        do_meta_commands(command, &mut repl, &mut table).unwrap();
+        assert_eq!(repl.render_row(&row), "NULL|");
+    }
+
+    #[test]
+    fn select_aliases() {
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let mut table = Table::new(
+            "t".to_string(),
+            schema,
+            std::path::Path::new(crate::table::MEMORY_PATH),
+        )
+        .unwrap();
+        for line in ["insert 1 7 \"x\"", "insert 2 8 null"] {
+            execution(prepare_statement(line, &table).unwrap(), &mut table).unwrap();
+        }
+
+        let line = "select a as id, b as name, a * 2, upper(b) from t";
+        let statement = prepare_statement(line, &table).unwrap();
+        assert!(statement.is_read_only());
+        assert_eq!(
+            statement.to_string(),
+            "select a as id, b as name, a * 2, upper(b) from table"
+        );
+        let rows = execution(statement, &mut table).unwrap().into_rows();
+
+        let mut repl = Repl::new();
+        repl.mode = Mode::Column;
+        assert_eq!(
+            repl.render(&rows),
+            vec![
+                "id  name  a * 2  upper(b)",
+                "--  ----  -----  --------",
+                "7   x     14     X",
+                "8         16",
+            ]
+        );
+        repl.mode = Mode::Json;
+        assert_eq!(
+            repl.render(&rows),
+            vec![
+                r#"[{"id":7,"name":"x","a * 2":14,"upper(b)":"X"},"#,
+                r#"{"id":8,"name":null,"a * 2":16,"upper(b)":null}]"#,
+            ]
+        );
+
+        // Without a where clause `*` reads every row, keeping column names
+        let rows = execution(
+            prepare_statement("select * from t", &table).unwrap(),
+            &mut table,
+        )
+        .unwrap()
+        .into_rows();
+        assert_eq!(
+            repl.render(&rows),
+            vec![r#"[{"a":7,"b":"x"},"#, r#"{"a":8,"b":null}]"#]
+        );
+    }
+
+    #[test]
+    fn column_mode_widths() {
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let rows = vec![
+            Row::new(
+                0,
+                vec![
+                    ScalarValue::Number(1),
+                    ScalarValue::String("xyz".to_string()),
+                ],
+                &schema,
+            ),
+            Row::new(
+                1,
+                vec![
+                    ScalarValue::Number(22),
+                    ScalarValue::String("w".to_string()),
+                ],
+                &schema,
+            ),
+        ];
+        let mut repl = Repl::new();
+        repl.mode = Mode::Column;
+        assert_eq!(
+            repl.render(&rows),
+            vec!["a   b", "--  ---", "1   xyz", "22  w"]
+        );
+
+        let mut table = Table::new(
+            "t".to_string(),
+            schema.clone(),
+            std::path::Path::new(crate::table::MEMORY_PATH),
+        )
+        .unwrap();
+        let command: Command = ".width 4 2".parse().unwrap();
+        do_meta_commands(command, &mut repl, &mut table).unwrap();
+        assert_eq!(
+            repl.render(&rows),
+            vec!["a     b", "----  --", "1     xy", "22    w"]
+        );
+
+        // 0 leaves the column auto-sized
+        let command: Command = ".width 0 1".parse().unwrap();
+        do_meta_commands(command, &mut repl, &mut table).unwrap();
+        assert_eq!(repl.render(&rows), vec!["a   b", "--  -", "1   x", "22  w"]);
+    }
+
+    #[test]
+    fn caret_under_unexpected_token() {
+        assert_eq!(
+            caret("insert 0 1 x", 11),
+            format!("{}^", " ".repeat(8 + 11))
+        );
+        // Counts characters rather than bytes
+        assert_eq!(
+            caret("insert 0 \"é\" x", 14),
+            format!("{}^", " ".repeat(8 + 13))
+        );
+    }
+
+    #[test]
+    fn statement_over_several_lines() {
+        let mut input = "insert 0\n  1 \"a;\nb\";\n.exit\nread 0;\n".as_bytes();
+        let mut repl = Repl::new();
+        assert_eq!(
+            repl.read_statement(&mut input).unwrap(),
+            "insert 0\n  1 \"a;\nb\";"
+        );
+        assert_eq!(repl.read_statement(&mut input).unwrap(), ".exit");
+        assert_eq!(repl.read_statement(&mut input).unwrap(), "read 0;");
+        assert_eq!(repl.read_statement(&mut input), None);
+
+        // A caret for an earlier line comes with that line
+        assert_eq!(
+            caret("insert 0\n1 x\n;", 11),
+            format!("{0}1 x\n{0}  ^", " ".repeat(8))
+        );
+    }
+
+    #[test]
+    fn output_to_file() {
+        let path = std::env::temp_dir().join("output_to_file.txt");
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new(
+            "t".to_string(),
+            schema.clone(),
+            std::path::Path::new(crate::table::MEMORY_PATH),
+        )
+        .unwrap();
+        let rows = [
+            Row::new(0, vec![ScalarValue::Number(1)], &schema),
+            Row::new(1, vec![ScalarValue::Number(2)], &schema),
+        ];
+
+        let mut repl = Repl::new();
+        let command: Command = format!(".output {}", path.display()).parse().unwrap();
+        do_meta_commands(command, &mut repl, &mut table).unwrap();
+        repl.print_rows(&rows).unwrap();
+        let command: Command = ".pragma row_size".parse().unwrap();
+        do_meta_commands(command, &mut repl, &mut table).unwrap();
+
+        let command: Command = ".output stdout".parse().unwrap();
+        do_meta_commands(command, &mut repl, &mut table).unwrap();
+        repl.print_rows(&rows).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "1\n2\n9\n");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn result_counts() {
+        let path = std::env::temp_dir().join("result_counts.txt");
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let rows = vec![
+            Row::new(0, vec![ScalarValue::Number(1)], &schema),
+            Row::new(1, vec![ScalarValue::Number(2)], &schema),
+        ];
+
+        let mut repl = Repl::new();
+        repl.set_output(Some(&path)).unwrap();
+        repl.print_result(&QueryResult::Rows(rows)).unwrap();
+        repl.print_result(&QueryResult::Affected(1)).unwrap();
+        repl.print_result(&QueryResult::Affected(0)).unwrap();
+        repl.set_output(None).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "1\n2\n2 rows\n1 row affected\n0 rows affected\n"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
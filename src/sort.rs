@@ -0,0 +1,265 @@
+//! Sorting for `order by` on results that may not fit in memory. Rows are
+//! buffered up to a limit; a full buffer is sorted and spilled to a
+//! temporary file as a run, and once every row is in, the runs are merged
+//! back into one ordered stream, reading a row of each at a time.
+
+use std::{
+    cmp::Ordering,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering as AtomicOrdering},
+};
+
+use crate::{
+    datatype::{Collation, ScalarValue},
+    errors::Error,
+};
+
+/// Rows a sort holds in memory before spilling them, unless set otherwise
+/// with `.pragma sort_buffer_rows`.
+pub const SORT_BUFFER_ROWS: usize = 10_000;
+
+/// How one sort value is compared.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SortKey {
+    pub collation: Collation,
+    pub descending: bool,
+}
+
+/// A row waiting to be sorted: the values it is ordered by, then its key
+/// and the values it is output with.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SortEntry {
+    pub sort_values: Vec<ScalarValue>,
+    pub key: i64,
+    pub values: Vec<ScalarValue>,
+}
+
+/// A sort of entries pushed one at a time. Entries that compare equal come
+/// out in the order they went in.
+#[derive(Debug)]
+pub struct ExternalSort {
+    keys: Vec<SortKey>,
+    buffer_rows: usize,
+    buffer: Vec<SortEntry>,
+    runs: Vec<Run>,
+}
+
+impl ExternalSort {
+    /// A sort by `keys`, in order of importance, holding up to
+    /// `buffer_rows` entries in memory. At least one is always held.
+    pub fn new(keys: Vec<SortKey>, buffer_rows: usize) -> Self {
+        Self {
+            keys,
+            buffer_rows: buffer_rows.max(1),
+            buffer: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    /// Adds an entry, spilling the buffer first when it is full.
+    pub fn push(&mut self, entry: SortEntry) -> Result<(), Error> {
+        if self.buffer.len() >= self.buffer_rows {
+            self.spill()?;
+        }
+        self.buffer.push(entry);
+        Ok(())
+    }
+
+    /// How many runs have been spilled to disk so far.
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// The entries pushed, in order. Nothing touches the disk when they all
+    /// fit in the buffer.
+    pub fn finish(mut self) -> Result<SortedEntries, Error> {
+        if self.runs.is_empty() {
+            self.sort_buffer();
+            return Ok(SortedEntries::Memory(self.buffer.into_iter()));
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        let mut heads = Vec::with_capacity(self.runs.len());
+        for run in &mut self.runs {
+            heads.push(run.next()?);
+        }
+        Ok(SortedEntries::Merge(Merge {
+            keys: self.keys,
+            runs: self.runs,
+            heads,
+        }))
+    }
+
+    fn sort_buffer(&mut self) {
+        let keys = &self.keys;
+        // Stable, so equal entries keep the order they were pushed in
+        self.buffer.sort_by(|a, b| compare(keys, a, b));
+    }
+
+    fn spill(&mut self) -> Result<(), Error> {
+        self.sort_buffer();
+        let run = Run::write(&self.buffer)?;
+        self.runs.push(run);
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+fn compare(keys: &[SortKey], a: &SortEntry, b: &SortEntry) -> Ordering {
+    keys.iter()
+        .zip(a.sort_values.iter().zip(&b.sort_values))
+        .map(|(key, (a, b))| {
+            let ordering = key.collation.compare(a, b);
+            match key.descending {
+                true => ordering.reverse(),
+                false => ordering,
+            }
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// The entries of a finished sort, in order.
+#[derive(Debug)]
+pub enum SortedEntries {
+    Memory(std::vec::IntoIter<SortEntry>),
+    Merge(Merge),
+}
+
+impl Iterator for SortedEntries {
+    type Item = Result<SortEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SortedEntries::Memory(entries) => entries.next().map(Ok),
+            SortedEntries::Merge(merge) => merge.next(),
+        }
+    }
+}
+
+/// Merges spilled runs, holding the next entry of each.
+#[derive(Debug)]
+pub struct Merge {
+    keys: Vec<SortKey>,
+    runs: Vec<Run>,
+    heads: Vec<Option<SortEntry>>,
+}
+
+impl Iterator for Merge {
+    type Item = Result<SortEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The first of equal heads wins, as earlier runs hold earlier entries
+        let mut min: Option<usize> = None;
+        for (i, head) in self.heads.iter().enumerate() {
+            let Some(head) = head else { continue };
+            let smaller = match min.and_then(|min| self.heads[min].as_ref()) {
+                Some(current) => compare(&self.keys, head, current).is_lt(),
+                None => true,
+            };
+            if smaller {
+                min = Some(i);
+            }
+        }
+        let i = min?;
+        let next = match self.runs[i].next() {
+            Ok(next) => next,
+            Err(err) => return Some(Err(err)),
+        };
+        std::mem::replace(&mut self.heads[i], next).map(Ok)
+    }
+}
+
+// A sorted run in a temporary file, removed once the run is dropped
+#[derive(Debug)]
+struct Run {
+    path: PathBuf,
+    reader: BufReader<File>,
+    remaining: usize,
+}
+
+impl Run {
+    fn write(entries: &[SortEntry]) -> Result<Self, Error> {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        let n = RUNS.fetch_add(1, AtomicOrdering::Relaxed);
+        let path = std::env::temp_dir().join(format!("sqlite-sort-{}-{n}", std::process::id()));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for entry in entries {
+            bincode::serialize_into(&mut writer, entry)?;
+        }
+        writer.flush()?;
+        Ok(Self {
+            reader: BufReader::new(File::open(&path)?),
+            path,
+            remaining: entries.len(),
+        })
+    }
+
+    fn next(&mut self) -> Result<Option<SortEntry>, Error> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        Ok(Some(bincode::deserialize_from(&mut self.reader)?))
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datatype::{Collation, ScalarValue};
+
+    use super::{ExternalSort, SortEntry, SortKey};
+
+    fn entry(key: i64, value: ScalarValue) -> SortEntry {
+        SortEntry {
+            sort_values: vec![value.clone()],
+            key,
+            values: vec![value],
+        }
+    }
+
+    #[test]
+    fn spilled_runs_merge_in_order() {
+        let values: Vec<ScalarValue> = (0..100)
+            .map(|i| match i % 7 {
+                0 => ScalarValue::Null,
+                1 => ScalarValue::String(format!("s{}", i % 5)),
+                _ => ScalarValue::Number((i * 37) % 11),
+            })
+            .collect();
+        let keys = vec![SortKey {
+            collation: Collation::Binary,
+            descending: true,
+        }];
+
+        let sorted = |buffer_rows: usize| {
+            let mut sort = ExternalSort::new(keys.clone(), buffer_rows);
+            for (i, value) in values.iter().enumerate() {
+                sort.push(entry(i as i64, value.clone())).unwrap();
+            }
+            let runs = sort.runs();
+            let entries: Vec<SortEntry> = sort.finish().unwrap().map(Result::unwrap).collect();
+            (runs, entries)
+        };
+        let (runs, in_memory) = sorted(1000);
+        assert_eq!(runs, 0);
+        let (runs, spilled) = sorted(3);
+        assert_eq!(runs, 33);
+        assert_eq!(spilled, in_memory);
+
+        // Descending, with equal values still in the order they were pushed
+        for pair in in_memory.windows(2) {
+            let ordering = Collation::Binary.compare(&pair[0].values[0], &pair[1].values[0]);
+            assert!(ordering.is_gt() || (ordering.is_eq() && pair[0].key < pair[1].key));
+        }
+    }
+}
@@ -1,102 +1,2054 @@
-use std::ops::Deref;
+use std::{fmt::Display, ops::Deref};
 
 use crate::{
-    datatype::{DataType, ScalarValue, Schema},
+    aggregate::NamedAggregate,
+    custom::CustomType,
+    datatype::{Collation, ColumnOptions, DataType, ScalarValue, Schema},
     errors::Error,
+    expr::{
+        parse_aggregate_list, parse_order_by, parse_predicate, parse_select_list, Expr, OrderBy,
+        Projection,
+    },
     table::Table,
 };
 
+#[derive(Clone)]
 pub struct InsertStatement {
+    pub key: i64,
     pub values: Vec<ScalarValue>,
+    /// Columns to hand back once the row is inserted, from a `returning`
+    /// clause. `*` is expanded to every column.
+    pub returning: Option<Vec<String>>,
 }
 
+#[derive(Clone)]
+pub enum AlterTableStatement {
+    RenameTable(String),
+    RenameColumn {
+        column: String,
+        name: String,
+    },
+    AddColumn {
+        name: String,
+        ty: DataType,
+        options: ColumnOptions,
+    },
+}
+
+#[derive(Clone)]
+pub enum DeleteStatement {
+    Key(i64),
+    /// Every row the predicate matches, or every row when there is none.
+    Where(Option<Expr>),
+}
+
+#[derive(Clone)]
+pub struct SelectStatement {
+    pub projection: Vec<Projection>,
+    /// Aggregates folded over every row in place of the projection, giving
+    /// a single row. Empty for a select of rows.
+    pub aggregates: Vec<NamedAggregate>,
+    /// The rows with these keys, in list order, or every row when `None`.
+    pub keys: Option<Vec<i64>>,
+    /// Only the rows this matches, as `Expr::matches` decides.
+    pub filter: Option<Expr>,
+    /// What the rows are sorted by; they stay in the order they were read
+    /// when it is empty.
+    pub order_by: Vec<OrderBy>,
+}
+
+impl SelectStatement {
+    /// The schema of the rows the select gives, with a column per
+    /// projection named after it. Bare columns keep their options.
+    pub fn schema(&self, table: &Schema) -> Schema {
+        if !self.aggregates.is_empty() {
+            let feilds = self
+                .aggregates
+                .iter()
+                .map(|named| (named.name.clone(), named.aggregate.data_type(table)))
+                .collect();
+            let options = vec![ColumnOptions::default(); self.aggregates.len()];
+            return Schema { feilds, options };
+        }
+        let (feilds, options) = self
+            .projection
+            .iter()
+            .map(|projection| {
+                let options = match projection.expr {
+                    Expr::Column { index, .. } => table.options[index].clone(),
+                    _ => ColumnOptions::default(),
+                };
+                let feild = (projection.name.clone(), projection.expr.data_type(table));
+                (feild, options)
+            })
+            .unzip();
+        Schema { feilds, options }
+    }
+}
+
+#[derive(Clone)]
 pub enum Statement {
     Insert(InsertStatement),
-    Read(usize),
+    Delete(DeleteStatement),
+    Read(i64),
+    /// The rows with these keys, in list order. Keys with no row are skipped.
+    ReadKeys(Vec<i64>),
+    Select(SelectStatement),
+    AlterTable(AlterTableStatement),
+    Truncate,
+    CreateIndex {
+        name: String,
+        columns: Vec<String>,
+    },
+    /// A new table next to this one, see `Table::create_table`.
+    CreateTable {
+        name: String,
+        schema: Schema,
+    },
+    DropTable {
+        name: String,
+        if_exists: bool,
+    },
+}
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Statement::Insert(insert) => {
+                let values: Vec<String> = insert.values.iter().map(literal).collect();
+                write!(f, "insert {} values ({})", insert.key, values.join(", "))?;
+                if let Some(columns) = &insert.returning {
+                    write!(f, " returning {}", columns.join(", "))?;
+                }
+                Ok(())
+            }
+            Statement::Delete(DeleteStatement::Key(key)) => write!(f, "delete {key}"),
+            Statement::Delete(DeleteStatement::Where(predicate)) => {
+                write!(f, "delete from table")?;
+                if let Some(predicate) = predicate {
+                    write!(f, " where {predicate}")?;
+                }
+                Ok(())
+            }
+            Statement::Read(index) => write!(f, "read {index}"),
+            Statement::ReadKeys(keys) => {
+                let keys: Vec<String> = keys.iter().map(i64::to_string).collect();
+                write!(f, "select * from table where key in ({})", keys.join(", "))
+            }
+            Statement::Select(select) => {
+                let projection: Vec<String> = match select.aggregates.is_empty() {
+                    true => select
+                        .projection
+                        .iter()
+                        .map(Projection::to_string)
+                        .collect(),
+                    false => select
+                        .aggregates
+                        .iter()
+                        .map(NamedAggregate::to_string)
+                        .collect(),
+                };
+                write!(f, "select {} from table", projection.join(", "))?;
+                if let Some(keys) = &select.keys {
+                    let keys: Vec<String> = keys.iter().map(i64::to_string).collect();
+                    write!(f, " where key in ({})", keys.join(", "))?;
+                }
+                if let Some(filter) = &select.filter {
+                    write!(f, " where {filter}")?;
+                }
+                if !select.order_by.is_empty() {
+                    let order_by: Vec<String> =
+                        select.order_by.iter().map(OrderBy::to_string).collect();
+                    write!(f, " order by {}", order_by.join(", "))?;
+                }
+                Ok(())
+            }
+            Statement::AlterTable(AlterTableStatement::RenameTable(name)) => {
+                write!(f, "alter table rename to {name}")
+            }
+            Statement::AlterTable(AlterTableStatement::RenameColumn { column, name }) => {
+                write!(f, "alter table rename column {column} to {name}")
+            }
+            Statement::AlterTable(AlterTableStatement::AddColumn { name, ty, options }) => {
+                write!(f, "alter table add column ")?;
+                write_column_definition(f, name, ty, options)
+            }
+            Statement::Truncate => write!(f, "truncate table"),
+            Statement::CreateIndex { name, columns } => {
+                write!(f, "create index {name} on table({})", columns.join(", "))
+            }
+            Statement::CreateTable { name, schema } => {
+                write!(f, "create table {name} (")?;
+                let columns = schema.feilds.iter().zip(&schema.options);
+                for (i, ((column, ty), options)) in columns.enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write_column_definition(f, column, ty, options)?;
+                }
+                write!(f, ")")
+            }
+            Statement::DropTable { name, if_exists } => {
+                write!(f, "drop table ")?;
+                if *if_exists {
+                    write!(f, "if exists ")?;
+                }
+                write!(f, "{name}")
+            }
+        }
+    }
+}
+
+// Writes a column definition as `column_definition` parses it
+fn write_column_definition(
+    f: &mut std::fmt::Formatter<'_>,
+    name: &str,
+    ty: &DataType,
+    options: &ColumnOptions,
+) -> std::fmt::Result {
+    write!(f, "{name} {ty}")?;
+    if options.compact {
+        write!(f, " compact")?;
+    }
+    if options.dictionary {
+        write!(f, " dict")?;
+    }
+    if options.not_null {
+        write!(f, " not null")?;
+    }
+    if options.unique {
+        write!(f, " unique")?;
+    }
+    if options.collation == Collation::NoCase {
+        write!(f, " collate nocase")?;
+    }
+    if let Some(default) = &options.default {
+        write!(f, " default {}", literal(default))?;
+    }
+    Ok(())
+}
+
+/// Formats a value the way it would be written in a statement.
+pub(crate) fn literal(value: &ScalarValue) -> String {
+    match value {
+        ScalarValue::String(x) => format!("\"{}\"", x.replace('\\', "\\\\").replace('"', "\\\"")),
+        _ => value.to_string(),
+    }
+}
+
+impl Statement {
+    /// Whether the statement can run without changing the table.
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Statement::Read(_) | Statement::ReadKeys(_) | Statement::Select(_)
+        )
+    }
+
+    // Parses `<key> <value>...` and `into <t> ...`. Either way the key comes
+    // before the column values, and `returning <columns>` may follow them.
+    fn insert_statement(line: &str, args: &str, table: &Table) -> Result<Self, Error> {
+        let schema = table.schema();
+        let (args, returning) = split_returning(args);
+        let returning = returning
+            .map(|columns| Self::returning_columns(columns, schema))
+            .transpose()?;
+        let (key, values) = match args.strip_prefix("into ") {
+            Some(args) => Self::insert_into_values(line, args, table)?,
+            None => {
+                let mut values = value_tokens(line, args)?;
+                if values.is_empty() {
+                    return Err(Error::ArityMismatch {
+                        expected: schema.feilds.len() + 1,
+                        got: 0,
+                    });
+                }
+                let key = values.remove(0);
+                (key, Self::positional_values(values, schema)?)
+            }
+        };
+
+        let key = table.key_type().encode(&key)?;
+        let mut values = schema.fill_defaults(values)?;
+        if table.coerce {
+            values = schema.coerce_row(values);
+        }
+        schema.validate_row(&values)?;
+
+        Ok(Statement::Insert(InsertStatement {
+            key,
+            values,
+            returning,
+        }))
+    }
+
+    // `*` or a comma separated list of column names
+    fn returning_columns(columns: &str, schema: &Schema) -> Result<Vec<String>, Error> {
+        if columns == "*" {
+            return Ok(schema.feilds.iter().map(|(name, _)| name.clone()).collect());
+        }
+        columns
+            .split(',')
+            .map(|column| {
+                let column = identifier(column.trim());
+                match schema.index_of(column) {
+                    Some(_) => Ok(column.to_string()),
+                    None if column.is_empty() => Err(Error::ParseError),
+                    None => Err(Error::UnknownColumn(column.to_string())),
+                }
+            })
+            .collect()
+    }
+
+    // Trailing columns may be left out and take their default
+    fn positional_values(
+        values: Vec<ScalarValue>,
+        schema: &Schema,
+    ) -> Result<Vec<Option<ScalarValue>>, Error> {
+        if schema.feilds.len() < values.len() {
+            return Err(Error::ArityMismatch {
+                expected: schema.feilds.len(),
+                got: values.len(),
+            });
+        }
+
+        let mut values: Vec<Option<ScalarValue>> = values.into_iter().map(Some).collect();
+        values.resize(schema.feilds.len(), None);
+        Ok(values)
+    }
+
+    // Parses `<table> [(key, <column>, ...)] values (<key>, <value>, ...)`,
+    // placing every value at the index of the column it was given for. The
+    // key has to be given, but may appear anywhere in the column list.
+    fn insert_into_values(
+        line: &str,
+        args: &str,
+        table: &Table,
+    ) -> Result<(ScalarValue, Vec<Option<ScalarValue>>), Error> {
+        let schema = table.schema();
+        let args = args.trim_start();
+        let name_end = args
+            .find(|c: char| c.is_whitespace() || c == '(')
+            .unwrap_or(args.len());
+        let (name, args) = args.split_at(name_end);
+        Self::check_table_name(name, table)?;
+
+        let args = args.trim_start();
+        let (columns, args) = match args.strip_prefix('(') {
+            Some(args) => {
+                let (columns, args) = args.split_once(')').ok_or(Error::ParseError)?;
+                let columns: Vec<&str> = columns.split(',').map(str::trim).collect();
+                (Some(columns), args.trim_start())
+            }
+            None => (None, args),
+        };
+
+        let values = args
+            .strip_prefix("values")
+            .map(str::trim)
+            .and_then(|values| values.strip_prefix('('))
+            .and_then(|values| values.strip_suffix(')'))
+            .ok_or(Error::ParseError)?;
+        let mut values = value_tokens(line, values)?;
+
+        let Some(columns) = columns else {
+            if values.is_empty() {
+                return Err(Error::ParseError);
+            }
+            let key = values.remove(0);
+            return Ok((key, Self::positional_values(values, schema)?));
+        };
+
+        if columns.len() != values.len() {
+            return Err(Error::ArityMismatch {
+                expected: columns.len(),
+                got: values.len(),
+            });
+        }
+
+        let mut key = None;
+        let mut row = vec![None; schema.feilds.len()];
+        for (column, value) in columns.into_iter().zip(values) {
+            // A quoted "key" names a column rather than the row key
+            if column == "key" {
+                if key.replace(value).is_some() {
+                    return Err(Error::ParseError);
+                }
+                continue;
+            }
+            let column = identifier(column);
+            let index = schema
+                .index_of(column)
+                .ok_or_else(|| Error::UnknownColumn(column.to_string()))?;
+            if row[index].replace(value).is_some() {
+                return Err(Error::ParseError);
+            }
+        }
+        Ok((key.ok_or(Error::ParseError)?, row))
+    }
 }
 
 impl Statement {
-    fn insert_statement(values: &str, schema: &Schema) -> Result<Self, Error> {
-        let values = value_tokens(values)?;
+    // Parses `<key>` and `from <t> [where <predicate>]`
+    fn delete_statement(line: &str, args: &str, table: &Table) -> Result<Self, Error> {
+        let from = args
+            .strip_prefix("from")
+            .filter(|args| args.starts_with(char::is_whitespace));
+        let Some(args) = from else {
+            let key = Self::key(line, args, table)?;
+            return Ok(Statement::Delete(DeleteStatement::Key(key)));
+        };
+
+        let args = args.trim_start();
+        let name_end = args.find(char::is_whitespace).unwrap_or(args.len());
+        let (name, args) = args.split_at(name_end);
+        Self::check_table_name(name, table)?;
+
+        let args = args.trim_start();
+        if args.is_empty() {
+            return Ok(Statement::Delete(DeleteStatement::Where(None)));
+        }
+        let predicate = args
+            .strip_prefix("where")
+            .filter(|predicate| predicate.starts_with(char::is_whitespace))
+            .ok_or_else(|| Error::UnexpectedToken {
+                token: args.split_whitespace().next().unwrap_or(args).to_string(),
+                position: args.as_ptr() as usize - line.as_ptr() as usize,
+            })?;
+        let predicate = Expr::parse(predicate, table.schema())
+            .map_err(|err| Self::within(line, predicate, err))?;
+        Ok(Statement::Delete(DeleteStatement::Where(Some(predicate))))
+    }
+
+    // Places an expression error, positioned within `s`, within `line`
+    fn within(line: &str, s: &str, err: Error) -> Error {
+        match err {
+            Error::UnexpectedToken { token, position } => Error::UnexpectedToken {
+                token,
+                position: position + s.as_ptr() as usize - line.as_ptr() as usize,
+            },
+            err => err,
+        }
+    }
+
+    // Parses `<projection> from <t> [where key in (<key>, ...) | where <expr>]
+    // [order by <expr> [asc | desc], ...]`, where the projection is `*` or
+    // expressions each optionally named with `as`. An unordered `*` over a
+    // key list reads the rows as they are stored. A projection of aggregate
+    // calls is `<aggregates> from <t>`, folded over every row.
+    fn select_statement(line: &str, args: &str, table: &Table) -> Result<Self, Error> {
+        let aggregates = parse_aggregate_list(args, table.schema())
+            .map_err(|err| Self::within(line, args, err))?;
+        if let Some((aggregates, rest)) = aggregates {
+            let rest = expect(line, rest, "from")?;
+            let name = rest.split_whitespace().next().ok_or(Error::ParseError)?;
+            Self::check_table_name(name, table)?;
+            let rest = rest[name.len()..].trim_start();
+            if let Some(token) = rest.split_whitespace().next() {
+                return Err(Self::within(
+                    line,
+                    rest,
+                    Error::UnexpectedToken {
+                        token: token.to_string(),
+                        position: 0,
+                    },
+                ));
+            }
+            return Ok(Statement::Select(SelectStatement {
+                projection: Vec::new(),
+                aggregates,
+                keys: None,
+                filter: None,
+                order_by: Vec::new(),
+            }));
+        }
+
+        let (projection, rest) = match args.strip_prefix('*') {
+            Some(rest) => (None, rest.trim_start()),
+            None => {
+                let (projection, rest) = parse_select_list(args, table.schema())
+                    .map_err(|err| Self::within(line, args, err))?;
+                (Some(projection), rest)
+            }
+        };
+        let rest = expect(line, rest, "from")?;
+        let name = rest.split_whitespace().next().ok_or(Error::ParseError)?;
+        Self::check_table_name(name, table)?;
+        let rest = rest[name.len()..].trim_start();
+        let (keys, filter, rest) = match rest.split_whitespace().next() {
+            Some("where") if Self::is_key_list(rest) => {
+                let (keys, rest) = Self::key_list(line, rest, table)?;
+                (Some(keys), None, rest)
+            }
+            Some("where") => {
+                let predicate = expect(line, rest, "where")?;
+                let (filter, rest) = parse_predicate(predicate, table.schema())
+                    .map_err(|err| Self::within(line, predicate, err))?;
+                (None, Some(filter), rest)
+            }
+            _ => (None, None, rest),
+        };
+        let order_by = match rest.is_empty() {
+            true => Vec::new(),
+            false => {
+                let rest = expect(line, rest, "order")?;
+                let rest = expect(line, rest, "by")?;
+                parse_order_by(rest, table.schema()).map_err(|err| Self::within(line, rest, err))?
+            }
+        };
+        Ok(match (projection, keys) {
+            (None, Some(keys)) if order_by.is_empty() => Statement::ReadKeys(keys),
+            (projection, keys) => {
+                let projection = projection.unwrap_or_else(|| {
+                    let feilds = &table.schema().feilds;
+                    let columns = feilds.iter().enumerate().map(|(index, (name, _))| {
+                        let expr = Expr::Column {
+                            index,
+                            name: name.clone(),
+                        };
+                        Projection {
+                            expr,
+                            name: name.clone(),
+                        }
+                    });
+                    columns.collect()
+                });
+                Statement::Select(SelectStatement {
+                    projection,
+                    aggregates: Vec::new(),
+                    keys,
+                    filter,
+                    order_by,
+                })
+            }
+        })
+    }
+
+    // Whether `s` starts `where key in`, rather than a `where` expression
+    fn is_key_list(s: &str) -> bool {
+        let mut tokens = s.split_whitespace();
+        tokens.next();
+        tokens.next() == Some("key") && tokens.next().is_some_and(|t| t.starts_with("in"))
+    }
+
+    // Parses `where key in (<key>, ...)`, returning the keys and what
+    // follows the list
+    fn key_list<'a>(line: &str, s: &'a str, table: &Table) -> Result<(Vec<i64>, &'a str), Error> {
+        let rest = expect(line, s, "where")?;
+        let rest = expect(line, rest, "key")?;
+        let list = rest
+            .strip_prefix("in")
+            .map(str::trim_start)
+            .and_then(|list| list.strip_prefix('('))
+            .ok_or(Error::ParseError)?;
+        let end = closing_paren(list).ok_or(Error::ParseError)?;
+
+        let values = value_tokens(line, list[..end].trim())?;
+        if values.is_empty() {
+            return Err(Error::ParseError);
+        }
+        let keys = values
+            .iter()
+            .map(|value| table.key_type().encode(value))
+            .collect::<Result<_, _>>()?;
+        Ok((keys, list[end + 1..].trim_start()))
+    }
+
+    // Parses a lone row key: a number, or a string for a table with text keys
+    fn key(line: &str, args: &str, table: &Table) -> Result<i64, Error> {
+        match &value_tokens(line, args.trim())?[..] {
+            [key] => table.key_type().encode(key),
+            _ => Err(Error::ParseError),
+        }
+    }
+
+    // Parses `table <t> rename to <name>`,
+    // `table <t> rename column <column> to <name>` and
+    // `table <t> add column <column> <type> [compact | dict] [default <value>]`
+    fn alter_table_statement(line: &str, args: &str, table: &Table) -> Result<Self, Error> {
+        let tokens: Vec<&str> = args.split_whitespace().collect();
+        let statement = match tokens[..] {
+            ["table", name, "add", "column", _, ..] => {
+                Self::check_table_name(name, table)?;
+                let mut rest = args;
+                for keyword in ["table", name, "add", "column"] {
+                    rest = expect(line, rest, keyword)?;
+                }
+                let (name, ty, options) = column_definition(line, rest)?;
+                AlterTableStatement::AddColumn { name, ty, options }
+            }
+            ["table", name, "rename", "to", new_name] => {
+                Self::check_table_name(name, table)?;
+                AlterTableStatement::RenameTable(identifier(new_name).to_string())
+            }
+            ["table", name, "rename", "column", column, "to", new_name] => {
+                Self::check_table_name(name, table)?;
+                AlterTableStatement::RenameColumn {
+                    column: identifier(column).to_string(),
+                    name: identifier(new_name).to_string(),
+                }
+            }
+            _ => return Err(Error::ParseError),
+        };
+        Ok(Statement::AlterTable(statement))
+    }
+
+    // Parses `table <t>` and `table if exists <t>`
+    fn drop_table_statement(args: &str, table: &Table) -> Result<Self, Error> {
+        let tokens: Vec<&str> = args.split_whitespace().collect();
+        let statement = match tokens[..] {
+            ["table", "if", "exists", name] => Statement::DropTable {
+                name: identifier(name).to_string(),
+                if_exists: true,
+            },
+            ["table", name] => {
+                Self::check_table_name(name, table)?;
+                Statement::DropTable {
+                    name: identifier(name).to_string(),
+                    if_exists: false,
+                }
+            }
+            _ => return Err(Error::ParseError),
+        };
+        Ok(statement)
+    }
 
-        if schema.feilds.len() != values.len() {
+    // Parses `table <name> (<column definition>, ...)`, see
+    // `column_definition`
+    fn create_table_statement(line: &str, args: &str) -> Result<Self, Error> {
+        let rest = expect(line, args, "table")?;
+        let (name, columns) = rest.split_once('(').ok_or(Error::ParseError)?;
+        let name = identifier(name.trim());
+        let columns = columns
+            .trim_end()
+            .strip_suffix(')')
+            .ok_or(Error::ParseError)?;
+        if name.is_empty() {
             return Err(Error::ParseError);
         }
 
-        for ((_, ty), value) in schema.feilds.iter().zip(values.iter()) {
-            match (ty, value) {
-                (DataType::String(_), ScalarValue::String(_)) => {}
-                (DataType::Number, ScalarValue::Number(_)) => {}
-                _ => return Err(Error::ParseError),
-            };
+        let mut schema = Schema::new(Vec::new());
+        for definition in split_top_level(columns) {
+            let (column, ty, options) = column_definition(line, definition.trim())?;
+            if schema.index_of(&column).is_some() {
+                return Err(Error::AlreadyExists(format!("column {column}")));
+            }
+            schema.feilds.push((column, ty));
+            schema.options.push(options);
         }
+        Ok(Statement::CreateTable {
+            name: name.to_string(),
+            schema,
+        })
+    }
+
+    // Parses `index <name> on <t>(<column>, ...)`
+    fn create_index_statement(args: &str, table: &Table) -> Result<Self, Error> {
+        let tokens: Vec<&str> = args.splitn(4, char::is_whitespace).collect();
+        let ["index", name, "on", rest] = tokens[..] else {
+            return Err(Error::ParseError);
+        };
+        let (table_name, columns) = rest
+            .strip_suffix(')')
+            .and_then(|rest| rest.split_once('('))
+            .ok_or(Error::ParseError)?;
+        Self::check_table_name(table_name.trim(), table)?;
+        let columns = columns
+            .split(',')
+            .map(|column| {
+                let column = identifier(column.trim());
+                match table.schema().index_of(column) {
+                    Some(_) => Ok(column.to_string()),
+                    None => Err(Error::UnknownColumn(column.to_string())),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Statement::CreateIndex {
+            name: identifier(name).to_string(),
+            columns,
+        })
+    }
 
-        Ok(Statement::Insert(InsertStatement { values }))
+    fn check_table_name(name: &str, table: &Table) -> Result<(), Error> {
+        let name = identifier(name);
+        if name != table.header.name {
+            return Err(Error::NotFound(format!("table {name}")));
+        }
+        Ok(())
     }
 }
 
 pub fn prepare_statement(s: &str, table: impl Deref<Target = Table>) -> Result<Statement, Error> {
-    let (command, args) = s.split_once(' ').ok_or(Error::ParseError)?;
+    let line = strip_comments(s);
+    let statement = line.trim();
+    let statement = statement.strip_suffix(';').unwrap_or(statement).trim_end();
+    let (command, args) = statement
+        .split_once(char::is_whitespace)
+        .ok_or(Error::ParseError)?;
+    let args = args.trim_start();
     let statement = match command {
-        "insert" => Statement::insert_statement(args, table.schema())?,
-        "read" => Statement::Read(args.parse().unwrap()),
+        "insert" => Statement::insert_statement(&line, args, &table)?,
+        "delete" => Statement::delete_statement(&line, args, &table)?,
+        "read" => Statement::Read(Statement::key(&line, args, &table)?),
+        "select" => Statement::select_statement(&line, args, &table)?,
+        "alter" => Statement::alter_table_statement(&line, args, &table)?,
+        "drop" => Statement::drop_table_statement(args, &table)?,
+        "create" => match args.split_whitespace().next() {
+            Some("table") => Statement::create_table_statement(&line, args)?,
+            _ => Statement::create_index_statement(args, &table)?,
+        },
+        "truncate" => {
+            Statement::check_table_name(args.trim(), &table)?;
+            Statement::Truncate
+        }
         _ => return Err(Error::UnrecognizedCommand),
     };
     Ok(statement)
 }
 
-fn value_tokens(mut s: &str) -> Result<Vec<ScalarValue>, Error> {
-    let mut res = vec![];
+// Takes `keyword` off the front of `s`, returning what follows
+fn expect<'a>(line: &str, s: &'a str, keyword: &str) -> Result<&'a str, Error> {
+    let token = s.split_whitespace().next().unwrap_or(s);
+    if token != keyword {
+        return Err(Error::UnexpectedToken {
+            token: token.to_string(),
+            position: s.as_ptr() as usize - line.as_ptr() as usize,
+        });
+    }
+    Ok(s[token.len()..].trim_start())
+}
 
-    fn number(s: &str) -> Option<(i64, &str)> {
-        let (index, _) = s
-            .char_indices()
-            .take_while(|(_, x)| x.is_digit(10))
-            .last()?;
-        let (token, remainder) = s.split_at(index + 1);
-        let x: i64 = token.parse::<i64>().ok()?;
-        Some((x, remainder))
+/// The offset of the first `)` in `s` outside a string literal.
+fn closing_paren(s: &str) -> Option<usize> {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, char) in s.char_indices() {
+        if in_string {
+            match char {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if char == '"' {
+            in_string = true;
+        } else if char == ')' {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Splits off a trailing `returning` clause, skipping the word inside string
+/// literals. Returns the arguments before it and the column list after it.
+fn split_returning(args: &str) -> (&str, Option<&str>) {
+    const KEYWORD: &str = "returning";
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut prev = ' ';
+    for (i, char) in args.char_indices() {
+        if in_string {
+            match char {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if char == '"' {
+            in_string = true;
+        } else if prev.is_whitespace() && args[i..].starts_with(KEYWORD) {
+            let rest = &args[i + KEYWORD.len()..];
+            if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+                return (args[..i].trim_end(), Some(rest.trim()));
+            }
+        }
+        prev = char;
+    }
+    (args, None)
+}
+
+/// Strips the double quotes or backticks around a quoted identifier, so a
+/// table or column can be named like a keyword. Quoted names can't hold
+/// whitespace.
+fn identifier(s: &str) -> &str {
+    ['"', '`']
+        .into_iter()
+        .find_map(|quote| s.strip_prefix(quote)?.strip_suffix(quote))
+        .filter(|name| !name.is_empty())
+        .unwrap_or(s)
+}
+
+/// Parses a column definition: a name, bare or quoted with `"` or `` ` ``,
+/// its type, then any of `not null`, `unique`, `default <value>`,
+/// `collate binary`, `collate nocase`, `compact` and `dict`, in any order.
+/// `s` has to be a slice of `line`.
+fn column_definition(line: &str, s: &str) -> Result<(String, DataType, ColumnOptions), Error> {
+    let (name, rest) = column_name(s)?;
+    let ty = rest.split_whitespace().next().ok_or(Error::ParseError)?;
+    let mut rest = rest[ty.len()..].trim_start();
+    let ty = data_type(ty)?;
+
+    let unexpected = |s: &str| Error::UnexpectedToken {
+        token: s.split_whitespace().next().unwrap_or(s).to_string(),
+        position: s.as_ptr() as usize - line.as_ptr() as usize,
+    };
+    let mut options = ColumnOptions::default();
+    while let Some(token) = rest.split_whitespace().next() {
+        let after = rest[token.len()..].trim_start();
+        rest = match token {
+            "not" => {
+                options.not_null = true;
+                expect(line, after, "null")?
+            }
+            "unique" => {
+                options.unique = true;
+                after
+            }
+            "compact" => {
+                options.compact = true;
+                after
+            }
+            "dict" => {
+                options.dictionary = true;
+                after
+            }
+            "collate" => {
+                let collation = after.split_whitespace().next().unwrap_or_default();
+                options.collation = match collation {
+                    "binary" => Collation::Binary,
+                    "nocase" => Collation::NoCase,
+                    _ => return Err(unexpected(after)),
+                };
+                after[collation.len()..].trim_start()
+            }
+            "default" => {
+                let end = match string_literal(after) {
+                    Some((_, rest)) => after.len() - rest.len(),
+                    None => after.find(char::is_whitespace).unwrap_or(after.len()),
+                };
+                match &value_tokens(line, &after[..end])?[..] {
+                    [value] => options.default = Some(value.clone()),
+                    _ => return Err(Error::ParseError),
+                }
+                after[end..].trim_start()
+            }
+            _ => return Err(unexpected(rest)),
+        };
+    }
+    Ok((name.to_string(), ty, options))
+}
+
+// Splits `s` at the commas outside of quotes and parentheses
+fn split_top_level(s: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut depth, mut quote, mut escaped) = (0, 0, None, false);
+    for (i, char) in s.char_indices() {
+        match (quote, char) {
+            (Some('"'), _) if escaped => escaped = false,
+            (Some('"'), '\\') => escaped = true,
+            (Some(q), _) if char == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '`') => quote = Some(char),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+// Reads a column name off the front of `s`, returning it and the rest of `s`.
+// A quoted name may hold spaces.
+fn column_name(s: &str) -> Result<(&str, &str), Error> {
+    for quote in ['"', '`'] {
+        if let Some(quoted) = s.strip_prefix(quote) {
+            let end = quoted
+                .find(quote)
+                .filter(|&end| end > 0)
+                .ok_or(Error::ParseError)?;
+            return Ok((&quoted[..end], quoted[end + 1..].trim_start()));
+        }
+    }
+    let end = s.find(char::is_whitespace).unwrap_or(s.len());
+    Ok((&s[..end], s[end..].trim_start()))
+}
+
+/// Parses a column type: `number`, `text(<size>)`, `char(<size>)` or the
+/// name of a registered custom type.
+fn data_type(s: &str) -> Result<DataType, Error> {
+    if s == "number" {
+        return Ok(DataType::Number);
+    }
+    if let Some(ty) = CustomType::lookup(s) {
+        return Ok(DataType::Custom(ty));
     }
 
-    fn string(s: &str) -> Option<(String, &str)> {
-        if s.len() < 2 && &s[0..1] != "\"" {
-            return None;
+    let (ty, size) = s
+        .strip_suffix(')')
+        .and_then(|s| s.split_once('('))
+        .ok_or(Error::ParseError)?;
+    let size = size.parse().map_err(|_| Error::ParseError)?;
+    match ty {
+        "text" => Ok(DataType::String(size)),
+        "char" => Ok(DataType::Char(size)),
+        _ => Err(Error::ParseError),
+    }
+}
+
+/// Whether `s` holds a whole statement, ending in `;` outside of any string
+/// literal or comment.
+pub fn is_complete(s: &str) -> bool {
+    let s = strip_comments(s);
+    let mut in_string = false;
+    let mut chars = s.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            '\\' if in_string => {
+                chars.next();
+            }
+            '"' => in_string = !in_string,
+            _ => {}
         }
+    }
+    !in_string && s.trim_end().ends_with(';')
+}
 
-        let mut iter = s.char_indices().skip(1);
-        let mut index: Option<usize> = None;
+/// Blanks out `--` line comments and `/* */` block comments, leaving anything
+/// inside string literals untouched. Comments are replaced by as many spaces
+/// as they took up, so byte offsets into the result match the input.
+pub fn strip_comments(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    let mut in_string = false;
 
-        while let Some((i, char)) = iter.next() {
+    while let Some(char) = chars.next() {
+        if in_string {
+            res.push(char);
             if char == '\\' {
-                let _ = iter.next();
-                continue;
+                if let Some(escaped) = chars.next() {
+                    res.push(escaped);
+                }
+            } else if char == '"' {
+                in_string = false;
             }
+            continue;
+        }
 
-            if char == '"' {
-                index = Some(i);
-                break;
+        match (char, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                res.push(char);
             }
+            ('-', Some('-')) => {
+                blank(&mut res, char);
+                for char in chars.by_ref() {
+                    if char == '\n' {
+                        res.push('\n');
+                        break;
+                    }
+                    blank(&mut res, char);
+                }
+            }
+            ('/', Some('*')) => {
+                blank(&mut res, char);
+                chars.next();
+                blank(&mut res, '*');
+                let mut prev = None;
+                for char in chars.by_ref() {
+                    blank(&mut res, char);
+                    if prev == Some('*') && char == '/' {
+                        break;
+                    }
+                    prev = Some(char);
+                }
+            }
+            _ => res.push(char),
         }
+    }
+
+    res.truncate(res.trim_end().len());
+    res
+}
 
-        let index = index?;
-        let (token, remainder) = s.split_at(index + 1);
-        let token = &token[1..token.len() - 1];
-        let token = token.replace("\\\\", "\\");
-        let token = token.replace("\\\"", "\"");
-        Some((token, remainder))
+fn blank(res: &mut String, char: char) {
+    res.extend(std::iter::repeat_n(' ', char.len_utf8()));
+}
+
+/// Reads a double quoted string literal off the front of `s`, returning its
+/// unescaped value and the rest of `s`.
+pub(crate) fn string_literal(s: &str) -> Option<(String, &str)> {
+    if !s.starts_with('"') {
+        return None;
+    }
+
+    let mut iter = s.char_indices().skip(1);
+    let mut index: Option<usize> = None;
+
+    while let Some((i, char)) = iter.next() {
+        if char == '\\' {
+            let _ = iter.next();
+            continue;
+        }
+
+        if char == '"' {
+            index = Some(i);
+            break;
+        }
+    }
+
+    let index = index?;
+    let (token, remainder) = s.split_at(index + 1);
+    let token = unescape(&token[1..token.len() - 1]);
+    Some((token, remainder))
+}
+
+// Decodes \\, \", \n, \t and \r. Any other escape is kept as written,
+// backslash included, so "\q" stays a two character string.
+fn unescape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            res.push(char);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => res.push('\n'),
+            Some('t') => res.push('\t'),
+            Some('r') => res.push('\r'),
+            Some(x @ ('\\' | '"')) => res.push(x),
+            Some(x) => {
+                res.push('\\');
+                res.push(x);
+            }
+            None => res.push('\\'),
+        }
+    }
+    res
+}
+
+/// Parses a list of values out of `input`, which has to be a slice of `line`.
+/// An unexpected token is reported at its byte offset into `line`.
+fn value_tokens(line: &str, input: &str) -> Result<Vec<ScalarValue>, Error> {
+    let mut s = input;
+    let mut res = vec![];
+
+    fn number(s: &str) -> Option<Result<(i64, &str), Error>> {
+        let sign = usize::from(s.starts_with('-'));
+        let (index, _) = s[sign..]
+            .char_indices()
+            .take_while(|(_, x)| x.is_ascii_digit())
+            .last()?;
+        let (token, remainder) = s.split_at(sign + index + 1);
+        // The token is digits after an optional minus sign, so it can only
+        // fail to parse by overflowing
+        let x = token
+            .parse::<i64>()
+            .map_err(|_| Error::NumberOutOfRange(token.to_string()));
+        Some(x.map(|x| (x, remainder)))
     }
 
-    while s.len() != 0 {
-        if let Some((value, rem)) = number(s)
-            .map(|(x, rem)| (ScalarValue::Number(x), rem))
-            .or_else(|| string(s).map(|(x, rem)| (ScalarValue::String(x), rem)))
-        {
+    fn null(s: &str) -> Option<&str> {
+        s.strip_prefix("null")
+            .filter(|rem| rem.is_empty() || rem.starts_with(char::is_whitespace))
+    }
+
+    while !s.is_empty() {
+        let token = match number(s) {
+            Some(number) => number.map(|(x, rem)| Some((ScalarValue::Number(x), rem)))?,
+            None => null(s)
+                .map(|rem| (ScalarValue::Null, rem))
+                .or_else(|| string_literal(s).map(|(x, rem)| (ScalarValue::String(x), rem))),
+        };
+        if let Some((value, rem)) = token {
             res.push(value);
             s = rem.trim();
+            s = s.strip_prefix(',').unwrap_or(s).trim_start();
         } else {
-            return Err(Error::ParseError);
+            return Err(Error::UnexpectedToken {
+                token: s.split_whitespace().next().unwrap_or(s).to_string(),
+                position: s.as_ptr() as usize - line.as_ptr() as usize,
+            });
         }
     }
 
     Ok(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        datatype::{Collation, DataType, KeyType, ScalarValue, Schema},
+        errors::Error,
+        execution::{execution, query, QueryResult},
+        expr::Expr,
+        table::{Table, TableOptions},
+    };
+
+    use super::{is_complete, prepare_statement, strip_comments, Statement};
+
+    fn table(name: &str) -> Table {
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        table_with_schema(name, schema)
+    }
+
+    fn table_with_schema(name: &str, schema: Schema) -> Table {
+        let path = std::env::temp_dir().join(format!("{name}.db"));
+        let _ = fs::remove_file(&path);
+        let table = Table::new(name.to_string(), schema, &path).unwrap();
+        fs::remove_file(path).unwrap();
+        table
+    }
+
+    fn insert_values(statement: Statement) -> Vec<ScalarValue> {
+        match statement {
+            Statement::Insert(insert) => insert.values,
+            _ => panic!("expected an insert statement"),
+        }
+    }
+
+    #[test]
+    fn strips_comments() {
+        assert_eq!(strip_comments("read 1 -- first row"), "read 1");
+        assert_eq!(
+            strip_comments("read /* the first row */ 1"),
+            format!("read {} 1", " ".repeat(19))
+        );
+        assert_eq!(strip_comments("-- only a comment"), "");
+        assert_eq!(strip_comments("/**/ read 1"), "     read 1");
+        assert_eq!(strip_comments("read 1 /* unterminated"), "read 1");
+    }
+
+    #[test]
+    fn statement_completion() {
+        assert!(is_complete("read 1;"));
+        assert!(is_complete("insert 0\n1 \"x\"; -- done"));
+        assert!(!is_complete("insert 0 1"));
+        assert!(!is_complete("insert 0 1 \"a;"));
+        assert!(!is_complete("insert 0 1 \"a\\\";"));
+        assert!(!is_complete("read 1 -- ;"));
+        assert!(is_complete("insert 0 1 \"a;\nb\";"));
+
+        let table = table("statement_completion");
+        let statement = prepare_statement("insert\n0 null\n\"a;\nb\";", &table).unwrap();
+        assert_eq!(
+            insert_values(statement),
+            vec![ScalarValue::Null, ScalarValue::String("a;\nb".to_string())]
+        );
+    }
+
+    #[test]
+    fn comment_after_insert_is_ignored() {
+        let table = table("comment_after_insert");
+        let statement = prepare_statement("insert 0 1 \"x\" -- trailing", &table).unwrap();
+        assert_eq!(
+            insert_values(statement),
+            vec![ScalarValue::Number(1), ScalarValue::String("x".to_string())]
+        );
+    }
+
+    #[test]
+    fn comment_markers_in_strings_are_kept() {
+        let table = table("comment_in_string");
+        let statement = prepare_statement("insert 0 1 \"a--/*b\"", &table).unwrap();
+        assert_eq!(
+            insert_values(statement),
+            vec![
+                ScalarValue::Number(1),
+                ScalarValue::String("a--/*b".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn string_escapes() {
+        let mut table = table("string_escapes");
+        let statement = prepare_statement("insert 0 1 \"a\\nb\\t\\q\"", &table).unwrap();
+        execution(statement, &mut table).unwrap();
+        let row = table.read(0).unwrap();
+        assert_eq!(row["b"], ScalarValue::String("a\nb\t\\q".to_string()));
+    }
+
+    #[test]
+    fn insert_key() {
+        let schema = Schema::new(vec![("b".to_string(), DataType::String(10))]);
+        let mut table = table_with_schema("insert_key", schema);
+        let statement = prepare_statement("insert 7 \"x\"", &table).unwrap();
+        execution(statement, &mut table).unwrap();
+        let statement =
+            prepare_statement("insert into insert_key values (3, \"y\")", &table).unwrap();
+        execution(statement, &mut table).unwrap();
+
+        let row = table.read(7).unwrap();
+        assert_eq!(row.key(), 7);
+        assert_eq!(row["b"], ScalarValue::String("x".to_string()));
+        assert_eq!(table.read(3).unwrap().key(), 3);
+        assert!(matches!(table.read(5), Err(Error::NotFound(_))));
+
+        // The key is required and has to be a number
+        assert!(matches!(
+            prepare_statement("insert \"x\"", &table),
+            Err(Error::TypeMismatch { .. })
+        ));
+        assert!(matches!(
+            prepare_statement("insert into insert_key (b) values (\"x\")", &table),
+            Err(Error::ParseError)
+        ));
+        let statement = prepare_statement("insert 7 \"z\"", &table).unwrap();
+        assert!(matches!(
+            execution(statement, &mut table),
+            Err(Error::ConstraintViolation(_))
+        ));
+    }
+
+    #[test]
+    fn drop_table() {
+        let path = std::env::temp_dir().join("drop_table.db");
+        let _ = fs::remove_file(&path);
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("drop_table".to_string(), schema, &path).unwrap();
+
+        assert!(matches!(
+            prepare_statement("drop table missing", &table),
+            Err(Error::NotFound(_))
+        ));
+        let statement = prepare_statement("drop table if exists missing", &table).unwrap();
+        execution(statement, &mut table).unwrap();
+        assert!(path.exists());
+
+        let statement = prepare_statement("drop table drop_table", &table).unwrap();
+        execution(statement, &mut table).unwrap();
+        assert!(!path.exists());
+
+        // The handle no longer stands for a table, so nothing is lost to it
+        let statement = prepare_statement("insert 1 2", &table).unwrap();
+        assert!(matches!(
+            execution(statement, &mut table),
+            Err(Error::NotFound(name)) if name == "table drop_table"
+        ));
+        assert!(matches!(table.scan(), Err(Error::NotFound(_))));
+        assert!(matches!(table.read(1), Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn number_out_of_range() {
+        let table = table("number_out_of_range");
+        assert!(matches!(
+            prepare_statement("insert 0 9223372036854775808 \"x\"", &table),
+            Err(Error::NumberOutOfRange(token)) if token == "9223372036854775808"
+        ));
+        assert!(matches!(
+            prepare_statement("insert 0 abc \"x\"", &table),
+            Err(Error::UnexpectedToken { .. })
+        ));
+        assert!(matches!(
+            prepare_statement("insert 9223372036854775808 1 \"x\"", &table),
+            Err(Error::NumberOutOfRange(_))
+        ));
+        // Keys are signed and wider than 32 bits
+        for key in [-5, 4294967296] {
+            match prepare_statement(&format!("insert {key} 1 \"x\""), &table).unwrap() {
+                Statement::Insert(insert) => assert_eq!(insert.key, key),
+                _ => panic!("expected an insert statement"),
+            }
+        }
+        let statement = prepare_statement("insert 0 9223372036854775807 \"x\"", &table).unwrap();
+        assert_eq!(insert_values(statement)[0], ScalarValue::Number(i64::MAX));
+    }
+
+    #[test]
+    fn malformed_inserts() {
+        let table = table("malformed_inserts");
+        assert!(matches!(
+            prepare_statement("insert 0 1 \"x\" 2", &table),
+            Err(Error::ArityMismatch {
+                expected: 2,
+                got: 3
+            })
+        ));
+        assert!(matches!(
+            prepare_statement(
+                "insert into malformed_inserts (key, a) values (0, 1, 2)",
+                &table
+            ),
+            Err(Error::ArityMismatch {
+                expected: 2,
+                got: 3
+            })
+        ));
+        assert!(matches!(
+            prepare_statement("insert 0 \"x\" 1", &table),
+            Err(Error::TypeMismatch { column, expected: DataType::Number, got: "text" })
+                if column == "a"
+        ));
+        assert!(matches!(
+            prepare_statement("insert 0 1 x", &table),
+            Err(Error::UnexpectedToken { token, position: 11 }) if token == "x"
+        ));
+    }
+
+    #[test]
+    fn unexpected_token_position() {
+        let table = table("unexpected_token_position");
+        // Positions are into the whole line, past comments and string values
+        assert!(matches!(
+            prepare_statement("insert /* é */ 0 \"é\" x", &table),
+            Err(Error::UnexpectedToken { token, position: 23 }) if token == "x"
+        ));
+        assert!(matches!(
+            prepare_statement("insert into unexpected_token_position values (0, 1, x)", &table),
+            Err(Error::UnexpectedToken { token, position: 52 }) if token == "x"
+        ));
+    }
+
+    #[test]
+    fn coerce_values() {
+        let mut table = table("coerce_values");
+        assert!(matches!(
+            prepare_statement("insert 0 \"5\" 7", &table),
+            Err(Error::TypeMismatch { .. })
+        ));
+
+        table.coerce = true;
+        let statement = prepare_statement("insert 0 \" 5\" 7", &table).unwrap();
+        assert_eq!(
+            insert_values(statement),
+            vec![ScalarValue::Number(5), ScalarValue::String("7".to_string())]
+        );
+        let statement = prepare_statement("insert 0 null null", &table).unwrap();
+        assert_eq!(
+            insert_values(statement),
+            vec![ScalarValue::Null, ScalarValue::Null]
+        );
+        assert!(matches!(
+            prepare_statement("insert 0 \"five\" \"x\"", &table),
+            Err(Error::TypeMismatch { column, .. }) if column == "a"
+        ));
+        assert!(matches!(
+            ScalarValue::String("99999999999999999999".to_string()).coerce(&DataType::Number),
+            Err(Error::TypeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn null_literal() {
+        let table = table("null_literal");
+        let statement = prepare_statement("insert 0 null \"x\"", &table).unwrap();
+        assert_eq!(
+            insert_values(statement),
+            vec![ScalarValue::Null, ScalarValue::String("x".to_string())]
+        );
+    }
+
+    #[test]
+    fn omitted_column_takes_default() {
+        let mut schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        schema.options[1].default = Some(ScalarValue::String("none".to_string()));
+        let table = table_with_schema("omitted_column_takes_default", schema);
+
+        let statement = prepare_statement("insert 0 5", &table).unwrap();
+        assert_eq!(
+            insert_values(statement),
+            vec![
+                ScalarValue::Number(5),
+                ScalarValue::String("none".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn omitted_column_without_default() {
+        let mut schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let table = table_with_schema("omitted_nullable", schema.clone());
+        let statement = prepare_statement("insert 0 5", &table).unwrap();
+        assert_eq!(
+            insert_values(statement),
+            vec![ScalarValue::Number(5), ScalarValue::Null]
+        );
+
+        schema.options[1].not_null = true;
+        let table = table_with_schema("omitted_not_null", schema);
+        assert!(matches!(
+            prepare_statement("insert 0 5", &table),
+            Err(Error::ConstraintViolation(_))
+        ));
+    }
+
+    #[test]
+    fn insert_into_named_columns() {
+        let mut table = table("insert_into_named_columns");
+        let statement = prepare_statement(
+            "insert into insert_into_named_columns (b, key, a) values (\"x\", 0, 5)",
+            &table,
+        )
+        .unwrap();
+        let Statement::Insert(insert) = statement else {
+            panic!("expected an insert statement")
+        };
+        table.insert(insert).unwrap();
+
+        let rows = table.scan().unwrap();
+        assert_eq!(rows[0]["a"], ScalarValue::Number(5));
+        assert_eq!(rows[0]["b"], ScalarValue::String("x".to_string()));
+    }
+
+    #[test]
+    fn insert_into_fills_missing_columns() {
+        let table = table("insert_into_fills_missing_columns");
+        let statement = prepare_statement(
+            "insert into insert_into_fills_missing_columns (key, b) values (0, \"x\")",
+            &table,
+        )
+        .unwrap();
+        assert_eq!(
+            insert_values(statement),
+            vec![ScalarValue::Null, ScalarValue::String("x".to_string())]
+        );
+    }
+
+    #[test]
+    fn quoted_identifiers() {
+        let mut table = table("quoted_identifiers");
+        for sql in [
+            "alter table quoted_identifiers add column \"order\" number",
+            "alter table `quoted_identifiers` add column `key` number",
+        ] {
+            let statement = prepare_statement(sql, &table).unwrap();
+            execution(statement, &mut table).unwrap();
+        }
+        let names: Vec<&str> = table
+            .schema()
+            .feilds
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["a", "b", "order", "key"]);
+
+        let statement = prepare_statement(
+            "insert into quoted_identifiers (key, \"order\", `key`) values (1, 2, 3)",
+            &table,
+        )
+        .unwrap();
+        execution(statement, &mut table).unwrap();
+        let row = table.read(1).unwrap();
+        assert_eq!(row["order"], ScalarValue::Number(2));
+        assert_eq!(row["key"], ScalarValue::Number(3));
+    }
+
+    #[test]
+    fn insert_into_unknown_column() {
+        let table = table("insert_into_unknown_column");
+        assert!(matches!(
+            prepare_statement(
+                "insert into insert_into_unknown_column (key, c) values (0, 1)",
+                &table
+            ),
+            Err(Error::UnknownColumn(_))
+        ));
+        assert!(matches!(
+            prepare_statement("insert into other (key, a) values (0, 1)", &table),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn insert_returning() {
+        use ScalarValue::{Number, String};
+        let mut table = table("insert_returning");
+
+        let statement = prepare_statement("insert 1 2 \"x\" returning *;", &table).unwrap();
+        assert_eq!(
+            statement.to_string(),
+            "insert 1 values (2, \"x\") returning a, b"
+        );
+        let rows = execution(statement, &mut table).unwrap().into_rows();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].key(), 1);
+        assert_eq!(rows[0].values(), [Number(2), String("x".to_string())]);
+
+        let line = "insert into insert_returning (key, a) values (2, 5) returning b, `a`";
+        let statement = prepare_statement(line, &table).unwrap();
+        let rows = execution(statement, &mut table).unwrap().into_rows();
+        assert_eq!(rows[0].values(), [ScalarValue::Null, Number(5)]);
+        let names: Vec<&str> = rows[0]
+            .schema()
+            .feilds
+            .iter()
+            .map(|(x, _)| x.as_str())
+            .collect();
+        assert_eq!(names, ["b", "a"]);
+
+        // The keyword inside a string is a value
+        let statement = prepare_statement("insert 3 4 \" returning *\"", &table).unwrap();
+        assert_eq!(
+            insert_values(statement)[1],
+            String(" returning *".to_string())
+        );
+
+        assert!(matches!(
+            prepare_statement("insert 4 4 \"y\" returning c", &table),
+            Err(Error::UnknownColumn(column)) if column == "c"
+        ));
+        assert!(matches!(
+            prepare_statement("insert 4 4 \"y\" returning", &table),
+            Err(Error::ParseError)
+        ));
+        assert!(matches!(
+            execution(
+                prepare_statement("insert 4 4 \"y\"", &table).unwrap(),
+                &mut table
+            ),
+            Ok(QueryResult::Affected(1))
+        ));
+    }
+
+    #[test]
+    fn delete_where() {
+        use ScalarValue::Number;
+        let mut table = table("delete_where");
+        for i in 0..10 {
+            let line = format!("insert {i} {i} \"row {i}\"");
+            execution(prepare_statement(&line, &table).unwrap(), &mut table).unwrap();
+        }
+
+        let line = "delete from delete_where where a > 5;";
+        let statement = prepare_statement(line, &table).unwrap();
+        assert_eq!(statement.to_string(), "delete from table where a > 5");
+        execution(statement, &mut table).unwrap();
+        let values: Vec<ScalarValue> = table
+            .scan()
+            .unwrap()
+            .iter()
+            .map(|row| row[0].clone())
+            .collect();
+        assert_eq!(values, (0..=5).map(Number).collect::<Vec<_>>());
+        assert_eq!(table.header.num_rows, 6);
+
+        execution(prepare_statement("delete 0", &table).unwrap(), &mut table).unwrap();
+        assert!(matches!(table.read(0), Err(Error::NotFound(_))));
+        assert!(matches!(
+            execution(prepare_statement("delete 0", &table).unwrap(), &mut table),
+            Err(Error::NotFound(_))
+        ));
+
+        let line = "delete from delete_where where b = \"row 3\" or a = 1";
+        assert!(matches!(
+            prepare_statement(line, &table),
+            Err(Error::UnexpectedToken { token, position: 43 }) if token == "or"
+        ));
+        assert!(matches!(
+            prepare_statement("delete from delete_where when a = 1", &table),
+            Err(Error::UnexpectedToken { token, position: 25 }) if token == "when"
+        ));
+
+        let statement = prepare_statement("delete from delete_where", &table).unwrap();
+        execution(statement, &mut table).unwrap();
+        assert_eq!(table.header.num_rows, 0);
+        assert!(table.scan().unwrap().is_empty());
+    }
+
+    #[test]
+    fn select_aggregates() {
+        let mut table = table("select_aggregates");
+        for i in [1, 4, 5] {
+            let line = format!("insert {i} {} \"row {i}\"", i * 10);
+            execution(prepare_statement(&line, &table).unwrap(), &mut table).unwrap();
+        }
+
+        let line = "select count(*), sum(a) as total, min(a), max(b), min(a, 0) from t";
+        assert!(matches!(
+            prepare_statement(line, &table),
+            Err(Error::UnexpectedToken { token, position: 50 }) if token == "min"
+        ));
+        let line = "select count(*), sum(a) as total, min(a), max(b) from select_aggregates";
+        let statement = prepare_statement(line, &table).unwrap();
+        assert!(statement.is_read_only());
+        assert_eq!(
+            statement.to_string(),
+            "select count(*), sum(a) as total, min(a), max(b) from table"
+        );
+        let rows = execution(statement, &mut table).unwrap().into_rows();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0].values(),
+            [
+                ScalarValue::Number(3),
+                ScalarValue::Number(100),
+                ScalarValue::Number(10),
+                ScalarValue::String("row 5".to_string()),
+            ]
+        );
+        assert_eq!(rows[0].schema().feilds[1].0, "total");
+
+        // The scalar min of two values is no aggregate
+        let line = "select min(a, 20) from select_aggregates";
+        let rows = execution(prepare_statement(line, &table).unwrap(), &mut table)
+            .unwrap()
+            .into_rows();
+        assert_eq!(rows.len(), 3);
+        assert!(matches!(
+            prepare_statement("select count(*) from select_aggregates where key in (1)", &table),
+            Err(Error::UnexpectedToken { token, position: 39 }) if token == "where"
+        ));
+    }
+
+    #[test]
+    fn select_key_in_list() {
+        let mut table = table("select_key_in_list");
+        for i in [1, 4, 5, 9] {
+            let line = format!("insert {i} {} \"row {i}\"", i * 10);
+            execution(prepare_statement(&line, &table).unwrap(), &mut table).unwrap();
+        }
+
+        let line = "select * from select_key_in_list where key in (9, 2, 1, 4, 9);";
+        let statement = prepare_statement(line, &table).unwrap();
+        assert!(statement.is_read_only());
+        assert_eq!(
+            statement.to_string(),
+            "select * from table where key in (9, 2, 1, 4, 9)"
+        );
+        // 2 has no row, and asking twice gives the row twice
+        let keys: Vec<i64> = execution(statement, &mut table)
+            .unwrap()
+            .into_rows()
+            .iter()
+            .map(|row| row.key())
+            .collect();
+        assert_eq!(keys, [9, 1, 4, 9]);
+
+        assert!(matches!(
+            prepare_statement("select * from select_key_in_list where key in ()", &table),
+            Err(Error::ParseError)
+        ));
+        assert!(matches!(
+            prepare_statement(
+                "select * from select_key_in_list where key in (1, \"x\")",
+                &table
+            ),
+            Err(Error::TypeMismatch { got: "text", .. })
+        ));
+        assert!(matches!(
+            prepare_statement("select a b from select_key_in_list where key in (1)", &table),
+            Err(Error::UnexpectedToken { token, position: 9 }) if token == "b"
+        ));
+        assert!(matches!(
+            prepare_statement("select * from other where key in (1)", &table),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn order_by_spills_to_disk() {
+        let mut table = table("order_by_spills_to_disk");
+        for i in 0..50 {
+            let line = format!("insert {i} {} \"s{}\"", (i * 7) % 10, i % 4);
+            execution(prepare_statement(&line, &table).unwrap(), &mut table).unwrap();
+        }
+
+        let line = "select b, a as n from order_by_spills_to_disk order by b desc, a";
+        let statement = prepare_statement(line, &table).unwrap();
+        assert_eq!(
+            statement.to_string(),
+            "select b, a as n from table order by b desc, a"
+        );
+        let run = |table: &mut Table| -> Vec<(i64, Vec<ScalarValue>)> {
+            let statement = prepare_statement(line, &*table).unwrap();
+            let rows = execution(statement, table).unwrap().into_rows();
+            rows.iter()
+                .map(|row| (row.key(), row.values().to_vec()))
+                .collect()
+        };
+        let in_memory = run(&mut table);
+        table.sort_buffer_rows = 3;
+        let spilled = run(&mut table);
+        assert_eq!(spilled, in_memory);
+
+        let mut expected: Vec<(i64, Vec<ScalarValue>)> = (0..50)
+            .map(|i| {
+                let values = vec![
+                    ScalarValue::String(format!("s{}", i % 4)),
+                    ScalarValue::Number((i * 7) % 10),
+                ];
+                (i, values)
+            })
+            .collect();
+        expected.sort_by(|(_, a), (_, b)| {
+            let order = |a, b| Collation::Binary.compare(a, b);
+            order(&b[0], &a[0]).then(order(&a[1], &b[1]))
+        });
+        assert_eq!(spilled, expected);
+
+        // Ordering a key list keeps it from being read as stored
+        let line = "select * from order_by_spills_to_disk where key in (3, 1, 2) order by key";
+        assert!(matches!(
+            prepare_statement(line, &table),
+            Err(Error::UnknownColumn(column)) if column == "key"
+        ));
+        let line = "select * from order_by_spills_to_disk where key in (3, 1, 2) order by a";
+        let keys: Vec<i64> = execution(prepare_statement(line, &table).unwrap(), &mut table)
+            .unwrap()
+            .into_rows()
+            .iter()
+            .map(|row| row.key())
+            .collect();
+        assert_eq!(keys, [3, 2, 1]);
+        assert!(matches!(
+            prepare_statement("select * from order_by_spills_to_disk order a", &table),
+            Err(Error::UnexpectedToken { token, position: 44 }) if token == "a"
+        ));
+    }
+
+    #[test]
+    fn select_where_counts_like_count_where() {
+        let mut table = table("select_where_counts_like_count_where");
+        for i in 0..20 {
+            let line = format!("insert {i} {} \"s{i}\"", i % 7);
+            execution(prepare_statement(&line, &table).unwrap(), &mut table).unwrap();
+        }
+
+        let line = "select * from select_where_counts_like_count_where where a > 3";
+        let statement = prepare_statement(line, &table).unwrap();
+        assert_eq!(statement.to_string(), "select a, b from table where a > 3");
+        let selected = query(statement, &table).unwrap().into_rows();
+        let predicate = Expr::parse("a > 3", table.schema()).unwrap();
+        assert_eq!(table.count_where(&predicate).unwrap(), selected.len());
+        assert!(selected
+            .iter()
+            .all(|row| matches!(row["a"], ScalarValue::Number(a) if a > 3)));
+
+        // The filter runs before the sort, and `order by` may follow it
+        let line = "select b from select_where_counts_like_count_where where a = 6 order by b desc";
+        let rows = execution(prepare_statement(line, &table).unwrap(), &mut table)
+            .unwrap()
+            .into_rows();
+        let values: Vec<&ScalarValue> = rows.iter().map(|row| &row["b"]).collect();
+        let expected = ["s6", "s13"].map(|s| ScalarValue::String(s.to_string()));
+        assert_eq!(values, expected.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn nocase_where_and_order_by() {
+        let mut schema = Schema::new(vec![
+            ("name".to_string(), DataType::String(10)),
+            ("n".to_string(), DataType::Number),
+        ]);
+        schema.options[0].collation = Collation::NoCase;
+        let mut table = table_with_schema("nocase_where_and_order_by", schema);
+        for (key, name) in ["bob", "Alice", "carol", "ALICE", "Bob"].iter().enumerate() {
+            let line = format!("insert {key} \"{name}\" {key}");
+            execution(prepare_statement(&line, &table).unwrap(), &mut table).unwrap();
+        }
+        let mut run = |line: &str| -> Vec<i64> {
+            let statement = prepare_statement(line, &table).unwrap();
+            let rows = execution(statement, &mut table).unwrap().into_rows();
+            rows.iter().map(|row| row.key()).collect()
+        };
+
+        let line = "select * from nocase_where_and_order_by where name = \"alice\"";
+        assert_eq!(run(line), [1, 3]);
+        // The column's collation holds with the literal on the left too
+        let line = "select * from nocase_where_and_order_by where \"BOB\" = name";
+        assert_eq!(run(line), [0, 4]);
+        // An expression that isn't a bare column compares byte-wise
+        let line = "select * from nocase_where_and_order_by where upper(name) = \"alice\"";
+        assert!(run(line).is_empty());
+
+        // Ties under nocase keep their scan order
+        let line = "select name from nocase_where_and_order_by order by name";
+        assert_eq!(run(line), [1, 3, 0, 4, 2]);
+        let line =
+            "select name from nocase_where_and_order_by where name < \"C\" order by name desc";
+        assert_eq!(run(line), [0, 4, 1, 3]);
+    }
+
+    #[test]
+    fn affected_rows() {
+        let mut table = table("affected_rows");
+        let mut run = |line: &str| {
+            let statement = prepare_statement(line, &table).unwrap();
+            match execution(statement, &mut table).unwrap() {
+                QueryResult::Affected(n) => n,
+                QueryResult::Rows(rows) => panic!("expected a count, got {rows:?}"),
+            }
+        };
+        assert_eq!(run("insert 1 1 \"x\""), 1);
+        assert_eq!(run("insert 2 7 \"y\""), 1);
+        assert_eq!(run("delete from affected_rows where a > 10"), 0);
+        assert_eq!(run("delete from affected_rows where a < 5"), 1);
+        assert_eq!(run("alter table affected_rows add column c number"), 0);
+
+        let statement = prepare_statement("read 2", &table).unwrap();
+        let rows = execution(statement, &mut table).unwrap().into_rows();
+        assert_eq!(rows.len(), 1);
+
+        let statement = prepare_statement("truncate affected_rows", &table).unwrap();
+        assert!(matches!(
+            execution(statement, &mut table),
+            Ok(QueryResult::Affected(1))
+        ));
+    }
+
+    #[test]
+    fn add_compact_column() {
+        let mut table = table("add_compact_column");
+        let statement = prepare_statement("insert 1 1 \"x\"", &table).unwrap();
+        execution(statement, &mut table).unwrap();
+
+        let line = "alter table add_compact_column add column c number compact default -3";
+        let statement = prepare_statement(line, &table).unwrap();
+        assert_eq!(
+            statement.to_string(),
+            "alter table add column c number compact default -3"
+        );
+        execution(statement, &mut table).unwrap();
+        assert!(table.schema().is_compact(2));
+        assert_eq!(table.read(1).unwrap()["c"], ScalarValue::Number(-3));
+
+        let statement = prepare_statement("insert 2 2 \"y\" 300", &table).unwrap();
+        execution(statement, &mut table).unwrap();
+        assert_eq!(table.read(2).unwrap()["c"], ScalarValue::Number(300));
+
+        let line = "alter table add_compact_column add column d text(4) compact";
+        let statement = prepare_statement(line, &table).unwrap();
+        assert!(matches!(
+            execution(statement, &mut table),
+            Err(Error::InvalidSchema(_))
+        ));
+    }
+
+    #[test]
+    fn add_dictionary_column() {
+        let mut table = table("add_dictionary_column");
+        let statement = prepare_statement("insert 1 1 \"x\"", &table).unwrap();
+        execution(statement, &mut table).unwrap();
+
+        let line = "alter table add_dictionary_column add column c text(8) dict default \"none\"";
+        let statement = prepare_statement(line, &table).unwrap();
+        assert_eq!(
+            statement.to_string(),
+            "alter table add column c text(8) dict default \"none\""
+        );
+        execution(statement, &mut table).unwrap();
+        assert!(table.schema().is_dictionary(2));
+        assert_eq!(table.dictionary.len(), 1);
+        let statement = prepare_statement("insert 2 2 \"y\" \"none\"", &table).unwrap();
+        execution(statement, &mut table).unwrap();
+        assert_eq!(table.dictionary.len(), 1);
+        assert_eq!(
+            table.read(2).unwrap()["c"],
+            ScalarValue::String("none".to_string())
+        );
+
+        let line = "alter table add_dictionary_column add column d number dict";
+        let statement = prepare_statement(line, &table).unwrap();
+        assert!(matches!(
+            execution(statement, &mut table),
+            Err(Error::InvalidSchema(_))
+        ));
+    }
+
+    #[test]
+    fn add_column_definition() {
+        let mut table = table("add_column_definition");
+        let statement = prepare_statement("insert 1 1 \"x\"", &table).unwrap();
+        execution(statement, &mut table).unwrap();
+
+        let line = "alter table add_column_definition add column \"first name\" text(8) \
+                    default \"a b\" collate nocase not null";
+        let statement = prepare_statement(line, &table).unwrap();
+        assert_eq!(
+            statement.to_string(),
+            "alter table add column first name text(8) not null collate nocase default \"a b\""
+        );
+        execution(statement, &mut table).unwrap();
+        let options = &table.schema().options[2];
+        assert!(options.not_null && !options.unique);
+        assert_eq!(options.collation, Collation::NoCase);
+        assert_eq!(
+            table.read(1).unwrap()["first name"],
+            ScalarValue::String("a b".to_string())
+        );
+
+        // The one row gets null, which a unique column allows
+        let line = "alter table add_column_definition add column `c` number unique compact";
+        execution(prepare_statement(line, &table).unwrap(), &mut table).unwrap();
+        assert!(table.schema().options[3].unique);
+        assert!(table.schema().is_compact(3));
+
+        // Existing rows would be left null
+        let line = "alter table add_column_definition add column d number not null";
+        assert!(matches!(
+            execution(prepare_statement(line, &table).unwrap(), &mut table),
+            Err(Error::ConstraintViolation(_))
+        ));
+        let line = "alter table add_column_definition add column d number collate upper";
+        assert!(matches!(
+            prepare_statement(line, &table),
+            Err(Error::UnexpectedToken { token, position: 62 }) if token == "upper"
+        ));
+        let line = "alter table add_column_definition add column d number primary key";
+        assert!(matches!(
+            prepare_statement(line, &table),
+            Err(Error::UnexpectedToken { token, position: 54 }) if token == "primary"
+        ));
+        let line = "alter table add_column_definition add column d number not nul";
+        assert!(matches!(
+            prepare_statement(line, &table),
+            Err(Error::UnexpectedToken { token, position: 58 }) if token == "nul"
+        ));
+        assert_eq!(table.schema().feilds.len(), 4);
+    }
+
+    #[test]
+    fn create_table() {
+        let mut table = table("create_table");
+        let path = std::env::temp_dir().join("people.db");
+        let _ = fs::remove_file(&path);
+
+        let line = "create table people (\"full name\" text(10) not null unique collate nocase, \
+                    score number compact default 0, tag text(8) dict default \"a, b\");";
+        let statement = prepare_statement(line, &table).unwrap();
+        assert!(!statement.is_read_only());
+        assert_eq!(
+            statement.to_string(),
+            "create table people (full name text(10) not null unique collate nocase, \
+             score number compact default 0, tag text(8) dict default \"a, b\")"
+        );
+        execution(statement.clone(), &mut table).unwrap();
+        assert!(matches!(
+            execution(statement, &mut table),
+            Err(Error::AlreadyExists(name)) if name == "table people"
+        ));
+
+        let schema = Schema::new(vec![("x".to_string(), DataType::Number)]);
+        let mut people = Table::new("people".to_string(), schema, &path).unwrap();
+        let options = &people.schema().options;
+        assert!(options[0].not_null && options[0].unique);
+        assert_eq!(options[0].collation, Collation::NoCase);
+        assert!(people.schema().is_compact(1) && people.schema().is_dictionary(2));
+
+        let mut run = |line: &str| {
+            let statement = prepare_statement(line, &people)?;
+            execution(statement, &mut people).map(drop)
+        };
+        run("insert into people (key, `full name`) values (1, \"Alice\")").unwrap();
+        // Unique under the column's collation
+        assert!(matches!(
+            run("insert into people (key, `full name`) values (2, \"ALICE\")"),
+            Err(Error::ConstraintViolation(_))
+        ));
+        assert!(matches!(
+            run("insert into people (key, score) values (2, 5)"),
+            Err(Error::ConstraintViolation(_))
+        ));
+        let row = people.read(1).unwrap();
+        assert_eq!(row["score"], ScalarValue::Number(0));
+        assert_eq!(row["tag"], ScalarValue::String("a, b".to_string()));
+        drop(people);
+        fs::remove_file(path).unwrap();
+
+        for (line, err) in [
+            ("create table t ()", Error::ParseError),
+            (
+                "create table t (a number, a number)",
+                Error::AlreadyExists("column a".into()),
+            ),
+            ("create table t (a number", Error::ParseError),
+        ] {
+            let res = prepare_statement(line, &table).map(drop);
+            assert_eq!(res.unwrap_err().to_string(), err.to_string());
+        }
+        let line = "create table t (a number, b text(4) compact)";
+        assert!(matches!(
+            execution(prepare_statement(line, &table).unwrap(), &mut table),
+            Err(Error::InvalidSchema(_))
+        ));
+    }
+
+    #[test]
+    fn text_keys() {
+        let path = std::env::temp_dir().join("text_keys.db");
+        let _ = fs::remove_file(&path);
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let options = TableOptions {
+            key_type: KeyType::Text,
+            ..Default::default()
+        };
+        let mut table =
+            Table::open_with_options("t".to_string(), schema.clone(), &path, options).unwrap();
+        // Inserted last to first, so leaves split and the root holds string
+        // separators
+        let count = table.rows_per_page() * 2;
+        for i in (0..count).rev() {
+            let line = format!("insert \"u{i:04}\" {i}");
+            execution(prepare_statement(&line, &table).unwrap(), &mut table).unwrap();
+        }
+        assert!(table.stats().unwrap().leaf_pages > 1);
+        table.check_invariants().unwrap();
+
+        let keys: Vec<ScalarValue> = table
+            .scan()
+            .unwrap()
+            .iter()
+            .map(|row| table.key_value(row.key()))
+            .collect();
+        let expected: Vec<ScalarValue> = (0..count)
+            .map(|i| ScalarValue::String(format!("u{i:04}")))
+            .collect();
+        assert_eq!(keys, expected);
+
+        let column_a = |line: &str, table: &mut Table| {
+            let rows = execution(prepare_statement(line, &*table).unwrap(), table)
+                .unwrap()
+                .into_rows();
+            rows.iter().map(|row| row[0].clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            column_a("read \"u0007\"", &mut table),
+            [ScalarValue::Number(7)]
+        );
+        assert_eq!(
+            column_a(
+                "select * from t where key in (\"u0003\", \"nope\")",
+                &mut table
+            ),
+            [ScalarValue::Number(3)]
+        );
+        column_a("delete \"u0003\"", &mut table);
+        assert!(matches!(
+            prepare_statement("read \"u0003\"", &table).and_then(|s| execution(s, &mut table)),
+            Err(Error::NotFound(_))
+        ));
+
+        assert!(matches!(
+            prepare_statement("insert 5 5", &table),
+            Err(Error::TypeMismatch { got: "number", .. })
+        ));
+        assert!(matches!(
+            prepare_statement("insert \"much too long\" 5", &table),
+            Err(Error::ConstraintViolation(_))
+        ));
+
+        // The key type is kept in the header, whatever the options say later
+        drop(table);
+        let table = Table::new("t".to_string(), schema, &path).unwrap();
+        assert_eq!(table.key_type(), KeyType::Text);
+        fs::remove_file(path).unwrap();
+    }
+}
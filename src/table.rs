@@ -1,22 +1,33 @@
 use std::{
+    collections::BTreeMap,
+    fs,
     fs::{File, OpenOptions},
-    io::{self, Read, Seek, Write},
-    path::Path,
+    io::{self, Cursor, Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{self, AtomicU64, AtomicUsize},
+        Arc, Mutex, OnceLock, PoisonError,
+    },
     vec,
 };
 
 use crate::{
-    datatype::Schema,
+    catalog, codec,
+    datatype::{ColumnOptions, DataType, KeyType, Row, ScalarValue, Schema},
+    dictionary::{Dictionary, DictionaryPage},
     errors::Error,
+    expr::Expr,
+    sort::SORT_BUFFER_ROWS,
     statement::InsertStatement,
-    tree::{InternalNode, LeafNode},
-    TABLE_MAX_PAGE,
+    tree::{InternalNode, LeafNode, Pos, NODE_TYPE_OFFSET},
+    PAGE_SIZE, TABLE_MAX_PAGE,
 };
 
 #[derive(Debug)]
 pub enum Page {
     Leaf(LeafNode),
     Intermediate(InternalNode),
+    Dictionary(DictionaryPage),
 }
 
 impl Page {
@@ -24,6 +35,7 @@ impl Page {
         match self {
             Page::Leaf(x) => &*x.bytes,
             Page::Intermediate(x) => &*x.bytes,
+            Page::Dictionary(x) => &*x.bytes,
         }
     }
 
@@ -31,222 +43,3703 @@ impl Page {
         match self {
             Page::Leaf(x) => &mut *x.bytes,
             Page::Intermediate(x) => &mut *x.bytes,
+            Page::Dictionary(x) => &mut *x.bytes,
         }
     }
 }
 
+/// Path that opens a table backed by memory instead of a file.
+pub const MEMORY_PATH: &str = ":memory:";
+
+/// Where the pager reads pages from and writes them to.
+#[derive(Debug)]
+pub enum Backend {
+    File(File),
+    Memory(Cursor<Vec<u8>>),
+    /// A table's part of a file shared with other tables.
+    Region(Region),
+}
+
+impl Backend {
+    pub fn memory() -> Self {
+        Backend::Memory(Cursor::new(Vec::new()))
+    }
+
+    pub fn len(&self) -> Result<u64, io::Error> {
+        match self {
+            Backend::File(file) => Ok(file.metadata()?.len()),
+            Backend::Memory(buf) => Ok(buf.get_ref().len() as u64),
+            Backend::Region(region) => Ok(region.len),
+        }
+    }
+
+    pub fn is_empty(&self) -> Result<bool, io::Error> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Forces written data to disk. Memory has nothing to sync.
+    pub fn sync_data(&self) -> Result<(), io::Error> {
+        match self {
+            Backend::File(file) => file.sync_data(),
+            Backend::Memory(_) => Ok(()),
+            Backend::Region(region) => region.lock().sync_data(),
+        }
+    }
+
+    /// Like `sync_data`, but also forces metadata such as the file length.
+    pub fn sync_all(&self) -> Result<(), io::Error> {
+        match self {
+            Backend::File(file) => file.sync_all(),
+            Backend::Memory(_) => Ok(()),
+            Backend::Region(region) => region.lock().sync_all(),
+        }
+    }
+
+    pub fn set_len(&mut self, len: u64) -> Result<(), io::Error> {
+        match self {
+            Backend::File(file) => file.set_len(len),
+            Backend::Memory(buf) => {
+                buf.get_mut().resize(len as usize, 0);
+                Ok(())
+            }
+            Backend::Region(region) => region.set_len(len),
+        }
+    }
+}
+
+impl From<File> for Backend {
+    fn from(file: File) -> Self {
+        Backend::File(file)
+    }
+}
+
+impl Read for Backend {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Backend::File(file) => file.read(buf),
+            Backend::Memory(cursor) => cursor.read(buf),
+            Backend::Region(region) => region.read(buf),
+        }
+    }
+}
+
+impl Write for Backend {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Backend::File(file) => file.write(buf),
+            Backend::Memory(cursor) => cursor.write(buf),
+            Backend::Region(region) => region.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Backend::File(file) => file.flush(),
+            Backend::Memory(_) => Ok(()),
+            Backend::Region(region) => region.lock().flush(),
+        }
+    }
+}
+
+impl Seek for Backend {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        match self {
+            Backend::File(file) => file.seek(pos),
+            Backend::Memory(cursor) => cursor.seek(pos),
+            Backend::Region(region) => region.seek(pos),
+        }
+    }
+}
+
+/// A table's namespace in a file holding several tables, see `catalog`. It
+/// starts with a page recording how many of its bytes are in use, followed
+/// by the table's header and pages laid out as in a file of their own.
+#[derive(Debug)]
+pub struct Region {
+    file: Arc<Mutex<Backend>>,
+    // Where the length page starts in the file
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl Region {
+    /// Bytes a region takes up in the file: the length page, the table
+    /// header and room for every page a table may have.
+    pub const SIZE: u64 = (PAGE_SIZE + HEADER_SPACE + TABLE_MAX_PAGE * PAGE_SIZE) as u64;
+    const LEN_PAGE: u64 = PAGE_SIZE as u64;
+
+    /// Opens the region starting at byte `start` of `file`, or an empty
+    /// one when the file ends before it.
+    pub fn open(file: Arc<Mutex<Backend>>, start: u64) -> Result<Self, io::Error> {
+        let mut len = [0u8; 8];
+        {
+            let mut shared = file.lock().unwrap_or_else(PoisonError::into_inner);
+            if shared.len()? >= start + Self::LEN_PAGE {
+                shared.seek(io::SeekFrom::Start(start))?;
+                shared.read_exact(&mut len)?;
+            } else {
+                shared.set_len(start + Self::LEN_PAGE)?;
+            }
+        }
+        Ok(Self {
+            file,
+            start,
+            len: u64::from_le_bytes(len),
+            pos: 0,
+        })
+    }
+
+    /// The page of the file the table's root is on.
+    pub fn root_page(&self) -> u32 {
+        ((self.data_start() + HEADER_SPACE as u64) / PAGE_SIZE as u64) as u32
+    }
+
+    /// Where the region starting with the table rooted at `root_page` starts.
+    pub fn start_of(root_page: u32) -> u64 {
+        root_page as u64 * PAGE_SIZE as u64 - HEADER_SPACE as u64 - Self::LEN_PAGE
+    }
+
+    /// Renames the table in the catalog of the file, failing when another
+    /// table there has the name.
+    pub(crate) fn rename_in_catalog(&self, name: &str) -> Result<(), Error> {
+        catalog::rename_entry(&mut self.lock(), self.root_page(), name)
+    }
+
+    /// Takes the table out of the catalog of the file and empties the
+    /// region, so the next table created there starts afresh.
+    pub(crate) fn remove_from_catalog(&mut self) -> Result<(), Error> {
+        catalog::remove_entry(&mut self.lock(), self.root_page())?;
+        self.set_len(0)?;
+        Ok(())
+    }
+
+    fn data_start(&self) -> u64 {
+        self.start + Self::LEN_PAGE
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Backend> {
+        self.file.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Grows or shrinks the table's part, never the file, as the regions of
+    // other tables may follow
+    fn set_len(&mut self, len: u64) -> Result<(), io::Error> {
+        if len > Self::SIZE - Self::LEN_PAGE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "table outgrew its region",
+            ));
+        }
+        let (start, old_len, data_start) = (self.start, self.len, self.data_start());
+        let mut file = self.lock();
+        if file.len()? < data_start + len {
+            file.set_len(data_start + len)?;
+        }
+        // Bytes past the old end may still hold pages the table dropped
+        if len > old_len {
+            file.seek(io::SeekFrom::Start(data_start + old_len))?;
+            file.write_all(&vec![0; (len - old_len) as usize])?;
+        }
+        file.seek(io::SeekFrom::Start(start))?;
+        file.write_all(&len.to_le_bytes())?;
+        drop(file);
+        self.len = len;
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.len.saturating_sub(self.pos) as usize);
+        if n > 0 {
+            let at = self.data_start() + self.pos;
+            let mut file = self.lock();
+            file.seek(io::SeekFrom::Start(at))?;
+            file.read_exact(&mut buf[..n])?;
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = self.pos + buf.len() as u64;
+        if end > self.len {
+            self.set_len(end)?;
+        }
+        let at = self.data_start() + self.pos;
+        let mut file = self.lock();
+        file.seek(io::SeekFrom::Start(at))?;
+        file.write_all(buf)?;
+        drop(file);
+        self.pos = end;
+        Ok(buf.len())
+    }
+
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            io::SeekFrom::Start(pos) => Some(pos),
+            io::SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            io::SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos = pos
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the region"))?;
+        Ok(self.pos)
+    }
+}
+
+/// How many page requests went to disk versus the cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PagerStats {
+    pub reads: usize,
+    pub hits: usize,
+    /// Writes forced to disk, see `TableOptions::sync`.
+    pub syncs: usize,
+}
+
+/// Pages can be read through `&self`, so any number of readers can share a
+/// pager: a page missing from the cache is loaded under the file lock and
+/// then kept. Anything that changes pages takes `&mut self` and bypasses the
+/// lock.
+///
+/// The cache holds at most `cache_size` pages, evicting the least recently
+/// used one (after writing it back) to make room. Eviction needs `&mut self`,
+/// so shared readers can grow the cache past the limit until the next write.
+/// In write-through mode it holds only the page in use.
 #[derive(Debug)]
 pub struct Pager {
-    file: File,
+    file: Mutex<Backend>,
     pages: usize,
-    cache: [Option<Page>; TABLE_MAX_PAGE],
+    cache: [OnceLock<Page>; TABLE_MAX_PAGE],
+    cache_size: usize,
+    clock: AtomicU64,
+    last_used: [AtomicU64; TABLE_MAX_PAGE],
+    reads: AtomicUsize,
+    hits: AtomicUsize,
+    syncs: usize,
+    read_only: bool,
+    sync: bool,
+    write_through: bool,
+    trace: Option<Trace>,
+}
+
+/// Where a pager reports each page it loads, finds cached or writes back,
+/// one line each, such as `read page 3`.
+pub struct Trace(Mutex<Box<dyn Write + Send>>);
+
+impl std::fmt::Debug for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Trace")
+    }
 }
 
-const HEADER_SPACE: usize = 4096;
+pub(crate) const HEADER_SPACE: usize = 4096;
+/// The header space holds two copies of the header. Each write goes to the
+/// slot holding the older copy, so a write cut short by a crash leaves the
+/// newer one readable.
+const HEADER_SLOT_SIZE: usize = HEADER_SPACE / 2;
+// A slot starts with the magic bytes, the format version, a sequence number,
+// a checksum and the length of the serialized header that follows.
+const HEADER_SLOT_PREFIX: usize = 24;
+/// Marks a file as a table.
+const MAGIC: [u8; 4] = *b"SCDB";
+/// Layout of the header and pages this build reads and writes. Version 2
+/// widened row keys from `u32` to `i64`, version 3 gave leaf cells slots so
+/// they can vary in size, version 4 added dictionary pages, version 5 the key
+/// type, version 6 index definitions.
+pub const FORMAT_VERSION: u32 = 6;
 
-const NONE_VALUE: Option<Page> = None;
 impl Pager {
-    pub fn new(file: File, pages: u64) -> Result<Self, io::Error> {
+    pub fn new(file: impl Into<Backend>, pages: u64) -> Result<Self, io::Error> {
         Ok(Self {
-            file,
+            file: Mutex::new(file.into()),
             pages: pages as usize,
-            cache: [NONE_VALUE; TABLE_MAX_PAGE],
+            cache: std::array::from_fn(|_| OnceLock::new()),
+            cache_size: TABLE_MAX_PAGE,
+            clock: AtomicU64::new(0),
+            last_used: std::array::from_fn(|_| AtomicU64::new(0)),
+            reads: AtomicUsize::new(0),
+            hits: AtomicUsize::new(0),
+            syncs: 0,
+            read_only: false,
+            sync: false,
+            write_through: false,
+            trace: None,
         })
     }
 
-    pub fn new_leaf_page(&mut self) -> Result<(u32, &mut LeafNode), io::Error> {
+    fn file(&mut self) -> &mut Backend {
+        self.file.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn is_memory(&self) -> bool {
+        let file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        matches!(*file, Backend::Memory(_))
+    }
+
+    // The region the table is in when it shares its file with others
+    fn region(&mut self) -> Option<&mut Region> {
+        match self.file() {
+            Backend::Region(region) => Some(region),
+            _ => None,
+        }
+    }
+
+    // The file the table's region is in when it shares one with others
+    fn shared_file(&self) -> Option<Arc<Mutex<Backend>>> {
+        let file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        match &*file {
+            Backend::Region(region) => Some(region.file.clone()),
+            _ => None,
+        }
+    }
+
+    // Whether the table is alone in its file, so the file can be renamed or
+    // removed with it
+    fn owns_file(&self) -> bool {
+        let file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        matches!(*file, Backend::File(_))
+    }
+
+    // Appends `page` to the file, failing once every page is taken
+    fn allocate(&mut self, page: Page) -> Result<usize, Error> {
         let index = self.pages;
-        self.file
-            .set_len((self.pages + 1) as u64 * 4096 + HEADER_SPACE as u64)?;
-        self.file.seek(std::io::SeekFrom::Start(
-            index as u64 * 4096 + HEADER_SPACE as u64,
-        ))?;
+        if index >= TABLE_MAX_PAGE {
+            return Err(Error::RowLimit);
+        }
+        self.evict(self.cache_limit() - 1, index)?;
+        let len = (self.pages + 1) as u64 * 4096 + HEADER_SPACE as u64;
+        self.file().set_len(len)?;
         self.pages += 1;
-        let page = vec![0u8; 4096].into_boxed_slice().try_into().unwrap();
-        self.cache[index] = Some(Page::Leaf(LeafNode::new_with_bytes(page)));
-        let Page::Leaf(page) = self.cache[index].as_mut().unwrap() else {
-            unreachable!()
+        self.cache[index] = OnceLock::from(page);
+        self.touch(index);
+        Ok(index)
+    }
+
+    /// Sends a line per page access to `trace`, or stops tracing on `None`.
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write + Send>>) {
+        self.trace = trace.map(|trace| Trace(Mutex::new(trace)));
+    }
+
+    // A trace that can't be written to is not worth failing the access for
+    fn trace(&self, event: &str, index: usize) {
+        if let Some(Trace(trace)) = &self.trace {
+            let mut trace = trace.lock().unwrap_or_else(PoisonError::into_inner);
+            let _ = writeln!(trace, "{event} page {index}");
+        }
+    }
+
+    fn touch(&self, index: usize) {
+        let now = self.clock.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+        self.last_used[index].store(now, atomic::Ordering::Relaxed);
+    }
+
+    pub fn cached_pages(&self) -> usize {
+        self.cache
+            .iter()
+            .filter(|page| page.get().is_some())
+            .count()
+    }
+
+    // Writes back and drops least recently used pages, other than `keep`,
+    // until no more than `limit` are cached
+    fn evict(&mut self, limit: usize, keep: usize) -> Result<(), io::Error> {
+        while self.cached_pages() > limit {
+            let Some(index) = (0..TABLE_MAX_PAGE)
+                .filter(|&i| i != keep && self.cache[i].get().is_some())
+                .min_by_key(|&i| self.last_used[i].load(atomic::Ordering::Relaxed))
+            else {
+                break;
+            };
+            self.flush_page(index)?;
+            self.cache[index] = OnceLock::new();
+        }
+        Ok(())
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    pub fn cache_size(&self) -> usize {
+        self.cache_size
+    }
+
+    /// Whether writes are forced to disk, see `TableOptions::sync`.
+    pub fn sync(&self) -> bool {
+        self.sync
+    }
+
+    pub fn set_sync(&mut self, sync: bool) {
+        self.sync = sync;
+    }
+
+    // In sync mode, forces what was just written to disk: the data alone
+    // after a page, the metadata too after the header
+    fn sync_write(&mut self, all: bool) -> Result<(), io::Error> {
+        if !self.sync {
+            return Ok(());
+        }
+        let file = self.file.get_mut().unwrap_or_else(PoisonError::into_inner);
+        match all {
+            true => file.sync_all()?,
+            false => file.sync_data()?,
+        }
+        self.syncs += 1;
+        Ok(())
+    }
+
+    /// Whether pages leave the cache once written back, see
+    /// `set_write_through`.
+    pub fn write_through(&self) -> bool {
+        self.write_through
+    }
+
+    /// Keeps only the page in use cached, writing back and dropping every
+    /// other, and empties the cache after each write. Memory stays bounded
+    /// through a large load, at the cost of reading pages again from the
+    /// file on every operation.
+    pub fn set_write_through(&mut self, write_through: bool) -> Result<(), io::Error> {
+        self.write_through = write_through;
+        self.trim_cache()
+    }
+
+    // Most pages kept cached while a page is in use
+    fn cache_limit(&self) -> usize {
+        match self.write_through {
+            true => 1,
+            false => self.cache_size,
+        }
+    }
+
+    /// Limits the cache to `size` pages (at least one), evicting any excess.
+    pub fn set_cache_size(&mut self, size: usize) -> Result<(), io::Error> {
+        self.cache_size = size.clamp(1, TABLE_MAX_PAGE);
+        self.trim_cache()
+    }
+
+    /// Evicts pages that shared readers loaded past the cache size, or every
+    /// page in write-through mode.
+    pub fn trim_cache(&mut self) -> Result<(), io::Error> {
+        let limit = match self.write_through {
+            true => 0,
+            false => self.cache_size,
         };
-        return Ok((index as u32, page));
+        self.evict(limit, TABLE_MAX_PAGE)
+    }
+
+    pub fn new_leaf_page(&mut self) -> Result<(u32, &mut LeafNode), Error> {
+        let index = self.allocate(Page::Leaf(LeafNode::new()))?;
+        Ok((index as u32, self.leaf(index)?))
+    }
+
+    pub fn new_internal_page(&mut self) -> Result<(u32, &mut InternalNode), Error> {
+        let mut node = InternalNode::new(vec![0u8; 4096].into_boxed_slice().try_into().unwrap());
+        node.initialize();
+        let index = self.allocate(Page::Intermediate(node))?;
+        Ok((index as u32, self.internal(index)?))
+    }
+
+    pub fn new_dictionary_page(&mut self) -> Result<(u32, &mut DictionaryPage), Error> {
+        let index = self.allocate(Page::Dictionary(DictionaryPage::new()))?;
+        Ok((index as u32, self.dictionary(index)?))
+    }
+
+    fn load(file: &mut Backend, index: usize) -> Result<Page, io::Error> {
+        file.seek(std::io::SeekFrom::Start(
+            index as u64 * 4096 + HEADER_SPACE as u64,
+        ))?;
+        let mut page: Box<[u8; 4096]> = vec![0u8; 4096].into_boxed_slice().try_into().unwrap();
+        file.read_exact(&mut *page)?;
+        match page[NODE_TYPE_OFFSET] {
+            0 => Ok(Page::Leaf(LeafNode::new_with_bytes(page))),
+            1 => Ok(Page::Intermediate(InternalNode::new(page))),
+            DictionaryPage::NODE_TYPE => Ok(Page::Dictionary(DictionaryPage::new_with_bytes(page))),
+            node_type => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("page {index} has unknown node type {node_type}"),
+            )),
+        }
     }
 
     pub fn page(&mut self, index: usize) -> Result<&mut Page, io::Error> {
-        match self.cache[index] {
-            Some(ref mut page) => Ok(&mut *page),
-            None => {
-                self.file.seek(std::io::SeekFrom::Start(
-                    index as u64 * 4096 + HEADER_SPACE as u64,
-                ))?;
-                let mut page: Box<[u8; 4096]> =
-                    vec![0u8; 4096].into_boxed_slice().try_into().unwrap();
-                self.file.read_exact(&mut *page)?;
-                let page = match page[0] {
-                    0 => Page::Leaf(LeafNode::new_with_bytes(page)),
-                    1 => Page::Intermediate(InternalNode::new(page)),
-                    _ => unreachable!(),
-                };
-                self.cache[index] = Some(page);
-                Ok(unsafe { (&mut self.cache[index]).as_mut().unwrap_unchecked() })
-            }
+        if self.cache[index].get().is_some() {
+            self.hits.fetch_add(1, atomic::Ordering::Relaxed);
+            self.trace("hit", index);
+        } else {
+            self.evict(self.cache_limit() - 1, index)?;
+            self.reads.fetch_add(1, atomic::Ordering::Relaxed);
+            self.trace("read", index);
+            let page = Self::load(self.file(), index)?;
+            self.cache[index] = OnceLock::from(page);
+        }
+        self.touch(index);
+        Ok(self.cache[index].get_mut().unwrap())
+    }
+
+    /// Shared access to a page, for readers that only hold `&self`.
+    pub fn page_ref(&self, index: usize) -> Result<&Page, io::Error> {
+        self.touch(index);
+        if let Some(page) = self.cache[index].get() {
+            self.hits.fetch_add(1, atomic::Ordering::Relaxed);
+            self.trace("hit", index);
+            return Ok(page);
+        }
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        // Another reader may have loaded the page while this one waited
+        if let Some(page) = self.cache[index].get() {
+            self.trace("hit", index);
+            return Ok(page);
+        }
+        self.reads.fetch_add(1, atomic::Ordering::Relaxed);
+        self.trace("read", index);
+        let page = Self::load(&mut file, index)?;
+        Ok(self.cache[index].get_or_init(|| page))
+    }
+
+    pub fn leaf(&mut self, index: usize) -> Result<&mut LeafNode, Error> {
+        match self.page(index)? {
+            Page::Leaf(page) => Ok(page),
+            _ => Err(Error::WrongNodeType(index, "leaf")),
+        }
+    }
+
+    pub fn internal(&mut self, index: usize) -> Result<&mut InternalNode, Error> {
+        match self.page(index)? {
+            Page::Intermediate(page) => Ok(page),
+            _ => Err(Error::WrongNodeType(index, "internal")),
+        }
+    }
+
+    pub fn dictionary(&mut self, index: usize) -> Result<&mut DictionaryPage, Error> {
+        match self.page(index)? {
+            Page::Dictionary(page) => Ok(page),
+            _ => Err(Error::WrongNodeType(index, "dictionary")),
+        }
+    }
+
+    pub fn leaf_ref(&self, index: usize) -> Result<&LeafNode, Error> {
+        match self.page_ref(index)? {
+            Page::Leaf(page) => Ok(page),
+            _ => Err(Error::WrongNodeType(index, "leaf")),
+        }
+    }
+
+    pub fn dictionary_ref(&self, index: usize) -> Result<&DictionaryPage, Error> {
+        match self.page_ref(index)? {
+            Page::Dictionary(page) => Ok(page),
+            _ => Err(Error::WrongNodeType(index, "dictionary")),
+        }
+    }
+
+    pub fn stats(&self) -> PagerStats {
+        PagerStats {
+            reads: self.reads.load(atomic::Ordering::Relaxed),
+            hits: self.hits.load(atomic::Ordering::Relaxed),
+            syncs: self.syncs,
+        }
+    }
+
+    pub fn reset_stats(&mut self) {
+        *self.reads.get_mut() = 0;
+        *self.hits.get_mut() = 0;
+        self.syncs = 0;
+    }
+
+    /// Reads the whole file, header and pages.
+    pub fn contents(&self) -> Result<Vec<u8>, io::Error> {
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut bytes = Vec::new();
+        file.seek(io::SeekFrom::Start(0))?;
+        file.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Writes every cached page back and flushes the backend.
+    pub fn flush_all(&mut self) -> Result<(), io::Error> {
+        for index in 0..self.pages {
+            self.flush_page(index)?;
         }
+        self.file().flush()
+    }
+
+    /// Drops every page, leaving only the table header in the file.
+    pub fn truncate(&mut self) -> Result<(), io::Error> {
+        self.file().set_len(HEADER_SPACE as u64)?;
+        self.cache = std::array::from_fn(|_| OnceLock::new());
+        self.pages = 0;
+        Ok(())
     }
 
     pub fn flush_page(&mut self, index: usize) -> Result<(), io::Error> {
-        match self.cache[index] {
-            Some(ref mut page) => {
-                self.file.seek(io::SeekFrom::Start(
-                    index as u64 * 4096 + HEADER_SPACE as u64,
-                ))?;
-                self.file.write_all(page.bytes())?;
-            }
-            None => (),
+        if self.read_only {
+            return Ok(());
+        }
+        if let Some(page) = self.cache[index].get() {
+            self.trace("write", index);
+            let file = self.file.get_mut().unwrap_or_else(PoisonError::into_inner);
+            file.seek(io::SeekFrom::Start(
+                index as u64 * 4096 + HEADER_SPACE as u64,
+            ))?;
+            file.write_all(page.bytes())?;
+            self.sync_write(false)?;
         }
         Ok(())
     }
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+/// Storage figures reported by `.stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableStats {
+    pub num_rows: usize,
+    pub pages: usize,
+    pub leaf_pages: usize,
+    pub internal_pages: usize,
+    pub height: usize,
+    /// Average share of the cell space in use across leaf pages.
+    pub fill_factor: f64,
+    pub row_size: usize,
+}
+
+impl std::fmt::Display for TableStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "rows: {}", self.num_rows)?;
+        writeln!(
+            f,
+            "pages: {} ({} leaf, {} internal)",
+            self.pages, self.leaf_pages, self.internal_pages
+        )?;
+        writeln!(f, "tree height: {}", self.height)?;
+        writeln!(f, "leaf fill factor: {:.2}", self.fill_factor)?;
+        write!(f, "row size: {}", self.row_size)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct TableHeader {
     pub name: String,
     pub schema: Schema,
     pub num_rows: usize,
+    /// The dictionary page holding the newest strings, 0 while there are
+    /// none.
+    pub dictionary_page: u32,
+    pub key_type: KeyType,
+    pub indexes: Vec<IndexDefinition>,
+}
+
+/// A secondary index as the header records it. Only the definition is kept
+/// so far; rows are still found through their keys.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IndexDefinition {
+    pub name: String,
+    pub columns: Vec<String>,
 }
 
 #[derive(Debug)]
 pub struct Table {
     pub header: TableHeader,
     pub pages: Pager,
+    /// Strings of the dictionary columns, read from the dictionary pages.
+    pub dictionary: Dictionary,
+    path: PathBuf,
+    // Sequence number of the last header written
+    header_seq: u64,
+    /// Whether inserts convert values to their column's type before checking
+    /// them. Off unless turned on with `.pragma coerce`; not stored in the file.
+    pub coerce: bool,
+    /// Most rows a single scan may look at before failing with
+    /// `Error::RowScanLimitExceeded`. No limit when `None`.
+    pub max_scanned_rows: Option<usize>,
+    /// Rows an `order by` sorts in memory before spilling them to temporary
+    /// files. Set with `.pragma sort_buffer_rows`; not stored in the file.
+    pub sort_buffer_rows: usize,
+    // Set once the table is dropped, after which the handle only fails
+    dropped: bool,
+}
+
+/// Settings for opening a table with `Table::open_with_options`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableOptions {
+    /// Forces every page write to disk with `sync_data`, and every header
+    /// write with `sync_all`, before it returns. A statement that returned
+    /// then survives a power loss. Each page costs a wait on the disk, so
+    /// writes run many times slower than the default, which leaves flushing
+    /// to the OS and can lose the last writes in a crash.
+    pub sync: bool,
+    /// What the row keys of a new table are. An existing table keeps the
+    /// type it was created with.
+    pub key_type: KeyType,
 }
 
 impl Table {
+    /// Opens the table stored at `path`, creating it with `name` and `schema`
+    /// if the file is empty. The path `:memory:` keeps the table in memory.
     pub fn new(name: String, schema: Schema, path: &Path) -> Result<Self, Error> {
-        let mut file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(path)?;
+        Self::open_with_options(name, schema, path, TableOptions::default())
+    }
+
+    /// Like `new`, with `options` applied to the opened table.
+    pub fn open_with_options(
+        name: String,
+        schema: Schema,
+        path: &Path,
+        options: TableOptions,
+    ) -> Result<Self, Error> {
+        check_schema(&name, &schema)?;
+        let file = if path == Path::new(MEMORY_PATH) {
+            Backend::memory()
+        } else {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?
+                .into()
+        };
+        Self::open_backend(name, schema, file, path, options)
+    }
 
-        if file.metadata()?.len() == 0 {
+    // Like `open_with_options`, over a backend that is already open. `path`
+    // is only kept, for renames and clones.
+    pub(crate) fn open_backend(
+        name: String,
+        schema: Schema,
+        mut file: Backend,
+        path: &Path,
+        options: TableOptions,
+    ) -> Result<Self, Error> {
+        if file.is_empty()? {
             let header = TableHeader {
                 name,
                 schema: schema.clone(),
                 num_rows: 0,
+                dictionary_page: 0,
+                key_type: options.key_type,
+                indexes: Vec::new(),
             };
             let mut buffer = vec![0u8; HEADER_SPACE];
-            bincode::serialize_into(&mut buffer[..], &header).unwrap();
-            dbg!(bincode::serialized_size(&header).unwrap());
-            dbg!(bincode::deserialize::<TableHeader>(&buffer).unwrap());
+            let offset = header_slot_offset(1) as usize;
+            buffer[offset..offset + HEADER_SLOT_SIZE].copy_from_slice(&encode_header(&header, 1)?);
 
             file.seek(io::SeekFrom::Start(0))?;
             file.write_all(&buffer)?;
+            if options.sync {
+                file.sync_all()?;
+            }
         }
 
-        dbg!(schema.row_size());
+        let mut table = Self::open(file, path)?;
+        table.pages.sync = options.sync;
+        Ok(table)
+    }
+
+    /// Opens an existing table without write access. Anything that would
+    /// modify it returns `Error::ReadOnly`.
+    pub fn open_read_only(path: &Path) -> Result<Self, Error> {
+        let file = OpenOptions::new().read(true).open(path)?;
+        let mut table = Self::open(file.into(), path)?;
+        table.pages.read_only = true;
+        Ok(table)
+    }
+
+    /// Writes the header and every page to `path` in one pass, so a table in
+    /// memory can be kept. The file can be opened like any other table.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        self.check_exists()?;
+        fs::write(path, self.pages.contents()?)?;
+        Ok(())
+    }
+
+    /// Copies the table byte for byte to `path` once every page and the
+    /// header are written back. Taking `&mut self` keeps writes out until the
+    /// copy is done, so the backup is a consistent snapshot with the same
+    /// page layout.
+    pub fn backup(&mut self, path: &Path) -> Result<(), Error> {
+        self.check_exists()?;
+        self.pages.flush_all()?;
+        self.flush_table_header()?;
+        self.save(path)
+    }
+
+    /// Reads the table saved at `path` into memory. Changes to it are not
+    /// written back to the file.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let bytes = fs::read(path)?;
+        Self::open(Backend::Memory(Cursor::new(bytes)), Path::new(MEMORY_PATH))
+    }
 
+    pub(crate) fn open(mut file: Backend, path: &Path) -> Result<Self, Error> {
         file.seek(io::SeekFrom::Start(0))?;
+        if file.len()? < HEADER_SPACE as u64 {
+            return Err(Error::BadMagic);
+        }
         let mut header = vec![0u8; HEADER_SPACE];
         file.read_exact(&mut header[..])?;
-        let header: TableHeader = bincode::deserialize(&header).unwrap();
-        dbg!(&header.schema);
-        let pages = header
-            .num_rows
-            .div_ceil(crate::PAGE_SIZE / header.schema.row_size());
+        let (header, header_seq) = decode_header(&header)?;
+        // The schema given to `new` is only used for a new file, and one
+        // written elsewhere may not fit this build's pages
+        check_row_size(&header.schema)?;
+        let pages = (file.len()? as usize).saturating_sub(HEADER_SPACE) / crate::PAGE_SIZE;
+        // The pager's cache has a slot for each page a table may have
+        if pages > TABLE_MAX_PAGE {
+            return Err(Error::TooManyPages {
+                pages,
+                limit: TABLE_MAX_PAGE,
+            });
+        }
+        let pages = Pager::new(file, pages as u64)?;
+        let dictionary = read_dictionary(&pages, header.dictionary_page)?;
         Ok(Self {
             header,
-            pages: Pager::new(file, pages as u64)?,
+            pages,
+            dictionary,
+            path: path.to_path_buf(),
+            header_seq,
+            coerce: false,
+            max_scanned_rows: None,
+            sort_buffer_rows: SORT_BUFFER_ROWS,
+            dropped: false,
         })
     }
 
-    pub fn insert(&mut self, _values: InsertStatement) -> Result<(), Error> {
-        let num_rows = self.header.num_rows;
+    /// Inserts a row. The values are checked against the schema here as well
+    /// as when a statement is prepared, so a statement built by hand is
+    /// rejected before any page is allocated or changed.
+    pub fn insert(&mut self, statement: InsertStatement) -> Result<(), Error> {
+        self.check_writable()?;
+        self.header.schema.validate_row(&statement.values)?;
+        self.check_constraints(statement.key, &statement.values)?;
+        self.insert_row(statement.key, statement.values)
+    }
 
-        if num_rows >= self.max_rows() {
-            return Err(Error::RowLimit);
+    /// Inserts every row of `src` into this table under the same key,
+    /// returning how many were copied. Column types must match in order; the
+    /// names may differ. Rows go through the same constraint checks as any
+    /// insert, and a failure stops the copy part way.
+    pub fn copy_from(&mut self, src: &Table) -> Result<usize, Error> {
+        self.check_writable()?;
+        let (dst_schema, src_schema) = (&self.header.schema, &src.header.schema);
+        if dst_schema.feilds.len() != src_schema.feilds.len() {
+            return Err(Error::SchemaMismatch(format!(
+                "{} has {} columns, {} has {}",
+                self.header.name,
+                dst_schema.feilds.len(),
+                src.header.name,
+                src_schema.feilds.len()
+            )));
+        }
+        for ((name, ty), (src_name, src_ty)) in dst_schema.feilds.iter().zip(&src_schema.feilds) {
+            if ty != src_ty {
+                return Err(Error::SchemaMismatch(format!(
+                    "column {name} is {ty} but {src_name} is {src_ty}"
+                )));
+            }
         }
 
-        let row_per_page = self.rows_per_page();
-        let page_index = (num_rows + 1) / row_per_page;
-        let page = self.pages.page(page_index)?;
-        todo!("insert value");
-        self.pages.flush_page(page_index)?;
-        self.header.num_rows += 1;
-        self.flush_table_header()?;
-        self.pages.file.flush()?;
-        Ok(())
+        let rows = src.scan_rows(None)?;
+        for row in &rows {
+            self.check_constraints(row.key(), row.values())?;
+            self.insert_row(row.key(), row.values().to_vec())?;
+        }
+        Ok(rows.len())
     }
 
-    pub fn read(&mut self, index: usize) -> Result<(), Error> {
-        let page_index = (self.header.num_rows + 1) / self.rows_per_page();
-        let index = index % self.rows_per_page();
-        let page = self.pages.page(page_index)?;
-        todo!("read row");
-        // println!(
-        //     "{}",
-        //     values
-        //         .iter()
-        //         .map(|x| format!(" {} ", x))
-        //         .collect::<String>()
-        // );
+    /// Loads rows into an empty table, returning how many were loaded. The
+    /// keys must be strictly increasing. Each row is appended to the last
+    /// leaf, and once that is full a new leaf is started to its right, so
+    /// the tree is built from the leaves up and leaves come out full rather
+    /// than half full from splits. Rows go through the same checks as any
+    /// insert, and a failure stops the load part way.
+    pub fn bulk_load(&mut self, rows: Vec<(i64, Vec<ScalarValue>)>) -> Result<usize, Error> {
+        self.check_writable()?;
+        if self.header.num_rows > 0 {
+            return Err(Error::ConstraintViolation(format!(
+                "bulk load into table {} which has rows",
+                self.header.name
+            )));
+        }
+        // Leaves emptied by deletes would sit in the middle of the new tree
+        self.truncate_pages()?;
 
-        Ok(())
-    }
+        let mut last_leaf = 0;
+        let mut last_key = None;
+        let mut loaded = 0;
+        for (key, values) in rows {
+            if let Some(prev) = last_key.filter(|&prev| key <= prev) {
+                return Err(Error::ConstraintViolation(format!(
+                    "key {key} does not follow {prev}"
+                )));
+            }
+            self.header.schema.validate_row(&values)?;
+            self.check_constraints(key, &values)?;
+            if self.pages.pages == 0 {
+                self.pages.new_leaf_page()?;
+            }
+            self.intern_strings(&values, 0)?;
 
-    pub fn flush_table_header(&mut self) -> Result<(), Error> {
-        let mut buf = vec![0u8; HEADER_SPACE];
-        bincode::serialize_into(&mut buf[..], &self.header)?;
-        self.pages.file.seek(io::SeekFrom::Start(0))?;
-        self.pages.file.write_all(&buf[..])?;
-        Ok(())
+            let cell_size = LeafNode::cell_size(&self.header.schema, &values);
+            if !self.pages.leaf(last_leaf)?.fits(cell_size) {
+                last_leaf = self.append_leaf(last_leaf)?;
+            }
+            let page = self.pages.leaf(last_leaf)?;
+            let split =
+                page.leaf_node_split_and_insert(key, values, &self.header.schema, &self.dictionary);
+            debug_assert!(split.is_none(), "the row was checked to fit");
+            self.header.num_rows += 1;
+            last_key = Some(key);
+            loaded += 1;
+        }
+
+        self.pages.flush_all()?;
+        self.pages.trim_cache()?;
+        self.flush_table_header()?;
+        self.pages.file().flush()?;
+        Ok(loaded)
     }
 
-    pub fn rows_per_page(&self) -> usize {
-        let row_size = self.header.schema.row_size();
-        crate::PAGE_SIZE / row_size
+    // Starts an empty leaf after `last`, the rightmost leaf, for keys above
+    // every key in the tree. Returns the new leaf's page.
+    fn append_leaf(&mut self, last: usize) -> Result<usize, Error> {
+        // Growing the root takes a page for its left child as well
+        let needed = if last == 0 { 2 } else { 1 };
+        if TABLE_MAX_PAGE - self.pages.pages < needed {
+            return Err(Error::RowLimit);
+        }
+        if last != 0 {
+            let root = self.pages.internal(0)?;
+            if root.num_keys() as usize >= root.max_cells() {
+                return Err(Error::RowLimit);
+            }
+        }
+
+        let (new_index, _) = self.pages.new_leaf_page()?;
+        let left = self.pages.leaf(last)?;
+        left.set_next_leaf(new_index);
+        let separator = left.max_key().expect("a full leaf has cells");
+        if last == 0 {
+            self.grow_root(separator, new_index)?;
+        } else {
+            let root = self.pages.internal(0)?;
+            let slot = root.num_keys() as usize;
+            root.insert_cell(slot, last as u32, separator);
+            root.set_right_most_child(new_index);
+        }
+        Ok(new_index as usize)
     }
 
-    pub fn max_rows(&self) -> usize {
-        self.rows_per_page() * crate::TABLE_MAX_PAGE
+    /// Creates the table `name` with the same schema and rows, stored in
+    /// `<name>.db` next to this one. A table in memory is cloned into memory,
+    /// and one sharing its file into a region of the same file.
+    pub fn clone_to(&self, name: String) -> Result<Table, Error> {
+        self.check_exists()?;
+        let mut table = self.create_table(name, self.header.schema.clone())?;
+        table.copy_from(self)?;
+        Ok(table)
+    }
+
+    /// Creates the empty table `name` with `schema`, stored in `<name>.db`
+    /// next to this one, as `create table` does. A table in memory creates
+    /// it in memory, and one sharing its file adds it to the file's catalog.
+    pub fn create_table(&self, name: String, schema: Schema) -> Result<Table, Error> {
+        if let Some(file) = self.pages.shared_file() {
+            return catalog::create_entry(file, &self.path, &name, schema);
+        }
+        let path = if self.pages.is_memory() {
+            PathBuf::from(MEMORY_PATH)
+        } else {
+            let path = self.path.with_file_name(format!("{name}.db"));
+            if path.exists() {
+                return Err(Error::AlreadyExists(format!("table {name}")));
+            }
+            path
+        };
+        Table::new(name, schema, &path)
+    }
+
+    // Places the row in the leaf covering its key, splitting the leaf when
+    // it is full
+    fn insert_row(&mut self, key: i64, values: Vec<ScalarValue>) -> Result<(), Error> {
+        if self.pages.pages == 0 {
+            self.pages.new_leaf_page()?;
+        }
+        let page_index = self.find_leaf(key)?;
+        let cell_size = LeafNode::cell_size(&self.header.schema, &values);
+        // A split takes a page for the new leaf, and one more when the root
+        // is the leaf being split. The pages are counted along with any the
+        // dictionary needs, so the tree is never left half split and no
+        // string is stored for a row that does not fit.
+        let split_pages = match self.pages.leaf(page_index)?.fits(cell_size) {
+            true => 0,
+            false => 2,
+        };
+        self.intern_strings(&values, split_pages)?;
+
+        let page = self.pages.leaf(page_index)?;
+        let mut dirty = vec![page_index];
+        let new_node =
+            page.leaf_node_split_and_insert(key, values, &self.header.schema, &self.dictionary);
+        if let Some(new_node) = new_node {
+            dirty.extend(self.split_leaf(page_index, new_node)?);
+        }
+        for page_index in dirty {
+            self.pages.flush_page(page_index)?;
+        }
+        self.pages.trim_cache()?;
+        self.header.num_rows += 1;
+        self.flush_table_header()?;
+        self.pages.file().flush()?;
+        Ok(())
+    }
+
+    // Adds the strings of the row's dictionary columns to the dictionary,
+    // writing any new ones to the newest dictionary page, or to a new page
+    // once that is full. Fails with nothing written when the new pages and
+    // `reserved` more would not fit in the table. A string is only interned
+    // once its page is written, so it never gets an id the file lacks.
+    fn intern_strings(&mut self, values: &[ScalarValue], reserved: usize) -> Result<(), Error> {
+        let schema = &self.header.schema;
+        let mut new: Vec<String> = Vec::new();
+        for (i, ((_, ty), value)) in schema.feilds.iter().zip(values).enumerate() {
+            if let (true, ScalarValue::String(value)) = (schema.is_dictionary(i), value) {
+                let value = codec::dictionary_string(value, ty);
+                if self.dictionary.id(&value).is_none() && !new.contains(&value) {
+                    new.push(value);
+                }
+            }
+        }
+        if new.is_empty() {
+            return Ok(());
+        }
+
+        let newest = self.header.dictionary_page as usize;
+        let mut free = match newest {
+            0 => 0,
+            _ => self.pages.dictionary_ref(newest)?.free_space(),
+        };
+        let mut needed = 0;
+        for value in &new {
+            let size = DictionaryPage::entry_size(value);
+            if size > free {
+                needed += 1;
+                free = DictionaryPage::SPACE;
+            }
+            free -= size;
+        }
+        if TABLE_MAX_PAGE - self.pages.pages < needed + reserved {
+            return Err(Error::RowLimit);
+        }
+
+        for value in new {
+            let newest = self.header.dictionary_page as usize;
+            if newest == 0 || !self.pages.dictionary(newest)?.fits(&value) {
+                let (index, page) = self.pages.new_dictionary_page()?;
+                page.set_prev_page(newest as u32);
+                self.header.dictionary_page = index;
+            }
+            let newest = self.header.dictionary_page as usize;
+            self.pages.dictionary(newest)?.push(&value);
+            self.pages.flush_page(newest)?;
+            self.dictionary.intern(&value);
+        }
+        Ok(())
+    }
+
+    // Builds a tree of `rows` laid out by `schema` in memory, away from the
+    // table's pages, so a row that fails leaves the table as it was. The
+    // result goes in with `swap_in`.
+    fn rebuild(
+        &self,
+        schema: Schema,
+        rows: impl IntoIterator<Item = (i64, Vec<ScalarValue>)>,
+    ) -> Result<Table, Error> {
+        let mut rebuilt = Table::open_backend(
+            self.header.name.clone(),
+            schema,
+            Backend::memory(),
+            Path::new(MEMORY_PATH),
+            TableOptions::default(),
+        )?;
+        for (key, values) in rows {
+            rebuilt.insert_row(key, values)?;
+        }
+        rebuilt.pages.flush_all()?;
+        Ok(rebuilt)
+    }
+
+    // Replaces every page, and the schema, with those of `rebuilt`
+    fn swap_in(&mut self, rebuilt: Table) -> Result<(), Error> {
+        let contents = rebuilt.pages.contents()?;
+        self.truncate_pages()?;
+        let file = self.pages.file();
+        file.seek(io::SeekFrom::Start(HEADER_SPACE as u64))?;
+        file.write_all(&contents[HEADER_SPACE..])?;
+        self.pages.sync_write(false)?;
+        self.pages.pages = rebuilt.pages.pages;
+        self.dictionary = rebuilt.dictionary;
+        self.header.schema = rebuilt.header.schema;
+        self.header.num_rows = rebuilt.header.num_rows;
+        self.header.dictionary_page = rebuilt.header.dictionary_page;
+        self.flush_table_header()
+    }
+
+    // Drops every page, the dictionary's included
+    fn truncate_pages(&mut self) -> Result<(), Error> {
+        self.pages.truncate()?;
+        self.dictionary = Dictionary::default();
+        self.header.dictionary_page = 0;
+        Ok(())
+    }
+
+    /// Removes the row with `key`. Leaves are never merged, so a leaf can be
+    /// left empty until later inserts fill it again.
+    pub fn delete(&mut self, key: i64) -> Result<(), Error> {
+        self.check_writable()?;
+        self.delete_row(key)?;
+        self.flush_table_header()?;
+        self.pages.file().flush()?;
+        Ok(())
+    }
+
+    /// Removes every row `predicate` accepts and returns how many went. The
+    /// matching keys are collected by a scan before anything is removed, so
+    /// cells shifting left as rows go can't make the walk skip any, and a
+    /// predicate error leaves the table as it was.
+    pub fn delete_where(
+        &mut self,
+        mut predicate: impl FnMut(&Row) -> Result<bool, Error>,
+    ) -> Result<usize, Error> {
+        self.check_writable()?;
+        let mut keys = Vec::new();
+        self.for_each(|row| {
+            if predicate(&row)? {
+                keys.push(row.key());
+            }
+            Ok(())
+        })?;
+
+        for &key in &keys {
+            self.delete_row(key)?;
+        }
+        // The scan may have cached pages no row was deleted from
+        self.pages.trim_cache()?;
+        self.flush_table_header()?;
+        self.pages.file().flush()?;
+        Ok(keys.len())
+    }
+
+    fn delete_row(&mut self, key: i64) -> Result<(), Error> {
+        if self.pages.pages == 0 {
+            return Err(Error::NotFound(format!("key {key}")));
+        }
+        let page_index = self.find_leaf(key)?;
+        let page = self.pages.leaf(page_index)?;
+        let index = page
+            .binary_search(key)
+            .map_err(|_| Error::NotFound(format!("key {key}")))?;
+        page.remove_cell(index);
+        self.pages.flush_page(page_index)?;
+        self.pages.trim_cache()?;
+        self.header.num_rows -= 1;
+        Ok(())
+    }
+
+    // Gives the right half of a split leaf its own page and links it into the
+    // leaf chain and the parent. Returns the other pages that changed.
+    fn split_leaf(&mut self, page_index: usize, new_node: LeafNode) -> Result<Vec<usize>, Error> {
+        let (new_index, page) = self.pages.new_leaf_page()?;
+        page.bytes.copy_from_slice(&*new_node.bytes);
+
+        let left = self.pages.leaf(page_index)?;
+        left.set_next_leaf(new_index);
+        let separator = left.max_key().expect("a split leaf keeps cells");
+
+        if page_index == 0 {
+            let left_index = self.grow_root(separator, new_index)?;
+            return Ok(vec![0, left_index as usize, new_index as usize]);
+        }
+
+        let parent_index = left.parent() as usize;
+        let parent = self.pages.internal(parent_index)?;
+        // With TABLE_MAX_PAGE pages an internal node can not fill up, so
+        // internal nodes are never split.
+        if parent.num_keys() as usize >= parent.max_cells() {
+            return Err(Error::RowLimit);
+        }
+        let slot = parent.find_child(separator);
+        parent.insert_cell(slot, page_index as u32, separator);
+        parent.set_child(slot + 1, new_index);
+        Ok(vec![parent_index, new_index as usize])
+    }
+
+    // The root stays at page 0, so when the root leaf gains a right sibling
+    // its cells move to a new left child and it becomes an internal node
+    // over the two. Returns the left child's page.
+    fn grow_root(&mut self, separator: i64, right: u32) -> Result<u32, Error> {
+        let bytes = self.pages.leaf(0)?.bytes.clone();
+        let (left_index, left) = self.pages.new_leaf_page()?;
+        left.bytes.copy_from_slice(&*bytes);
+        self.pages.leaf(right as usize)?.set_parent(0);
+
+        let mut root = InternalNode::new(bytes);
+        root.bytes.fill(0);
+        root.initialize();
+        root.set_root_node();
+        root.insert_cell(0, left_index, separator);
+        root.set_right_most_child(right);
+        self.pages.cache[0] = OnceLock::from(Page::Intermediate(root));
+        Ok(left_index)
+    }
+
+    // Descends from the root to the leaf whose key range holds `key`
+    fn find_leaf(&self, key: i64) -> Result<usize, Error> {
+        let mut page_index = 0;
+        while let Page::Intermediate(node) = self.pages.page_ref(page_index)? {
+            page_index = node.children(node.find_child(key), Pos::Left) as usize;
+        }
+        Ok(page_index)
+    }
+
+    pub fn read(&self, key: i64) -> Result<Row<'_>, Error> {
+        self.check_exists()?;
+        // An empty table has no root page to descend from
+        if self.pages.pages == 0 {
+            return Err(Error::NotFound(format!("key {key}")));
+        }
+        let page_index = self.find_leaf(key)?;
+        let page = self.pages.leaf_ref(page_index)?;
+        page.binary_search(key)
+            .ok()
+            .and_then(|index| page.read_row(index, &self.header.schema, &self.dictionary))
+            .ok_or_else(|| Error::NotFound(format!("key {key}")))
+    }
+
+    /// Looks up each key in turn, returning the rows found in the order of
+    /// `keys`. Keys with no row are skipped.
+    pub fn read_keys(&self, keys: &[i64]) -> Result<Vec<Row<'_>>, Error> {
+        self.check_exists()?;
+        let mut rows = Vec::with_capacity(keys.len());
+        if self.pages.pages == 0 {
+            return Ok(rows);
+        }
+        for &key in keys {
+            let page = self.pages.leaf_ref(self.find_leaf(key)?)?;
+            let index = page.binary_search(key).ok();
+            rows.extend(
+                index.and_then(|index| page.read_row(index, &self.header.schema, &self.dictionary)),
+            );
+        }
+        Ok(rows)
+    }
+
+    /// Returns every row of the table in key order, following the leaf chain.
+    pub fn scan(&self) -> Result<Vec<Row<'_>>, Error> {
+        self.scan_rows(self.max_scanned_rows)
+    }
+
+    /// Returns every key in order, following the leaf chain without decoding
+    /// any values.
+    pub fn key_scan(&self) -> Result<Vec<i64>, Error> {
+        self.check_exists()?;
+        let mut keys = Vec::with_capacity(self.header.num_rows);
+        if self.pages.pages == 0 {
+            return Ok(keys);
+        }
+
+        let mut page_index = self.find_leaf(i64::MIN)?;
+        loop {
+            let page = self.pages.leaf_ref(page_index)?;
+            keys.extend(page.keys());
+            match page.next_leaf() {
+                0 => break,
+                next => page_index = next as usize,
+            }
+        }
+        Ok(keys)
+    }
+
+    // Scans for the table's own bookkeeping pass no limit, since a query
+    // limit shouldn't stop them
+    fn scan_rows(&self, limit: Option<usize>) -> Result<Vec<Row<'_>>, Error> {
+        let mut rows = Vec::with_capacity(self.header.num_rows);
+        self.for_each_row(limit, |row| {
+            rows.push(row);
+            Ok(())
+        })?;
+        Ok(rows)
+    }
+
+    /// Returns the row at position `n` in key order, whatever its key. Whole
+    /// leaves are skipped by their cell count, so only that row is decoded.
+    pub fn nth(&self, mut n: usize) -> Result<Option<Row<'_>>, Error> {
+        self.check_exists()?;
+        if self.pages.pages == 0 {
+            return Ok(None);
+        }
+
+        let mut page_index = self.find_leaf(i64::MIN)?;
+        loop {
+            let page = self.pages.leaf_ref(page_index)?;
+            let num_cells = page.num_cells() as usize;
+            if n < num_cells {
+                return Ok(page.read_row(n, &self.header.schema, &self.dictionary));
+            }
+            n -= num_cells;
+            match page.next_leaf() {
+                0 => return Ok(None),
+                next => page_index = next as usize,
+            }
+        }
+    }
+
+    /// Counts the rows `predicate` matches, as a select's `where` would pick
+    /// them. Rows are decoded one at a time and dropped straight after, so
+    /// nothing is collected.
+    pub fn count_where(&self, predicate: &Expr) -> Result<usize, Error> {
+        let mut count = 0;
+        self.for_each(|row| {
+            if predicate.matches(&row)? {
+                count += 1;
+            }
+            Ok(())
+        })?;
+        Ok(count)
+    }
+
+    /// Hands every row to `f` in key order, decoding one at a time so no
+    /// more than a row is held. The walk stops at the first error `f`
+    /// returns.
+    pub fn for_each<'a>(
+        &'a self,
+        f: impl FnMut(Row<'a>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.for_each_row(self.max_scanned_rows, f)
+    }
+
+    // Walks the leaf chain, handing every row to `f` in key order. Looking
+    // at more than `limit` rows fails.
+    fn for_each_row<'a>(
+        &'a self,
+        limit: Option<usize>,
+        mut f: impl FnMut(Row<'a>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.check_exists()?;
+        if self.pages.pages == 0 {
+            return Ok(());
+        }
+
+        let limit = limit.unwrap_or(usize::MAX);
+        let mut scanned = 0;
+        let mut page_index = self.find_leaf(i64::MIN)?;
+        loop {
+            let page = self.pages.leaf_ref(page_index)?;
+            for row in page.cells(&self.header.schema, &self.dictionary) {
+                scanned += 1;
+                if scanned > limit {
+                    return Err(Error::RowScanLimitExceeded(limit));
+                }
+                f(row)?;
+            }
+            // Page 0 is the root, so it never follows another leaf
+            match page.next_leaf() {
+                0 => break,
+                next => page_index = next as usize,
+            }
+        }
+        Ok(())
+    }
+
+    fn check_constraints(&mut self, key: i64, values: &[ScalarValue]) -> Result<(), Error> {
+        if self.pages.pages > 0 {
+            let page_index = self.find_leaf(key)?;
+            if self.pages.leaf(page_index)?.binary_search(key).is_ok() {
+                return Err(Error::ConstraintViolation(format!("duplicate key {key}")));
+            }
+        }
+
+        let schema = &self.header.schema;
+        for ((name, _), (options, value)) in
+            schema.feilds.iter().zip(schema.options.iter().zip(values))
+        {
+            if options.not_null && *value == ScalarValue::Null {
+                return Err(Error::ConstraintViolation(format!(
+                    "column {name} can not be null"
+                )));
+            }
+        }
+
+        let unique: Vec<usize> = (0..schema.feilds.len())
+            .filter(|&i| schema.options[i].unique && values[i] != ScalarValue::Null)
+            .collect();
+        if unique.is_empty() {
+            return Ok(());
+        }
+
+        for row in self.scan_rows(None)? {
+            let options = &row.schema().options;
+            if let Some(&i) = unique
+                .iter()
+                .find(|&&i| options[i].collation.equals(&row[i], &values[i]))
+            {
+                let (name, _) = &row.schema().feilds[i];
+                return Err(Error::ConstraintViolation(format!(
+                    "duplicate value {} for unique column {name}",
+                    values[i]
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn stats(&mut self) -> Result<TableStats, Error> {
+        let mut leaf_pages = 0;
+        let mut internal_pages = 0;
+        let mut fill = 0.0;
+        for page_index in 0..self.pages.pages {
+            match self.pages.page(page_index)? {
+                Page::Leaf(page) => {
+                    leaf_pages += 1;
+                    let used = LeafNode::SPACE_FOR_CELLS - page.free_space();
+                    fill += used as f64 / LeafNode::SPACE_FOR_CELLS as f64;
+                }
+                Page::Intermediate(_) => internal_pages += 1,
+                Page::Dictionary(_) => {}
+            }
+        }
+
+        Ok(TableStats {
+            num_rows: self.header.num_rows,
+            pages: self.pages.pages,
+            leaf_pages,
+            internal_pages,
+            height: self.tree_height()?,
+            fill_factor: if leaf_pages == 0 {
+                0.0
+            } else {
+                fill / leaf_pages as f64
+            },
+            row_size: self.header.schema.row_size(),
+        })
+    }
+
+    /// Levels from the root down to the leaves: 1 while the root is a leaf,
+    /// 0 for a table with no pages. Every leaf is at the same depth, so this
+    /// is how many pages a key lookup reads.
+    pub fn tree_height(&self) -> Result<usize, Error> {
+        if self.pages.pages == 0 {
+            return Ok(0);
+        }
+
+        // Follows the leftmost children from the root down to a leaf
+        let mut height = 1;
+        let mut page_index = 0;
+        while let Page::Intermediate(node) = self.pages.page_ref(page_index)? {
+            page_index = node.children(0, Pos::Left) as usize;
+            height += 1;
+        }
+        Ok(height)
+    }
+
+    /// Walks the tree and checks its structure: keys in every node are sorted
+    /// and unique, no node holds more cells than fit, each separator key is
+    /// the largest key under its child, the leaf chain visits every leaf once
+    /// in key order, and the leaves hold `num_rows` rows. The first problem
+    /// found is returned as `Error::InvariantViolation`.
+    pub fn check_invariants(&self) -> Result<(), Error> {
+        if self.pages.pages == 0 {
+            return Ok(());
+        }
+
+        let mut visited = vec![false; self.pages.pages];
+        let mut leaves = Vec::new();
+        let rows = self.check_node(0, None, None, &mut visited, &mut leaves)?;
+        if rows != self.header.num_rows {
+            return Err(Error::InvariantViolation(format!(
+                "leaves hold {rows} rows, header says {}",
+                self.header.num_rows
+            )));
+        }
+
+        let mut page_index = leaves[0];
+        for (i, &expected) in leaves.iter().enumerate() {
+            if page_index != expected {
+                return Err(Error::InvariantViolation(format!(
+                    "leaf chain reaches page {page_index} where page {expected} comes next"
+                )));
+            }
+            page_index = self.pages.leaf_ref(page_index)?.next_leaf() as usize;
+            if page_index == 0 && i + 1 < leaves.len() {
+                return Err(Error::InvariantViolation(format!(
+                    "leaf chain ends after {} of {} leaves",
+                    i + 1,
+                    leaves.len()
+                )));
+            }
+        }
+        if page_index != 0 {
+            return Err(Error::InvariantViolation(format!(
+                "last leaf {} points on to page {page_index}",
+                leaves[leaves.len() - 1]
+            )));
+        }
+        Ok(())
+    }
+
+    // Checks the subtree at `page_index`, whose keys must be above `low` and
+    // at most `high`. Leaves are collected in key order. Returns the number
+    // of rows under it.
+    fn check_node(
+        &self,
+        page_index: usize,
+        low: Option<i64>,
+        high: Option<i64>,
+        visited: &mut [bool],
+        leaves: &mut Vec<usize>,
+    ) -> Result<usize, Error> {
+        let violation = |message: String| Err(Error::InvariantViolation(message));
+        match visited.get_mut(page_index) {
+            None => return violation(format!("page {page_index} is past the end of the file")),
+            Some(true) => return violation(format!("page {page_index} is reached twice")),
+            Some(seen) => *seen = true,
+        }
+        let in_bounds =
+            |key: i64| low.is_none_or(|low| key > low) && high.is_none_or(|high| key <= high);
+
+        let keys: Vec<i64> = match self.pages.page_ref(page_index)? {
+            Page::Leaf(page) => {
+                let (cells, max_cells) = (page.num_cells() as usize, page.max_cells());
+                if cells > max_cells {
+                    return violation(format!(
+                        "leaf {page_index} holds {cells} cells, at most {max_cells} fit"
+                    ));
+                }
+                if let Some(i) = (0..cells).find(|&i| !page.slot_in_bounds(i)) {
+                    return violation(format!(
+                        "leaf {page_index} has cell {i} outside its cell area"
+                    ));
+                }
+                page.keys().collect()
+            }
+            Page::Intermediate(node) => {
+                let (num_keys, max_cells) = (node.num_keys() as usize, node.max_cells());
+                if num_keys > max_cells {
+                    return violation(format!(
+                        "internal node {page_index} holds {num_keys} keys, at most {max_cells} fit"
+                    ));
+                }
+                (0..num_keys).map(|i| node.key(i)).collect()
+            }
+            Page::Dictionary(_) => {
+                return violation(format!("dictionary page {page_index} is in the tree"))
+            }
+        };
+
+        if let Some(pair) = keys.windows(2).find(|pair| pair[0] >= pair[1]) {
+            return violation(format!(
+                "page {page_index} has key {} before key {}",
+                pair[0], pair[1]
+            ));
+        }
+        if let Some(key) = keys.iter().find(|&&key| !in_bounds(key)) {
+            return violation(format!(
+                "page {page_index} has key {key} outside its parent's range"
+            ));
+        }
+
+        let Page::Intermediate(node) = self.pages.page_ref(page_index)? else {
+            leaves.push(page_index);
+            return Ok(keys.len());
+        };
+        // Child i holds the keys up to separator i, the right most child the
+        // keys past the last separator
+        let mut rows = 0;
+        let mut child_low = low;
+        for i in 0..=keys.len() {
+            let child_high = keys.get(i).copied().or(high);
+            let child = node.children(i, Pos::Left) as usize;
+            rows += self.check_node(child, child_low, child_high, visited, leaves)?;
+            child_low = child_high;
+        }
+        Ok(rows)
+    }
+
+    /// Renames the table along with its backing file, which becomes
+    /// `<name>.db` next to the current one. A table sharing its file only
+    /// changes its name.
+    pub fn rename(&mut self, name: String) -> Result<(), Error> {
+        self.check_writable()?;
+        self.check_header(|header| header.name.clone_from(&name))?;
+        if !self.pages.owns_file() {
+            if let Some(region) = self.pages.region() {
+                region.rename_in_catalog(&name)?;
+            }
+            self.header.name = name;
+            return self.flush_table_header();
+        }
+
+        let path = self.path.with_file_name(format!("{name}.db"));
+        if path.exists() {
+            return Err(Error::AlreadyExists(format!("table {name}")));
+        }
+
+        fs::rename(&self.path, &path)?;
+        self.path = path;
+        self.header.name = name;
+        self.flush_table_header()
+    }
+
+    /// Deletes the backing file. A table sharing its file is taken out of
+    /// the file's catalog instead. Anything done with the handle afterwards
+    /// fails with `Error::NotFound`.
+    pub fn drop_table(&mut self) -> Result<(), Error> {
+        self.check_writable()?;
+        self.header.num_rows = 0;
+        self.truncate_pages()?;
+        if self.pages.owns_file() {
+            fs::remove_file(&self.path)?;
+        } else if let Some(region) = self.pages.region() {
+            region.remove_from_catalog()?;
+        }
+        self.dropped = true;
+        Ok(())
+    }
+
+    /// Appends a column to the schema and rewrites every row in the new
+    /// layout, filling the column with its default (or null) for existing
+    /// rows.
+    pub fn add_column(
+        &mut self,
+        name: String,
+        ty: DataType,
+        options: ColumnOptions,
+    ) -> Result<(), Error> {
+        self.check_writable()?;
+        if self.header.schema.index_of(&name).is_some() {
+            return Err(Error::AlreadyExists(format!("column {name}")));
+        }
+        check_column(&name, &ty, &options)?;
+
+        // Every existing row gets the same value for the new column
+        let backfill = options.default.clone().unwrap_or(ScalarValue::Null);
+        if options.not_null && backfill == ScalarValue::Null && self.header.num_rows > 0 {
+            return Err(Error::ConstraintViolation(format!(
+                "column {name} can not be null"
+            )));
+        }
+        if options.unique && backfill != ScalarValue::Null && self.header.num_rows > 1 {
+            return Err(Error::ConstraintViolation(format!(
+                "duplicate value {backfill} for unique column {name}"
+            )));
+        }
+
+        let mut schema = self.header.schema.clone();
+        schema.feilds.push((name, ty));
+        schema.options.push(options);
+        check_row_size(&schema)?;
+        self.check_header(|header| header.schema = schema.clone())?;
+        // Rows go back in key order, which leaves every split leaf half full
+        let rows_per_page = LeafNode::capacity(schema.row_size()) / 2;
+        if self.header.num_rows > rows_per_page * (TABLE_MAX_PAGE - 1) {
+            return Err(Error::RowLimit);
+        }
+
+        // The row size changes, so every row is decoded with the old schema
+        // before any page gets rewritten.
+        let rows: Vec<(i64, Vec<ScalarValue>)> = self
+            .scan_rows(None)?
+            .into_iter()
+            .map(|row| {
+                let key = row.key();
+                let mut values = row.into_values();
+                values.push(backfill.clone());
+                (key, values)
+            })
+            .collect();
+
+        let rebuilt = self.rebuild(schema, rows)?;
+        self.swap_in(rebuilt)
+    }
+
+    /// Rebuilds the tree from the rows stored in its leaves, for when the
+    /// links between pages are broken. Every leaf page is read directly,
+    /// without following the tree or the leaf chain, and the rows go back in
+    /// key order. A key found in more than one leaf is kept once. Returns the
+    /// number of rows.
+    pub fn reindex(&mut self) -> Result<usize, Error> {
+        self.check_writable()?;
+        let value_size = self.header.schema.row_size();
+        let mut rows = BTreeMap::new();
+        for index in 0..self.pages.pages {
+            if let Page::Leaf(page) = self.pages.page_ref(index)? {
+                let cells = (page.num_cells() as usize).min(page.max_cells());
+                for cell in (0..cells).filter(|&cell| page.slot_in_bounds(cell)) {
+                    if let Some(row) = page.read_row(cell, &self.header.schema, &self.dictionary) {
+                        rows.entry(row.key()).or_insert_with(|| row.into_values());
+                    }
+                }
+            }
+        }
+        // Rows go back in key order, which leaves every split leaf half full
+        if rows.len() > LeafNode::capacity(value_size) / 2 * (TABLE_MAX_PAGE - 1) {
+            return Err(Error::RowLimit);
+        }
+
+        let count = rows.len();
+        let rebuilt = self.rebuild(self.header.schema.clone(), rows)?;
+        self.swap_in(rebuilt)?;
+        Ok(count)
+    }
+
+    /// Removes every row while keeping the schema.
+    pub fn truncate(&mut self) -> Result<(), Error> {
+        self.check_writable()?;
+        self.header.num_rows = 0;
+        self.truncate_pages()?;
+        self.flush_table_header()
+    }
+
+    pub fn rename_column(&mut self, column: &str, name: String) -> Result<(), Error> {
+        self.check_writable()?;
+        if self.header.schema.index_of(&name).is_some() {
+            return Err(Error::AlreadyExists(format!("column {name}")));
+        }
+
+        let index = self
+            .header
+            .schema
+            .index_of(column)
+            .ok_or_else(|| Error::UnknownColumn(column.to_string()))?;
+        self.check_header(|header| header.schema.feilds[index].0.clone_from(&name))?;
+        let schema = &mut self.header.schema;
+        let column = std::mem::replace(&mut schema.feilds[index].0, name.clone());
+        for index in &mut self.header.indexes {
+            for indexed in &mut index.columns {
+                if *indexed == column {
+                    indexed.clone_from(&name);
+                }
+            }
+        }
+        self.flush_table_header()
+    }
+
+    /// Records the index `name` over `columns`, which must all exist.
+    pub fn create_index(&mut self, name: String, columns: Vec<String>) -> Result<(), Error> {
+        self.check_writable()?;
+        if self.header.indexes.iter().any(|index| index.name == name) {
+            return Err(Error::AlreadyExists(format!("index {name}")));
+        }
+        if let Some(column) = columns
+            .iter()
+            .find(|column| self.schema().index_of(column).is_none())
+        {
+            return Err(Error::UnknownColumn(column.clone()));
+        }
+        let index = IndexDefinition { name, columns };
+        self.check_header(|header| header.indexes.push(index.clone()))?;
+        self.header.indexes.push(index);
+        self.flush_table_header()
+    }
+
+    /// The indexes defined on the table, in the order they were created.
+    pub fn indexes(&self) -> &[IndexDefinition] {
+        &self.header.indexes
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Fails with `Error::HeaderTooLarge` when the header, changed by
+    // `change`, would not fit its slot. Run before anything changes, so a
+    // header that can't be written leaves the table as it was.
+    fn check_header(&self, change: impl FnOnce(&mut TableHeader)) -> Result<(), Error> {
+        let mut header = self.header.clone();
+        change(&mut header);
+        encode_header(&header, self.header_seq + 1).map(drop)
+    }
+
+    pub fn flush_table_header(&mut self) -> Result<(), Error> {
+        if self.pages.read_only {
+            return Ok(());
+        }
+        let seq = self.header_seq + 1;
+        let slot = encode_header(&self.header, seq)?;
+        let file = self.pages.file();
+        file.seek(io::SeekFrom::Start(header_slot_offset(seq)))?;
+        file.write_all(&slot)?;
+        self.pages.sync_write(true)?;
+        self.header_seq = seq;
+        Ok(())
+    }
+
+    pub fn rows_per_page(&self) -> usize {
+        LeafNode::capacity(self.header.schema.row_size())
+    }
+
+    /// Upper bound on the rows the table can hold, with every page but the
+    /// root a full leaf.
+    pub fn max_rows(&self) -> usize {
+        self.rows_per_page() * (TABLE_MAX_PAGE - 1)
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.pages.read_only
+    }
+
+    fn check_writable(&self) -> Result<(), Error> {
+        self.check_exists()?;
+        if self.pages.read_only {
+            return Err(Error::ReadOnly);
+        }
+        Ok(())
+    }
+
+    fn check_exists(&self) -> Result<(), Error> {
+        if self.dropped {
+            return Err(Error::NotFound(format!("table {}", self.header.name)));
+        }
+        Ok(())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.header.name
+    }
+
+    /// How many rows the table holds, as kept in its header.
+    pub fn len(&self) -> usize {
+        self.header.num_rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.header.num_rows == 0
     }
 
     pub fn schema(&self) -> &Schema {
         &self.header.schema
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::{
-        fs::{self, OpenOptions},
-        io::Write,
-    };
+    pub fn key_type(&self) -> KeyType {
+        self.header.key_type
+    }
+
+    /// The value a row key stands for under the table's key type.
+    pub fn key_value(&self, key: i64) -> ScalarValue {
+        self.header.key_type.decode(key)
+    }
+}
+
+fn header_slot_offset(seq: u64) -> u64 {
+    seq % 2 * HEADER_SLOT_SIZE as u64
+}
+
+fn encode_header(header: &TableHeader, seq: u64) -> Result<Vec<u8>, Error> {
+    let body = bincode::serialize(header)?;
+    if body.len() > HEADER_SLOT_SIZE - HEADER_SLOT_PREFIX {
+        return Err(Error::HeaderTooLarge {
+            size: body.len(),
+            limit: HEADER_SLOT_SIZE - HEADER_SLOT_PREFIX,
+        });
+    }
+    let mut slot = vec![0u8; HEADER_SLOT_SIZE];
+    slot[0..4].copy_from_slice(&MAGIC);
+    slot[4..8].copy_from_slice(&FORMAT_VERSION.to_ne_bytes());
+    slot[8..16].copy_from_slice(&seq.to_ne_bytes());
+    slot[16..20].copy_from_slice(&header_checksum(seq, &body).to_ne_bytes());
+    slot[20..24].copy_from_slice(&(body.len() as u32).to_ne_bytes());
+    slot[HEADER_SLOT_PREFIX..HEADER_SLOT_PREFIX + body.len()].copy_from_slice(&body);
+    Ok(slot)
+}
+
+/// Picks the newest header slot that is intact, returning the header and
+/// its sequence number. Space with no slot marked as a table's is not a
+/// table, while slots that are marked but all fail their checksum are a
+/// damaged one.
+fn decode_header(space: &[u8]) -> Result<(TableHeader, u64), Error> {
+    // (version, seq, serialized header) of a slot that passes its checksum
+    fn slot(slot: &[u8]) -> Option<(u32, u64, &[u8])> {
+        if slot[0..4] != MAGIC {
+            return None;
+        }
+        let version = u32::from_ne_bytes(slot[4..8].try_into().unwrap());
+        let seq = u64::from_ne_bytes(slot[8..16].try_into().unwrap());
+        let checksum = u32::from_ne_bytes(slot[16..20].try_into().unwrap());
+        let len = u32::from_ne_bytes(slot[20..24].try_into().unwrap()) as usize;
+        let body = slot.get(HEADER_SLOT_PREFIX..HEADER_SLOT_PREFIX + len)?;
+        if seq == 0 || checksum != header_checksum(seq, body) {
+            return None;
+        }
+        Some((version, seq, body))
+    }
+
+    let newest = space
+        .chunks(HEADER_SLOT_SIZE)
+        .filter_map(slot)
+        .max_by_key(|(_, seq, _)| *seq);
+    match newest {
+        Some((version, _, _)) if version != FORMAT_VERSION => Err(Error::UnsupportedVersion {
+            found: version,
+            supported: FORMAT_VERSION,
+        }),
+        Some((_, seq, body)) => Ok((bincode::deserialize(body)?, seq)),
+        None if space
+            .chunks(HEADER_SLOT_SIZE)
+            .any(|slot| slot[0..4] == MAGIC) =>
+        {
+            Err(Error::CorruptHeader)
+        }
+        None => Err(Error::BadMagic),
+    }
+}
+
+// FNV-1a over the sequence number and the serialized header
+fn header_checksum(seq: u64, body: &[u8]) -> u32 {
+    seq.to_ne_bytes()
+        .iter()
+        .chain(body)
+        .fold(0x811c9dc5, |hash, byte| {
+            (hash ^ *byte as u32).wrapping_mul(0x01000193)
+        })
+}
+
+/// Checks that a new table `name` could be created with `schema`.
+pub(crate) fn check_schema(name: &str, schema: &Schema) -> Result<(), Error> {
+    if schema.feilds.is_empty() {
+        return Err(Error::InvalidSchema(format!("table {name} has no columns")));
+    }
+    for ((column, ty), options) in schema.feilds.iter().zip(&schema.options) {
+        check_column(column, ty, options)?;
+    }
+    check_row_size(schema)?;
+    let header = TableHeader {
+        name: name.to_string(),
+        schema: schema.clone(),
+        num_rows: 0,
+        dictionary_page: 0,
+        key_type: KeyType::default(),
+        indexes: Vec::new(),
+    };
+    encode_header(&header, 1).map(drop)
+}
+
+// Checks that a column's options suit its type
+fn check_column(name: &str, ty: &DataType, options: &ColumnOptions) -> Result<(), Error> {
+    if options.compact && *ty != DataType::Number {
+        return Err(Error::InvalidSchema(format!(
+            "compact column {name} is not a number"
+        )));
+    }
+    if options.dictionary && !ty.is_text() {
+        return Err(Error::InvalidSchema(format!(
+            "dictionary column {name} is not text"
+        )));
+    }
+    if let Some(default) = &options.default {
+        if !ty.accepts(default) {
+            return Err(Error::TypeMismatch {
+                column: name.to_string(),
+                expected: ty.clone(),
+                got: default.type_name(),
+            });
+        }
+    }
+    Ok(())
+}
+
+// A leaf has to hold at least one row, and a dictionary page any one string
+// of a dictionary column
+fn check_row_size(schema: &Schema) -> Result<(), Error> {
+    let row_size = schema.row_size();
+    if LeafNode::capacity(row_size) == 0 {
+        return Err(Error::RowTooLarge {
+            row_size,
+            page_size: crate::PAGE_SIZE,
+        });
+    }
+    for (i, (name, ty)) in schema.feilds.iter().enumerate() {
+        if schema.is_dictionary(i) && ty.size() > DictionaryPage::MAX_ENTRY_SIZE {
+            return Err(Error::InvalidSchema(format!(
+                "dictionary column {name} is wider than a page"
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Reads the strings of the dictionary pages, following the chain back from
+// the newest page at `newest`
+fn read_dictionary(pages: &Pager, newest: u32) -> Result<Dictionary, Error> {
+    let mut chain = Vec::new();
+    let mut page_index = newest as usize;
+    while page_index != 0 {
+        let page = pages.dictionary_ref(page_index)?;
+        chain.push(page);
+        page_index = page.prev_page() as usize;
+    }
+
+    let mut dictionary = Dictionary::default();
+    for page in chain.into_iter().rev() {
+        for s in page.entries() {
+            dictionary.intern(s);
+        }
+    }
+    Ok(dictionary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, OpenOptions},
+        io::{self, Seek, Write},
+        path::Path,
+        sync::{Arc, Mutex, RwLock},
+        thread,
+    };
+
+    use crate::{
+        datatype::{Collation, ColumnOptions, DataType, KeyType, ScalarValue, Schema},
+        errors::Error,
+        expr::Expr,
+        statement::InsertStatement,
+        tree::LeafNode,
+        PAGE_SIZE, TABLE_MAX_PAGE,
+    };
+
+    use super::{
+        encode_header, header_slot_offset, Page, Pager, PagerStats, Table, TableHeader,
+        TableOptions, FORMAT_VERSION, HEADER_SLOT_PREFIX, HEADER_SLOT_SIZE, HEADER_SPACE,
+        MEMORY_PATH,
+    };
+
+    fn temp_table(name: &str, schema: Schema) -> Table {
+        let path = std::env::temp_dir().join(format!("{name}.db"));
+        let _ = fs::remove_file(&path);
+        let table = Table::new(name.to_string(), schema, &path).unwrap();
+        fs::remove_file(path).unwrap();
+        table
+    }
+
+    fn insert(table: &mut Table, values: Vec<ScalarValue>) -> Result<(), Error> {
+        // Keys follow insertion order
+        let key = table.header.num_rows as i64;
+        table.insert(InsertStatement {
+            key,
+            values,
+            returning: None,
+        })
+    }
+
+    #[test]
+    fn pager_test() {
+        let path = std::env::temp_dir().join("glob.db");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .unwrap();
+
+        file.set_len(HEADER_SPACE as u64).unwrap();
+        let mut pager = Pager::new(file.try_clone().unwrap(), 0).unwrap();
+        let (_, page) = pager.new_leaf_page().unwrap();
+        page.bytes.fill(1u8);
+        let (_, page) = pager.new_leaf_page().unwrap();
+        page.bytes.fill(2u8);
+        // Keep a valid node type so the page can be read back
+        page.bytes[0] = 0;
+        pager.flush_page(0).unwrap();
+        pager.flush_page(1).unwrap();
+        pager.file().flush().unwrap();
+
+        drop(pager);
+        let mut pager = Pager::new(file, 2).unwrap();
+        assert_eq!(pager.page(0).unwrap().bytes(), vec![1u8; 4096].as_slice());
+        let mut expected = vec![2u8; 4096];
+        expected[0] = 0;
+        assert_eq!(pager.page(1).unwrap().bytes(), expected.as_slice());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn not_null_rejects_null() {
+        let mut schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        schema.options[1] = ColumnOptions {
+            not_null: true,
+            ..Default::default()
+        };
+        let mut table = temp_table("not_null_rejects_null", schema);
+
+        insert(
+            &mut table,
+            vec![ScalarValue::Null, ScalarValue::String("x".to_string())],
+        )
+        .unwrap();
+        let res = insert(&mut table, vec![ScalarValue::Number(1), ScalarValue::Null]);
+        assert!(matches!(res, Err(Error::ConstraintViolation(_))));
+        assert_eq!(table.header.num_rows, 1);
+    }
+
+    #[test]
+    fn unique_rejects_duplicate() {
+        let mut schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        schema.options[0] = ColumnOptions {
+            unique: true,
+            ..Default::default()
+        };
+        let mut table = temp_table("unique_rejects_duplicate", schema);
+
+        insert(
+            &mut table,
+            vec![ScalarValue::Number(1), ScalarValue::String("x".to_string())],
+        )
+        .unwrap();
+        insert(
+            &mut table,
+            vec![ScalarValue::Number(2), ScalarValue::String("x".to_string())],
+        )
+        .unwrap();
+        let res = insert(
+            &mut table,
+            vec![ScalarValue::Number(1), ScalarValue::String("y".to_string())],
+        );
+        assert!(matches!(res, Err(Error::ConstraintViolation(_))));
+
+        // Nulls never collide with each other
+        insert(
+            &mut table,
+            vec![ScalarValue::Null, ScalarValue::String("z".to_string())],
+        )
+        .unwrap();
+        insert(
+            &mut table,
+            vec![ScalarValue::Null, ScalarValue::String("z".to_string())],
+        )
+        .unwrap();
+        assert_eq!(table.header.num_rows, 4);
+    }
+
+    #[test]
+    fn rename_table_and_column() {
+        let dir = std::env::temp_dir();
+        let path = dir.join("rename_table_before.db");
+        let renamed = dir.join("rename_table_after.db");
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&renamed);
+
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let mut table =
+            Table::new("rename_table_before".to_string(), schema.clone(), &path).unwrap();
+        table.rename("rename_table_after".to_string()).unwrap();
+        table.rename_column("b", "c".to_string()).unwrap();
+        assert!(matches!(
+            table.rename_column("a", "c".to_string()),
+            Err(Error::AlreadyExists(_))
+        ));
+        drop(table);
+
+        assert!(!path.exists());
+        let table = Table::new("ignored".to_string(), schema, &renamed).unwrap();
+        assert_eq!(table.header.name, "rename_table_after");
+        assert_eq!(table.schema().index_of("c"), Some(1));
+        assert_eq!(table.schema().index_of("b"), None);
+
+        fs::remove_file(renamed).unwrap();
+    }
+
+    #[test]
+    fn rename_table_collision() {
+        let dir = std::env::temp_dir();
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut first = temp_table("rename_collision_first", schema.clone());
+        let other = dir.join("rename_collision_second.db");
+        let _ = fs::remove_file(&other);
+        let _second = Table::new("rename_collision_second".to_string(), schema, &other).unwrap();
+
+        assert!(matches!(
+            first.rename("rename_collision_second".to_string()),
+            Err(Error::AlreadyExists(_))
+        ));
+        fs::remove_file(other).unwrap();
+    }
+
+    #[test]
+    fn add_column_backfills_default() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = temp_table("add_column_backfills_default", schema);
+        for i in 0..3 {
+            insert(&mut table, vec![ScalarValue::Number(i)]).unwrap();
+        }
+
+        table
+            .add_column(
+                "b".to_string(),
+                DataType::String(10),
+                ColumnOptions {
+                    default: Some(ScalarValue::String("new".to_string())),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        table
+            .add_column("c".to_string(), DataType::Number, ColumnOptions::default())
+            .unwrap();
+        insert(
+            &mut table,
+            vec![
+                ScalarValue::Number(3),
+                ScalarValue::String("x".to_string()),
+                ScalarValue::Number(30),
+            ],
+        )
+        .unwrap();
+
+        let rows = table.scan().unwrap();
+        assert_eq!(rows.len(), 4);
+        for (i, row) in rows[..3].iter().enumerate() {
+            assert_eq!(row["a"], ScalarValue::Number(i as i64));
+            assert_eq!(row["b"], ScalarValue::String("new".to_string()));
+            assert_eq!(row["c"], ScalarValue::Null);
+        }
+        assert_eq!(rows[3]["c"], ScalarValue::Number(30));
+        assert_eq!(
+            table.schema().options[1].default,
+            Some(ScalarValue::String("new".to_string()))
+        );
+    }
+
+    #[test]
+    fn failed_add_column_keeps_rows() {
+        let mut schema = Schema::new(vec![("a".to_string(), DataType::String(200))]);
+        schema.options[0].dictionary = true;
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        let text = |i: i64| ScalarValue::String(format!("{i:x<199}"));
+        let mut count = 0;
+        while insert(&mut table, vec![text(count)]).is_ok() {
+            count += 1;
+        }
+
+        // The dictionary pages fill the table, so the wider rows need more
+        // leaves than are left
+        let err = table
+            .add_column("b".to_string(), DataType::Number, ColumnOptions::default())
+            .unwrap_err();
+        assert!(matches!(err, Error::RowLimit));
+        assert_eq!(table.schema().feilds.len(), 1);
+        assert_eq!(table.len(), count as usize);
+        table.check_invariants().unwrap();
+        for row in table.scan().unwrap() {
+            assert_eq!(row["a"], text(row.key()));
+        }
+    }
+
+    #[test]
+    fn nocase_collation() {
+        let nocase = Collation::NoCase;
+        let alice = ScalarValue::String("Alice".to_string());
+        assert!(nocase.equals(&alice, &ScalarValue::String("alice".to_string())));
+        assert!(!Collation::Binary.equals(&alice, &ScalarValue::String("alice".to_string())));
+
+        let mut schema = Schema::new(vec![("name".to_string(), DataType::String(10))]);
+        schema.options[0] = ColumnOptions {
+            unique: true,
+            collation: Collation::NoCase,
+            ..Default::default()
+        };
+        let mut table = temp_table("nocase_collation", schema);
+        insert(&mut table, vec![alice]).unwrap();
+        let res = insert(&mut table, vec![ScalarValue::String("ALICE".to_string())]);
+        assert!(matches!(res, Err(Error::ConstraintViolation(_))));
+    }
+
+    #[test]
+    fn stats_counts_rows_and_pages() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = temp_table("stats_counts_rows_and_pages", schema);
+        let empty = table.stats().unwrap();
+        assert_eq!((empty.num_rows, empty.pages, empty.height), (0, 0, 0));
+
+        for i in 0..10 {
+            insert(&mut table, vec![ScalarValue::Number(i)]).unwrap();
+        }
+        let stats = table.stats().unwrap();
+        assert_eq!(stats.num_rows, 10);
+        assert_eq!(stats.pages, 1);
+        assert_eq!((stats.leaf_pages, stats.internal_pages), (1, 0));
+        assert_eq!(stats.height, 1);
+        assert_eq!(stats.row_size, 9);
+        let used = 10 * (LeafNode::SLOT_SIZE + LeafNode::KEY_SIZE + stats.row_size);
+        assert_eq!(
+            stats.fill_factor,
+            used as f64 / LeafNode::SPACE_FOR_CELLS as f64
+        );
+    }
+
+    #[test]
+    fn insert_checks_values_before_storage() {
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        assert!(matches!(
+            insert(&mut table, vec![ScalarValue::Number(1)]),
+            Err(Error::ArityMismatch {
+                expected: 2,
+                got: 1
+            })
+        ));
+        assert!(matches!(
+            insert(&mut table, vec![ScalarValue::Number(1), ScalarValue::Number(2)]),
+            Err(Error::TypeMismatch { column, .. }) if column == "b"
+        ));
+        assert_eq!(table.pages.pages(), 0);
+        assert_eq!(table.header.num_rows, 0);
+
+        insert(&mut table, vec![ScalarValue::Number(1), ScalarValue::Null]).unwrap();
+        assert_eq!(table.pages.pages(), 1);
+    }
+
+    #[test]
+    fn negative_keys_scan_in_order() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        let keys = |table: &Table| -> Vec<i64> {
+            table.scan().unwrap().iter().map(|row| row.key()).collect()
+        };
+        for key in [5, -5, 0] {
+            let values = vec![ScalarValue::Number(key)];
+            let statement = InsertStatement {
+                key,
+                values,
+                returning: None,
+            };
+            table.insert(statement).unwrap();
+        }
+        assert_eq!(keys(&table), [-5, 0, 5]);
+        assert_eq!(table.read(-5).unwrap()[0], ScalarValue::Number(-5));
+
+        // Separators in internal nodes order negative keys too
+        let count = table.rows_per_page() as i64 * 2;
+        for key in (-count..-5).rev().chain([i64::MIN, i64::MAX]) {
+            let values = vec![ScalarValue::Number(key)];
+            let statement = InsertStatement {
+                key,
+                values,
+                returning: None,
+            };
+            table.insert(statement).unwrap();
+        }
+        let mut expected: Vec<i64> = (-count..-5).chain([-5, 0, 5]).collect();
+        expected.insert(0, i64::MIN);
+        expected.push(i64::MAX);
+        assert_eq!(keys(&table), expected);
+        table.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn key_scan_matches_scan() {
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(20)),
+        ]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        assert!(table.key_scan().unwrap().is_empty());
+
+        // Out of order, so the keys land on leaves apart from insert order
+        let count = table.rows_per_page() as i64 * 3;
+        for i in 0..count {
+            let key = i * 7 % count - count / 2;
+            let values = vec![ScalarValue::Number(i), ScalarValue::String(format!("r{i}"))];
+            let statement = InsertStatement {
+                key,
+                values,
+                returning: None,
+            };
+            table.insert(statement).unwrap();
+        }
+        assert!(table.stats().unwrap().leaf_pages > 1);
+
+        let keys: Vec<i64> = table.scan().unwrap().iter().map(|row| row.key()).collect();
+        assert_eq!(table.key_scan().unwrap(), keys);
+        assert_eq!(keys.len(), count as usize);
+    }
+
+    #[test]
+    fn new_table_is_empty() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        assert_eq!(table.name(), "t");
+        assert!(table.is_empty());
+        assert_eq!(table.len(), 0);
+
+        let statement = InsertStatement {
+            key: 1,
+            values: vec![ScalarValue::Number(1)],
+            returning: None,
+        };
+        table.insert(statement).unwrap();
+        assert!(!table.is_empty());
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn read_from_empty_table() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        for key in [0, -1, i64::MIN, i64::MAX] {
+            assert!(matches!(table.read(key), Err(Error::NotFound(_))));
+        }
+
+        let statement = InsertStatement {
+            key: 1,
+            values: vec![ScalarValue::Number(1)],
+            returning: None,
+        };
+        table.insert(statement).unwrap();
+        table.truncate().unwrap();
+        assert!(matches!(table.read(1), Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn bulk_load_matches_inserts() {
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(20)),
+        ]);
+        let mut inserted =
+            Table::new("t".to_string(), schema.clone(), Path::new(MEMORY_PATH)).unwrap();
+        let count = inserted.rows_per_page() as i64 * 4;
+        let rows: Vec<(i64, Vec<ScalarValue>)> = (0..count)
+            .map(|key| {
+                let b = ScalarValue::String(format!("r{key}"));
+                (key * 3, vec![ScalarValue::Number(key % 17), b])
+            })
+            .collect();
+        for (key, values) in rows.clone() {
+            let statement = InsertStatement {
+                key,
+                values,
+                returning: None,
+            };
+            inserted.insert(statement).unwrap();
+        }
+
+        let mut loaded = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        assert_eq!(loaded.bulk_load(rows.clone()).unwrap(), rows.len());
+        loaded.check_invariants().unwrap();
+        let contents = |table: &Table| -> Vec<(i64, Vec<ScalarValue>)> {
+            let rows = table.scan().unwrap();
+            rows.iter()
+                .map(|row| (row.key(), row.values().to_vec()))
+                .collect()
+        };
+        assert_eq!(contents(&loaded), rows);
+        assert_eq!(contents(&loaded), contents(&inserted));
+
+        // Splits leave every leaf but the last half full, the loader fills them
+        let (inserted, loaded_stats) = (inserted.stats().unwrap(), loaded.stats().unwrap());
+        assert!(loaded_stats.leaf_pages < inserted.leaf_pages);
+        assert!(loaded_stats.fill_factor > inserted.fill_factor);
+
+        // Later inserts split the full leaves as usual
+        for key in [1, count * 3] {
+            let statement = InsertStatement {
+                key,
+                values: vec![ScalarValue::Number(0), ScalarValue::Null],
+                returning: None,
+            };
+            loaded.insert(statement).unwrap();
+        }
+        loaded.check_invariants().unwrap();
+        assert_eq!(loaded.header.num_rows, rows.len() + 2);
+    }
+
+    #[test]
+    fn bulk_load_rejects_bad_input() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        let row = |key: i64| (key, vec![ScalarValue::Number(key)]);
+        assert!(matches!(
+            table.bulk_load(vec![row(1), row(3), row(2)]),
+            Err(Error::ConstraintViolation(_))
+        ));
+        // Rows before the bad key stay loaded
+        assert_eq!(table.key_scan().unwrap(), [1, 3]);
+        assert!(matches!(
+            table.bulk_load(vec![row(4)]),
+            Err(Error::ConstraintViolation(_))
+        ));
+
+        table.delete_where(|_| Ok(true)).unwrap();
+        assert_eq!(table.bulk_load(vec![row(5), row(6)]).unwrap(), 2);
+        assert_eq!(table.key_scan().unwrap(), [5, 6]);
+        table.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn tree_height_after_root_split() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        assert_eq!(table.tree_height().unwrap(), 0);
+        insert(&mut table, vec![ScalarValue::Number(0)]).unwrap();
+        assert_eq!(table.tree_height().unwrap(), 1);
+
+        for i in 1..=table.rows_per_page() as i64 {
+            assert_eq!(table.tree_height().unwrap(), 1);
+            insert(&mut table, vec![ScalarValue::Number(i)]).unwrap();
+        }
+        // One row past a full leaf splits the root
+        assert!(matches!(
+            table.pages.page(0).unwrap(),
+            Page::Intermediate(_)
+        ));
+        assert_eq!(table.tree_height().unwrap(), 2);
+    }
+
+    #[test]
+    fn typed_page_access() {
+        let path = std::env::temp_dir().join("typed_page_access.db");
+        let _ = fs::remove_file(&path);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .unwrap();
+        file.set_len(HEADER_SPACE as u64).unwrap();
+
+        let mut pager = Pager::new(file.try_clone().unwrap(), 0).unwrap();
+        let (_, page) = pager.new_leaf_page().unwrap();
+        // Turn the page into an internal node on disk
+        page.bytes[0] = 1;
+        pager.flush_page(0).unwrap();
+        drop(pager);
+
+        let mut pager = Pager::new(file, 1).unwrap();
+        assert!(matches!(pager.leaf(0), Err(Error::WrongNodeType(0, _))));
+        assert!(pager.internal(0).is_ok());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn trace_keyed_lookup() {
+        #[derive(Clone, Default)]
+        struct Capture(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
 
-    use super::{Pager, HEADER_SPACE};
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let path = std::env::temp_dir().join("trace_keyed_lookup.db");
+        let _ = fs::remove_file(&path);
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema.clone(), &path).unwrap();
+        for _ in 0..table.rows_per_page() * 2 {
+            insert(&mut table, vec![ScalarValue::Number(1)]).unwrap();
+        }
+        let key = table.header.num_rows as i64 - 1;
+        let leaf = table.find_leaf(key).unwrap();
+        assert_ne!(leaf, 0);
+        drop(table);
+
+        // Reopened, so the cache starts out empty
+        let mut table = Table::new("t".to_string(), schema, &path).unwrap();
+        let capture = Capture::default();
+        table.pages.set_trace(Some(Box::new(capture.clone())));
+        table.read(key).unwrap();
+        table.read(key).unwrap();
+        let trace = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        // The descent looks at the leaf to find it is one, then the read
+        // takes it again from the cache
+        let lookup =
+            |first: &str| format!("{first} page 0\n{first} page {leaf}\nhit page {leaf}\n");
+        assert_eq!(trace, lookup("read") + &lookup("hit"));
+
+        table.pages.set_trace(None);
+        table.read(key).unwrap();
+        assert_eq!(capture.0.lock().unwrap().len(), trace.len());
+        fs::remove_file(path).unwrap();
+    }
 
     #[test]
-    fn pager_test() {
-        let path = std::env::temp_dir().join("glob.db");
+    fn pager_counts_reads_and_hits() {
+        let path = std::env::temp_dir().join("pager_counts_reads_and_hits.db");
+        let _ = fs::remove_file(&path);
         let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create_new(true)
             .open(&path)
             .unwrap();
-
         file.set_len(HEADER_SPACE as u64).unwrap();
+        fs::remove_file(path).unwrap();
+
         let mut pager = Pager::new(file.try_clone().unwrap(), 0).unwrap();
-        let (_, page) = pager.new_leaf_page().unwrap();
-        (&mut *page.bytes).fill_with(|| 1u8);
-        let (_, page) = pager.new_leaf_page().unwrap();
-        (&mut *page.bytes).fill_with(|| 2u8);
+        pager.new_leaf_page().unwrap();
         pager.flush_page(0).unwrap();
-        pager.flush_page(1).unwrap();
-        pager.file.flush().unwrap();
-
         drop(pager);
-        let mut pager = Pager::new(file, 2).unwrap();
-        assert_eq!(pager.page(0).unwrap().bytes(), vec![1u8; 4096].as_slice());
-        assert_eq!(pager.page(1).unwrap().bytes(), vec![2u8; 4096].as_slice());
 
+        let mut pager = Pager::new(file, 1).unwrap();
+        pager.page(0).unwrap();
+        assert_eq!(
+            pager.stats(),
+            PagerStats {
+                reads: 1,
+                hits: 0,
+                syncs: 0
+            }
+        );
+        pager.page(0).unwrap();
+        assert_eq!(
+            pager.stats(),
+            PagerStats {
+                reads: 1,
+                hits: 1,
+                syncs: 0
+            }
+        );
+
+        pager.reset_stats();
+        assert_eq!(pager.stats(), PagerStats::default());
+    }
+
+    #[test]
+    fn truncate_keeps_schema() {
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let mut table = temp_table("truncate_keeps_schema", schema.clone());
+        for i in 0..3 {
+            insert(
+                &mut table,
+                vec![ScalarValue::Number(i), ScalarValue::String("x".to_string())],
+            )
+            .unwrap();
+        }
+
+        table.truncate().unwrap();
+        assert!(table.scan().unwrap().is_empty());
+        assert_eq!(table.schema(), &schema);
+
+        insert(
+            &mut table,
+            vec![ScalarValue::Number(7), ScalarValue::String("y".to_string())],
+        )
+        .unwrap();
+        let rows = table.scan().unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["a"], ScalarValue::Number(7));
+    }
+
+    #[test]
+    fn keyed_inserts_split_leaves() {
+        let path = std::env::temp_dir().join("keyed_inserts_split_leaves.db");
+        let _ = fs::remove_file(&path);
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema.clone(), &path).unwrap();
+
+        // Enough rows for a handful of leaves, inserted out of key order
+        let count = table.rows_per_page() as i64 * 4;
+        for i in 0..count {
+            let key = (i * 37) % count;
+            let values = vec![ScalarValue::Number(key as i64 * 10)];
+            table
+                .insert(InsertStatement {
+                    key,
+                    values,
+                    returning: None,
+                })
+                .unwrap();
+        }
+        assert!(table.pages.pages > 4);
+        drop(table);
+
+        let table = Table::new("t".to_string(), schema, &path).unwrap();
+        fs::remove_file(path).unwrap();
+        let keys: Vec<i64> = table.scan().unwrap().iter().map(|row| row.key()).collect();
+        assert_eq!(keys, (0..count).collect::<Vec<_>>());
+        for key in 0..count {
+            assert_eq!(table.read(key).unwrap()["a"], ScalarValue::Number(key * 10));
+        }
+    }
+
+    #[test]
+    fn read_only_table() {
+        let path = std::env::temp_dir().join("read_only_table.db");
+        let _ = fs::remove_file(&path);
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, &path).unwrap();
+        insert(&mut table, vec![ScalarValue::Number(1)]).unwrap();
+        drop(table);
+        let len = fs::metadata(&path).unwrap().len();
+
+        let mut table = Table::open_read_only(&path).unwrap();
+        assert!(table.is_read_only());
+        assert_eq!(table.read(0).unwrap()["a"], ScalarValue::Number(1));
+        assert!(matches!(
+            insert(&mut table, vec![ScalarValue::Number(2)]),
+            Err(Error::ReadOnly)
+        ));
+        assert!(matches!(table.truncate(), Err(Error::ReadOnly)));
+        assert_eq!(table.scan().unwrap().len(), 1);
+        drop(table);
+
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn readers_share_the_table() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        let table = Arc::new(RwLock::new(table));
+        let count = 500;
+
+        let writer = {
+            let table = Arc::clone(&table);
+            thread::spawn(move || {
+                for key in 0..count {
+                    let values = vec![ScalarValue::Number(key)];
+                    let statement = InsertStatement {
+                        key,
+                        values,
+                        returning: None,
+                    };
+                    table.write().unwrap().insert(statement).unwrap();
+                }
+            })
+        };
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let table = Arc::clone(&table);
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        let table = table.read().unwrap();
+                        let keys: Vec<i64> =
+                            table.scan().unwrap().iter().map(|row| row.key()).collect();
+                        // Every read sees some prefix of the writes
+                        assert_eq!(keys, (0..keys.len() as i64).collect::<Vec<_>>());
+                    }
+                })
+            })
+            .collect();
+
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        // Two read guards can be held at once
+        let first = table.read().unwrap();
+        let second = table.try_read().unwrap();
+        assert_eq!(first.scan().unwrap().len(), count as usize);
+        assert_eq!(second.read(7).unwrap().key(), 7);
+    }
+
+    #[test]
+    fn clone_table() {
+        let dir = std::env::temp_dir();
+        let (path, clone_path) = (dir.join("clone_src.db"), dir.join("clone_dst.db"));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&clone_path);
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let mut src = Table::new("clone_src".to_string(), schema, &path).unwrap();
+        for i in 0..5 {
+            insert(
+                &mut src,
+                vec![ScalarValue::Number(i), ScalarValue::String(i.to_string())],
+            )
+            .unwrap();
+        }
+
+        let mut clone = src.clone_to("clone_dst".to_string()).unwrap();
+        assert_eq!(clone.path(), clone_path);
+        assert_eq!(clone.schema(), src.schema());
+        let rows = |table: &Table| -> Vec<(i64, Vec<ScalarValue>)> {
+            let rows = table.scan().unwrap();
+            rows.into_iter()
+                .map(|row| (row.key(), row.into_values()))
+                .collect()
+        };
+        assert_eq!(rows(&clone), rows(&src));
+
+        // The two files are independent of each other
+        insert(&mut clone, vec![ScalarValue::Number(5), ScalarValue::Null]).unwrap();
+        assert_eq!(clone.scan().unwrap().len(), 6);
+        assert_eq!(src.scan().unwrap().len(), 5);
+
+        assert!(matches!(
+            src.clone_to("clone_dst".to_string()),
+            Err(Error::AlreadyExists(_))
+        ));
+        fs::remove_file(path).unwrap();
+        fs::remove_file(clone_path).unwrap();
+    }
+
+    #[test]
+    fn zero_column_schema() {
+        let path = std::env::temp_dir().join("zero_column_schema.db");
+        let _ = fs::remove_file(&path);
+        assert!(matches!(
+            Table::new("t".to_string(), Schema::new(vec![]), &path),
+            Err(Error::InvalidSchema(_))
+        ));
+        // Rejected before the file is created
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn row_too_large() {
+        let memory = Path::new(MEMORY_PATH);
+        let schema = Schema::new(vec![("a".to_string(), DataType::String(5000))]);
+        assert!(matches!(
+            Table::new("t".to_string(), schema, memory),
+            Err(Error::RowTooLarge {
+                row_size: 5001,
+                page_size: 4096
+            })
+        ));
+
+        let schema = Schema::new(vec![("a".to_string(), DataType::String(4000))]);
+        let mut table = Table::new("t".to_string(), schema, memory).unwrap();
+        assert!(matches!(
+            table.add_column(
+                "b".to_string(),
+                DataType::String(100),
+                ColumnOptions::default()
+            ),
+            Err(Error::RowTooLarge { .. })
+        ));
+        assert_eq!(table.schema().feilds.len(), 1);
+    }
+
+    #[test]
+    fn header_too_large() {
+        let path = std::env::temp_dir().join("header_too_large.db");
+        let _ = fs::remove_file(&path);
+        let column = |i: usize| (format!("column_{i:03}"), DataType::Number);
+        let schema = Schema::new((0..100).map(column).collect());
+        assert!(matches!(
+            Table::new("t".to_string(), schema, &path),
+            Err(Error::HeaderTooLarge { limit, .. }) if limit == HEADER_SLOT_SIZE - HEADER_SLOT_PREFIX
+        ));
+        // Rejected before the file is created
+        assert!(!path.exists());
+
+        // Columns go on until the header is full, and the one that doesn't
+        // fit leaves the table as it was
+        let schema = Schema::new((0..10).map(column).collect());
+        let mut table = Table::new("t".to_string(), schema, &path).unwrap();
+        table
+            .insert(InsertStatement {
+                key: 1,
+                values: vec![ScalarValue::Number(1); 10],
+                returning: None,
+            })
+            .unwrap();
+        let mut columns = 10;
+        let err = loop {
+            let (name, ty) = column(columns);
+            match table.add_column(name, ty, ColumnOptions::default()) {
+                Ok(()) => columns += 1,
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, Error::HeaderTooLarge { .. }), "{err}");
+        assert_eq!(table.schema().feilds.len(), columns);
+        let long = "x".repeat(HEADER_SLOT_SIZE);
+        assert!(matches!(
+            table.create_index(long.clone(), vec!["column_000".to_string()]),
+            Err(Error::HeaderTooLarge { .. })
+        ));
+        assert!(table.indexes().is_empty());
+        assert!(matches!(
+            table.rename(long),
+            Err(Error::HeaderTooLarge { .. })
+        ));
+        assert_eq!(table.name(), "t");
+        drop(table);
+
+        let schema = Schema::new(vec![column(0)]);
+        let table = Table::new("t".to_string(), schema, &path).unwrap();
+        assert_eq!(table.schema().feilds.len(), columns);
+        assert_eq!(table.read(1).unwrap()["column_009"], ScalarValue::Number(1));
+        assert_eq!(table.read(1).unwrap()[columns - 1], ScalarValue::Null);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn header_row_too_large() {
+        let path = std::env::temp_dir().join("header_row_too_large.db");
+        let header = TableHeader {
+            name: "t".to_string(),
+            schema: Schema::new(vec![("a".to_string(), DataType::String(5000))]),
+            num_rows: 1,
+            dictionary_page: 0,
+            key_type: KeyType::Number,
+            indexes: Vec::new(),
+        };
+        let mut bytes = vec![0u8; HEADER_SPACE + PAGE_SIZE];
+        let offset = header_slot_offset(1) as usize;
+        bytes[offset..offset + HEADER_SLOT_SIZE]
+            .copy_from_slice(&encode_header(&header, 1).unwrap());
+        fs::write(&path, bytes).unwrap();
+
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        assert!(matches!(
+            Table::new("t".to_string(), schema, &path),
+            Err(Error::RowTooLarge {
+                row_size: 5001,
+                page_size: 4096
+            })
+        ));
+        assert!(matches!(
+            Table::open_read_only(&path),
+            Err(Error::RowTooLarge { .. })
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn torn_header_write_keeps_previous_header() {
+        let path = std::env::temp_dir().join("torn_header_write.db");
+        let _ = fs::remove_file(&path);
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, &path).unwrap();
+        insert(&mut table, vec![ScalarValue::Number(1)]).unwrap();
+        insert(&mut table, vec![ScalarValue::Number(2)]).unwrap();
+        let seq = table.header_seq;
+        drop(table);
+
+        // Cut the newest copy off half way through, as a crash mid-write would
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        let offset = header_slot_offset(seq) + HEADER_SLOT_PREFIX as u64 + 4;
+        file.seek(io::SeekFrom::Start(offset)).unwrap();
+        file.write_all(&[0u8; 64]).unwrap();
+        drop(file);
+
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, &path).unwrap();
+        assert_eq!(table.header.num_rows, 1);
+        assert_eq!(table.header_seq, seq - 1);
+        // The next write goes over the torn slot
+        let values = vec![ScalarValue::Number(3)];
+        table
+            .insert(InsertStatement {
+                key: 5,
+                values,
+                returning: None,
+            })
+            .unwrap();
+        assert_eq!(table.header_seq, seq);
+        drop(table);
+        let table = Table::open_read_only(&path).unwrap();
+        assert_eq!(table.header.num_rows, 2);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn dictionary_strings_are_shared() {
+        let path = std::env::temp_dir().join("dictionary_strings_are_shared.db");
+        let _ = fs::remove_file(&path);
+        let mut schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        schema.options[1].dictionary = true;
+        let mut table = Table::new("t".to_string(), schema, &path).unwrap();
+        assert_eq!(table.schema().row_size(), 1 + 8 + 4);
+
+        let text = |s: &str| ScalarValue::String(s.to_string());
+        for (a, b) in [(1, text("red")), (2, text("red")), (3, ScalarValue::Null)] {
+            insert(&mut table, vec![ScalarValue::Number(a), b]).unwrap();
+        }
+        assert_eq!(table.dictionary.len(), 1);
+        assert_eq!(table.read(0).unwrap()["b"], text("red"));
+        assert_eq!(table.read(1).unwrap()["b"], text("red"));
+        assert_eq!(table.read(2).unwrap()["b"], ScalarValue::Null);
+
+        // Enough distinct strings to fill more than one dictionary page. A
+        // string is cut to the column like any other.
+        for i in 0..1000 {
+            let values = vec![ScalarValue::Number(i), text(&format!("{i:x<12}"))];
+            insert(&mut table, values).unwrap();
+        }
+        assert_eq!(table.dictionary.len(), 1001);
+        table.check_invariants().unwrap();
+        drop(table);
+
+        let table = Table::open_read_only(&path).unwrap();
+        assert_eq!(table.dictionary.len(), 1001);
+        assert_eq!(table.read(1).unwrap()["b"], text("red"));
+        for (i, row) in table.scan().unwrap().iter().skip(3).enumerate() {
+            assert_eq!(row["b"], text(&format!("{i:x<12}")[..9]));
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn full_table_interns_nothing() {
+        let path = std::env::temp_dir().join("full_table_interns_nothing.db");
+        let _ = fs::remove_file(&path);
+        let mut schema = Schema::new(vec![("b".to_string(), DataType::String(200))]);
+        schema.options[0].dictionary = true;
+        let mut table = Table::new("t".to_string(), schema, &path).unwrap();
+
+        let text = |i: i64| ScalarValue::String(format!("{i:x<199}"));
+        let mut i = 0;
+        let err = loop {
+            match insert(&mut table, vec![text(i)]) {
+                Ok(()) => i += 1,
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, Error::RowLimit));
+        // The string of the row that did not fit is not kept, in memory or
+        // in the file
+        assert_eq!(table.dictionary.len(), i as usize);
+        table.check_invariants().unwrap();
+        drop(table);
+
+        let table = Table::open_read_only(&path).unwrap();
+        assert_eq!(table.dictionary.len(), i as usize);
+        for row in table.scan().unwrap() {
+            assert_eq!(row["b"], text(row.key()));
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn page_count_comes_from_file_size() {
+        let path = std::env::temp_dir().join("page_count_comes_from_file_size.db");
+        let _ = fs::remove_file(&path);
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, &path).unwrap();
+        for i in 0..table.rows_per_page() as i64 * 2 {
+            insert(&mut table, vec![ScalarValue::Number(i)]).unwrap();
+        }
+        let pages = table.pages.pages();
+        drop(table);
+
+        // Two pages past the tree that no row accounts for, each marked so
+        // an overwrite shows
+        let mut extra = LeafNode::new();
+        extra.bytes[PAGE_SIZE - 1] = 0xab;
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&extra.bytes[..]).unwrap();
+        file.write_all(&extra.bytes[..]).unwrap();
+        drop(file);
+
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, &path).unwrap();
+        assert_eq!(table.pages.pages(), pages + 2);
+        let (index, _) = table.pages.new_leaf_page().unwrap();
+        assert_eq!(index as usize, pages + 2);
+        table.pages.flush_all().unwrap();
+        drop(table);
+
+        let bytes = fs::read(&path).unwrap();
+        for page in [pages, pages + 1] {
+            let end = HEADER_SPACE + (page + 1) * PAGE_SIZE;
+            assert_eq!(bytes[end - 1], 0xab);
+        }
+        assert_eq!(bytes.len(), HEADER_SPACE + (pages + 3) * PAGE_SIZE);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn file_past_page_limit() {
+        let path = std::env::temp_dir().join("file_past_page_limit.db");
+        let _ = fs::remove_file(&path);
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema.clone(), &path).unwrap();
+        insert(&mut table, vec![ScalarValue::Number(1)]).unwrap();
+        drop(table);
+
+        // Every page taken: the table opens, but has no room for another
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        let len = |pages: usize| (HEADER_SPACE + pages * PAGE_SIZE) as u64;
+        file.set_len(len(TABLE_MAX_PAGE)).unwrap();
+        let mut table = Table::new("t".to_string(), schema.clone(), &path).unwrap();
+        assert!(matches!(table.pages.new_leaf_page(), Err(Error::RowLimit)));
+        drop(table);
+
+        file.set_len(len(TABLE_MAX_PAGE + 1)).unwrap();
+        assert!(matches!(
+            Table::new("t".to_string(), schema, &path),
+            Err(Error::TooManyPages { pages, limit: TABLE_MAX_PAGE }) if pages == TABLE_MAX_PAGE + 1
+        ));
+        assert!(matches!(
+            Table::open_read_only(&path),
+            Err(Error::TooManyPages { .. })
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn nth_row_in_key_order() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        assert!(table.nth(0).unwrap().is_none());
+
+        // Spread over several leaves, inserted out of key order
+        let count = table.rows_per_page() as i64 * 3;
+        for i in 0..count {
+            let key = (i * 7) % count * 2 + 10;
+            let values = vec![ScalarValue::Number(key as i64)];
+            table
+                .insert(InsertStatement {
+                    key,
+                    values,
+                    returning: None,
+                })
+                .unwrap();
+        }
+
+        assert_eq!(table.nth(0).unwrap().unwrap().key(), 10);
+        let last = table.nth(count as usize - 1).unwrap().unwrap();
+        assert_eq!(last.key(), (count - 1) * 2 + 10);
+        assert_eq!(last["a"], ScalarValue::Number(last.key() as i64));
+        let rows = table.scan().unwrap();
+        let middle = table.nth(count as usize / 2).unwrap().unwrap();
+        assert_eq!(middle.key(), rows[count as usize / 2].key());
+        assert!(table.nth(count as usize).unwrap().is_none());
+    }
+
+    #[test]
+    fn save_and_load_memory_table() {
+        let path = std::env::temp_dir().join("save_and_load_memory_table.db");
+        let _ = fs::remove_file(&path);
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        let count = table.rows_per_page() as i64 * 2;
+        for i in 0..count {
+            let values = vec![ScalarValue::Number(i), ScalarValue::String(i.to_string())];
+            insert(&mut table, values).unwrap();
+        }
+        table.save(&path).unwrap();
+
+        let mut loaded = Table::load(&path).unwrap();
+        assert_eq!(loaded.header.name, "t");
+        assert_eq!(loaded.header.num_rows, count as usize);
+        let rows = |table: &Table| -> Vec<(i64, Vec<ScalarValue>)> {
+            let rows = table.scan().unwrap();
+            rows.into_iter()
+                .map(|row| (row.key(), row.into_values()))
+                .collect()
+        };
+        assert_eq!(rows(&loaded), rows(&table));
+
+        // The loaded copy lives in memory, apart from the saved file
+        let len = fs::metadata(&path).unwrap().len();
+        insert(&mut loaded, vec![ScalarValue::Number(0), ScalarValue::Null]).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        assert_eq!(Table::load(&path).unwrap().header.num_rows, count as usize);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn backup_is_identical() {
+        let dir = std::env::temp_dir();
+        let (path, backup) = (dir.join("backup_src.db"), dir.join("backup_dst.db"));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&backup);
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let mut table = Table::new("t".to_string(), schema, &path).unwrap();
+        for i in 0..table.rows_per_page() as i64 * 2 {
+            let values = vec![ScalarValue::Number(i), ScalarValue::String(i.to_string())];
+            insert(&mut table, values).unwrap();
+        }
+        table.backup(&backup).unwrap();
+
+        assert_eq!(fs::read(&backup).unwrap(), fs::read(&path).unwrap());
+        let copy = Table::open_read_only(&backup).unwrap();
+        assert_eq!(copy.header.num_rows, table.header.num_rows);
+        let rows = |table: &Table| -> Vec<(i64, Vec<ScalarValue>)> {
+            let rows = table.scan().unwrap();
+            rows.into_iter()
+                .map(|row| (row.key(), row.into_values()))
+                .collect()
+        };
+        assert_eq!(rows(&copy), rows(&table));
+        fs::remove_file(path).unwrap();
+        fs::remove_file(backup).unwrap();
+    }
+
+    #[test]
+    fn rows_at_leaf_boundaries_read_back() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        // One past a full leaf lands on the boundary the first split makes
+        let capacity = table.rows_per_page() as i64;
+        for i in 0..=capacity * 2 {
+            insert(&mut table, vec![ScalarValue::Number(i)]).unwrap();
+        }
+
+        // The first and last row of every leaf are found on that leaf
+        let mut page_index = table.find_leaf(i64::MIN).unwrap();
+        let mut leaves = 0;
+        loop {
+            let page = table.pages.leaf_ref(page_index).unwrap();
+            let keys: Vec<i64> = page.keys().collect();
+            for key in [keys[0], *keys.last().unwrap()] {
+                assert_eq!(table.find_leaf(key).unwrap(), page_index);
+                assert_eq!(table.read(key).unwrap()[0], ScalarValue::Number(key));
+            }
+            leaves += 1;
+            match page.next_leaf() {
+                0 => break,
+                next => page_index = next as usize,
+            }
+        }
+        assert!(leaves > 2);
+        for key in [capacity - 1, capacity, capacity + 1] {
+            assert_eq!(table.read(key).unwrap()[0], ScalarValue::Number(key));
+        }
+    }
+
+    #[test]
+    fn scan_row_limit() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        let count = table.rows_per_page() + 5;
+        for i in 0..count {
+            insert(&mut table, vec![ScalarValue::Number(i as i64)]).unwrap();
+        }
+
+        table.max_scanned_rows = Some(count);
+        assert_eq!(table.scan().unwrap().len(), count);
+        let always = Expr::parse("1", table.schema()).unwrap();
+        assert_eq!(table.count_where(&always).unwrap(), count);
+
+        table.max_scanned_rows = Some(count - 1);
+        assert!(matches!(
+            table.scan(),
+            Err(Error::RowScanLimitExceeded(limit)) if limit == count - 1
+        ));
+        assert!(matches!(
+            table.count_where(&Expr::parse("0", table.schema()).unwrap()),
+            Err(Error::RowScanLimitExceeded(_))
+        ));
+        // Keyed reads don't scan
+        assert!(table.read(0).is_ok());
+    }
+
+    #[test]
+    fn reindex_repairs_leaf_chain() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        let count = table.rows_per_page() * 3;
+        for i in 0..count {
+            insert(&mut table, vec![ScalarValue::Number(i as i64)]).unwrap();
+        }
+
+        // Cut the chain after the first leaf
+        let first = table.find_leaf(i64::MIN).unwrap();
+        table.pages.leaf(first).unwrap().set_next_leaf(0);
+        table.pages.flush_page(first).unwrap();
+        assert!(table.scan().unwrap().len() < count);
+
+        assert_eq!(table.reindex().unwrap(), count);
+        assert_eq!(table.header.num_rows, count);
+        let keys: Vec<i64> = table.scan().unwrap().iter().map(|row| row.key()).collect();
+        assert_eq!(keys, (0..count as i64).collect::<Vec<_>>());
+        assert!(table.read(count as i64 - 1).is_ok());
+    }
+
+    #[test]
+    fn failed_reindex_keeps_rows() {
+        let mut schema = Schema::new(vec![("a".to_string(), DataType::String(200))]);
+        schema.options[0].dictionary = true;
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        let text = |key: i64| ScalarValue::String(format!("{key:x<199}"));
+        // Keys out of order leave leaves fuller than the half full leaves a
+        // rebuild in key order makes
+        let mut count = 0;
+        loop {
+            let key = count * 7919 % 4001;
+            let statement = InsertStatement {
+                key,
+                values: vec![text(key)],
+                returning: None,
+            };
+            if table.insert(statement).is_err() {
+                break;
+            }
+            count += 1;
+        }
+
+        assert!(matches!(table.reindex(), Err(Error::RowLimit)));
+        assert_eq!(table.len(), count as usize);
+        table.check_invariants().unwrap();
+        let rows = table.scan().unwrap();
+        assert_eq!(rows.len(), count as usize);
+        for row in rows {
+            assert_eq!(row["a"], text(row.key()));
+        }
+    }
+
+    #[test]
+    fn check_invariants() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        table.check_invariants().unwrap();
+        // Keys out of order split leaves in the middle as well as at the end
+        let count = table.rows_per_page() as i64 * 4;
+        for i in 0..count {
+            let key = i * 7919 % count;
+            let values = vec![ScalarValue::Number(key as i64)];
+            table
+                .insert(InsertStatement {
+                    key,
+                    values,
+                    returning: None,
+                })
+                .unwrap();
+        }
+        table.check_invariants().unwrap();
+
+        let violation = |table: &Table| match table.check_invariants() {
+            Err(Error::InvariantViolation(message)) => message,
+            res => panic!("expected a violation, got {res:?}"),
+        };
+        let first = table.find_leaf(i64::MIN).unwrap();
+
+        // Two keys in a leaf swapped
+        let leaf = table.pages.leaf(first).unwrap();
+        let (a, b) = (leaf.key(0).unwrap(), leaf.key(1).unwrap());
+        leaf.cell_mut(0).unwrap()[..LeafNode::KEY_SIZE].copy_from_slice(&b.to_ne_bytes());
+        leaf.cell_mut(1).unwrap()[..LeafNode::KEY_SIZE].copy_from_slice(&a.to_ne_bytes());
+        assert!(violation(&table).contains("before key"));
+        let leaf = table.pages.leaf(first).unwrap();
+        leaf.cell_mut(0).unwrap()[..LeafNode::KEY_SIZE].copy_from_slice(&a.to_ne_bytes());
+        leaf.cell_mut(1).unwrap()[..LeafNode::KEY_SIZE].copy_from_slice(&b.to_ne_bytes());
+        table.check_invariants().unwrap();
+
+        // A separator smaller than keys in its child
+        let Page::Intermediate(root) = table.pages.page(0).unwrap() else {
+            panic!("root should be an internal node");
+        };
+        let separator = root.key(0);
+        root.set_key(0, separator - 1);
+        assert!(violation(&table).contains("outside its parent's range"));
+        let Page::Intermediate(root) = table.pages.page(0).unwrap() else {
+            unreachable!()
+        };
+        root.set_key(0, separator);
+
+        // The chain cut after the first leaf
+        let next = table.pages.leaf(first).unwrap().next_leaf();
+        table.pages.leaf(first).unwrap().set_next_leaf(0);
+        assert!(violation(&table).contains("leaf chain ends"));
+        table.pages.leaf(first).unwrap().set_next_leaf(next);
+
+        // A slot pointing into the header
+        let leaf = table.pages.leaf(first).unwrap();
+        let slot = LeafNode::HEADER_SIZE..LeafNode::HEADER_SIZE + 2;
+        let offset: [u8; 2] = leaf.bytes[slot.clone()].try_into().unwrap();
+        leaf.bytes[slot.clone()].fill(0);
+        assert!(violation(&table).contains("outside its cell area"));
+        table.pages.leaf(first).unwrap().bytes[slot].copy_from_slice(&offset);
+        table.check_invariants().unwrap();
+
+        // More cells than fit
+        let leaf = table.pages.leaf(first).unwrap();
+        let max_cells = leaf.max_cells() as u32;
+        leaf.set_num_cells(max_cells + 1);
+        assert!(violation(&table).contains("at most"));
+    }
+
+    #[test]
+    fn delete_where_across_leaves() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        let count = table.rows_per_page() as i64 * 3;
+        for i in 0..count {
+            insert(&mut table, vec![ScalarValue::Number(i)]).unwrap();
+        }
+
+        // Neighbouring rows in the same leaf go together
+        let deleted = table
+            .delete_where(|row| Ok(row[0] != ScalarValue::Number(0) && row.key() % 3 != 0))
+            .unwrap();
+        let keys: Vec<i64> = table.scan().unwrap().iter().map(|row| row.key()).collect();
+        let expected: Vec<i64> = (0..count as i64).filter(|key| key % 3 == 0).collect();
+        assert_eq!(keys, expected);
+        assert_eq!(deleted, count as usize - expected.len());
+        assert_eq!(table.header.num_rows, expected.len());
+        table.check_invariants().unwrap();
+
+        // A failing predicate removes nothing
+        let mut seen = 0;
+        let res = table.delete_where(|_| {
+            seen += 1;
+            match seen {
+                1 => Ok(true),
+                _ => Err(Error::DivisionByZero),
+            }
+        });
+        assert!(matches!(res, Err(Error::DivisionByZero)));
+        assert_eq!(table.header.num_rows, expected.len());
+
+        // Emptied leaves take rows again
+        assert_eq!(table.delete_where(|_| Ok(true)).unwrap(), expected.len());
+        for key in 0..count as i64 {
+            let values = vec![ScalarValue::Number(key)];
+            table
+                .insert(InsertStatement {
+                    key,
+                    values,
+                    returning: None,
+                })
+                .unwrap();
+        }
+        assert_eq!(table.scan().unwrap().len(), count as usize);
+        table.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn sync_mode_syncs_every_write() {
+        let path = std::env::temp_dir().join("sync_mode_syncs_every_write.db");
+        let _ = fs::remove_file(&path);
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let options = TableOptions {
+            sync: true,
+            ..Default::default()
+        };
+        let mut table =
+            Table::open_with_options("t".to_string(), schema.clone(), &path, options).unwrap();
+        assert!(table.pages.sync());
+
+        // One page and the header per insert while the root leaf has room
+        insert(&mut table, vec![ScalarValue::Number(1)]).unwrap();
+        assert_eq!(table.pages.stats().syncs, 2);
+        insert(&mut table, vec![ScalarValue::Number(2)]).unwrap();
+        assert_eq!(table.pages.stats().syncs, 4);
+        table.delete(0).unwrap();
+        assert_eq!(table.pages.stats().syncs, 6);
+        drop(table);
+
+        // Buffered by default
+        let mut table = Table::new("t".to_string(), schema, &path).unwrap();
+        assert!(!table.pages.sync());
+        let values = vec![ScalarValue::Number(3)];
+        let statement = InsertStatement {
+            key: 10,
+            values,
+            returning: None,
+        };
+        table.insert(statement).unwrap();
+        assert_eq!(table.pages.stats().syncs, 0);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn write_through_keeps_cache_bounded() {
+        let path = std::env::temp_dir().join("write_through_keeps_cache_bounded.db");
+        let _ = fs::remove_file(&path);
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(20)),
+        ]);
+        let mut table = Table::new("t".to_string(), schema, &path).unwrap();
+        table.pages.set_write_through(true).unwrap();
+        assert!(table.pages.write_through());
+
+        let count = table.rows_per_page() as i64 * 5;
+        for i in 0..count {
+            let values = vec![ScalarValue::Number(i), ScalarValue::String(i.to_string())];
+            insert(&mut table, values).unwrap();
+            assert!(table.pages.cached_pages() <= 1);
+        }
+        table.delete(3).unwrap();
+        assert!(table.pages.cached_pages() <= 1);
+        table.check_invariants().unwrap();
+        drop(table);
+
+        // Every page was written back before it was dropped
+        let table = Table::open_read_only(&path).unwrap();
+        assert_eq!(table.scan().unwrap().len(), count as usize - 1);
+        assert_eq!(
+            table.read(count - 1).unwrap()[1],
+            ScalarValue::String((count - 1).to_string())
+        );
         fs::remove_file(path).unwrap();
     }
+
+    #[test]
+    fn header_magic_and_version() {
+        let dir = std::env::temp_dir();
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+
+        let path = dir.join("header_good.db");
+        let _ = fs::remove_file(&path);
+        Table::new("t".to_string(), schema.clone(), &path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let slot = header_slot_offset(1) as usize;
+        assert_eq!(&bytes[slot..slot + 4], b"SCDB");
+        assert_eq!(Table::open_read_only(&path).unwrap().header.name, "t");
+
+        // Both header copies claim a newer format
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        for slot in [0, HEADER_SPACE as u64 / 2] {
+            file.seek(io::SeekFrom::Start(slot + 4)).unwrap();
+            file.write_all(&(FORMAT_VERSION + 1).to_ne_bytes()).unwrap();
+        }
+        drop(file);
+        assert!(matches!(
+            Table::new("t".to_string(), schema.clone(), &path),
+            Err(Error::UnsupportedVersion { found, supported })
+                if found == FORMAT_VERSION + 1 && supported == FORMAT_VERSION
+        ));
+        fs::remove_file(&path).unwrap();
+
+        // Every copy torn
+        let path = dir.join("header_corrupt.db");
+        let _ = fs::remove_file(&path);
+        Table::new("t".to_string(), schema.clone(), &path).unwrap();
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        for slot in [0, HEADER_SPACE as u64 / 2] {
+            file.seek(io::SeekFrom::Start(slot + HEADER_SLOT_PREFIX as u64))
+                .unwrap();
+            file.write_all(&[0xff; 8]).unwrap();
+        }
+        drop(file);
+        assert!(matches!(
+            Table::open_read_only(&path),
+            Err(Error::CorruptHeader)
+        ));
+        fs::remove_file(&path).unwrap();
+
+        let path = dir.join("header_bad_magic.db");
+        let zeros = String::from_utf8(vec![0; 2 * HEADER_SPACE]).unwrap();
+        for contents in ["not a table".repeat(1000), "short".to_string(), zeros] {
+            fs::write(&path, contents).unwrap();
+            assert!(matches!(
+                Table::new("t".to_string(), schema.clone(), &path),
+                Err(Error::BadMagic)
+            ));
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn count_where_matches_scan() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut table = Table::new("t".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        let always = Expr::parse("1", table.schema()).unwrap();
+        assert_eq!(table.count_where(&always).unwrap(), 0);
+        let count = table.rows_per_page() as i64 * 2;
+        for i in 0..count {
+            insert(&mut table, vec![ScalarValue::Number(i % 10)]).unwrap();
+        }
+
+        let above_three = Expr::parse("a > 3", table.schema()).unwrap();
+        let expected = table
+            .scan()
+            .unwrap()
+            .iter()
+            .filter(|row| matches!(row["a"], ScalarValue::Number(a) if a > 3))
+            .count();
+        assert_eq!(table.count_where(&above_three).unwrap(), expected);
+        assert_eq!(table.count_where(&always).unwrap(), count as usize);
+    }
+
+    #[test]
+    fn copy_between_tables() {
+        let memory = Path::new(MEMORY_PATH);
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let mut src = Table::new("src".to_string(), schema, memory).unwrap();
+        for i in 0..5 {
+            insert(
+                &mut src,
+                vec![ScalarValue::Number(i), ScalarValue::String(i.to_string())],
+            )
+            .unwrap();
+        }
+
+        // Column names don't have to match, only the types
+        let renamed = Schema::new(vec![
+            ("x".to_string(), DataType::Number),
+            ("y".to_string(), DataType::String(10)),
+        ]);
+        let mut dst = Table::new("dst".to_string(), renamed, memory).unwrap();
+        assert_eq!(dst.copy_from(&src).unwrap(), 5);
+        assert_eq!(dst.header.num_rows, 5);
+        let copied: Vec<Vec<ScalarValue>> = dst
+            .scan()
+            .unwrap()
+            .into_iter()
+            .map(|row| row.into_values())
+            .collect();
+        let original: Vec<Vec<ScalarValue>> = src
+            .scan()
+            .unwrap()
+            .into_iter()
+            .map(|row| row.into_values())
+            .collect();
+        assert_eq!(copied, original);
+
+        let other = Schema::new(vec![
+            ("a".to_string(), DataType::String(10)),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let mut other = Table::new("other".to_string(), other, memory).unwrap();
+        assert!(matches!(
+            other.copy_from(&src),
+            Err(Error::SchemaMismatch(_))
+        ));
+        assert!(other.scan().unwrap().is_empty());
+    }
+
+    #[test]
+    fn in_memory_table() {
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let mut table = Table::new("mem".to_string(), schema, Path::new(MEMORY_PATH)).unwrap();
+        for i in 0..3 {
+            insert(
+                &mut table,
+                vec![
+                    ScalarValue::Number(i),
+                    ScalarValue::String(format!("row {i}")),
+                ],
+            )
+            .unwrap();
+        }
+
+        let rows = table.scan().unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2]["b"], ScalarValue::String("row 2".to_string()));
+        assert!(!Path::new(MEMORY_PATH).exists());
+    }
 }
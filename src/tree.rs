@@ -1,12 +1,14 @@
-use std::{io::Write, mem};
+use std::mem;
 
 use crate::{
-    datatype::{DataType, ScalarValue, Schema},
-    table::Pager,
+    codec,
+    datatype::{Row, ScalarValue, Schema},
+    dictionary::Dictionary,
+    PAGE_SIZE,
 };
 
 const NODE_TYPE_SIZE: usize = mem::size_of::<u8>();
-const NODE_TYPE_OFFSET: usize = 0;
+pub const NODE_TYPE_OFFSET: usize = 0;
 const IS_ROOT_SIZE: usize = mem::size_of::<u8>();
 const IS_ROOT_OFFSET: usize = NODE_TYPE_SIZE;
 const PARENT_POINTER_SIZE: usize = mem::size_of::<u32>();
@@ -29,9 +31,14 @@ impl LeafNode {
     const NUM_CELLS_OFFSET: usize = COMMON_NODE_HEADER_SIZE;
     const NEXT_LEAF_SIZE: usize = mem::size_of::<u32>();
     const NEXT_LEAF_OFFSET: usize = Self::NUM_CELLS_OFFSET + Self::NUM_CELLS_SIZE;
-    const HEADER_SIZE: usize = Self::NEXT_LEAF_OFFSET + Self::NEXT_LEAF_SIZE;
-    const KEY_SIZE: usize = mem::size_of::<u32>();
-    const SPACE_FOR_CELLS: usize = 4096 - Self::HEADER_SIZE;
+    const CELLS_START_SIZE: usize = mem::size_of::<u16>();
+    const CELLS_START_OFFSET: usize = Self::NEXT_LEAF_OFFSET + Self::NEXT_LEAF_SIZE;
+    pub const HEADER_SIZE: usize = Self::CELLS_START_OFFSET + Self::CELLS_START_SIZE;
+    pub const KEY_SIZE: usize = mem::size_of::<i64>();
+    /// Every cell has a slot after the header holding its offset and length,
+    /// both `u16`. Slots are kept in key order.
+    pub const SLOT_SIZE: usize = 2 * mem::size_of::<u16>();
+    pub const SPACE_FOR_CELLS: usize = PAGE_SIZE - Self::HEADER_SIZE;
 
     pub fn new() -> Self {
         Self {
@@ -43,12 +50,10 @@ impl LeafNode {
         Self { bytes }
     }
 
-    pub fn cell_size(&self, value_size: usize) -> usize {
-        Self::KEY_SIZE + value_size
-    }
-
-    pub fn max_cells(&self, value_size: usize) -> usize {
-        (4096 - Self::HEADER_SIZE) / self.cell_size(value_size)
+    /// How many cells fit in a leaf when every row takes `value_size` bytes.
+    /// Rows with compact columns can take less, so more of them may fit.
+    pub fn capacity(value_size: usize) -> usize {
+        Self::SPACE_FOR_CELLS / (Self::SLOT_SIZE + Self::KEY_SIZE + value_size)
     }
 
     pub fn parent(&self) -> u32 {
@@ -89,194 +94,348 @@ impl LeafNode {
             .copy_from_slice(&value.to_ne_bytes())
     }
 
-    pub fn key(&self, index: usize, value_size: usize) -> u32 {
-        let offset: usize = Self::HEADER_SIZE + index * self.cell_size(value_size);
-        let key = &self.bytes[offset..offset + Self::KEY_SIZE];
-        u32::from_ne_bytes(key.try_into().unwrap())
+    /// Where the cell area begins. Cells are packed from the end of the page
+    /// down towards the slots; a new page, which stores 0, has none.
+    pub fn cells_start(&self) -> usize {
+        let bytes = self.bytes[Self::CELLS_START_OFFSET..Self::HEADER_SIZE]
+            .try_into()
+            .unwrap();
+        match u16::from_ne_bytes(bytes) {
+            0 => PAGE_SIZE,
+            start => start as usize,
+        }
     }
 
-    pub fn read_row(&self, index: usize, schema: &Schema) -> (u32, Vec<ScalarValue>) {
-        let value_size = schema.row_size();
-        let mut offset = Self::HEADER_SIZE + index * self.cell_size(value_size);
+    fn set_cells_start(&mut self, start: usize) {
+        self.bytes[Self::CELLS_START_OFFSET..Self::HEADER_SIZE]
+            .copy_from_slice(&(start as u16).to_ne_bytes())
+    }
 
-        let key = &self.bytes[offset..offset + Self::KEY_SIZE];
-        let key = u32::from_ne_bytes(key.try_into().unwrap());
-        offset += Self::KEY_SIZE;
+    /// How many slots fit before the cell area, which no valid node exceeds.
+    pub fn max_cells(&self) -> usize {
+        self.cells_start().saturating_sub(Self::HEADER_SIZE) / Self::SLOT_SIZE
+    }
 
-        let values_bytes = &self.bytes[offset..offset + value_size];
-        let mut value_offset = 0;
-        let mut values = Vec::new();
+    /// Whether slot `index` points at a cell within the cell area that is
+    /// long enough to hold a key.
+    pub fn slot_in_bounds(&self, index: usize) -> bool {
+        let (offset, len) = self.slot(index);
+        offset >= self.cells_start() && offset + len <= PAGE_SIZE && len >= Self::KEY_SIZE
+    }
 
-        for (_, ty) in &schema.feilds {
-            let value = match ty {
-                DataType::String(size) => {
-                    let len = values_bytes[value_offset] as usize;
-                    if len != 0 {
-                        let bytes = &values_bytes[(value_offset + 1)..=(value_offset + len)];
-                        value_offset += size;
-                        let string = String::from_utf8(bytes.to_owned()).unwrap();
-                        ScalarValue::String(string)
-                    } else {
-                        ScalarValue::String("".to_string())
-                    }
-                }
-                DataType::Number => {
-                    let bytes = &values_bytes[value_offset..value_offset + 8];
-                    value_offset += 8;
-                    ScalarValue::Number(i64::from_ne_bytes(bytes.try_into().unwrap()))
-                }
-            };
-            values.push(value);
+    fn slot_offset(index: usize) -> usize {
+        Self::HEADER_SIZE + index * Self::SLOT_SIZE
+    }
+
+    /// The offset and length of cell `index`.
+    pub fn slot(&self, index: usize) -> (usize, usize) {
+        let offset = Self::slot_offset(index);
+        let slot = &self.bytes[offset..offset + Self::SLOT_SIZE];
+        let cell_offset = u16::from_ne_bytes([slot[0], slot[1]]);
+        let len = u16::from_ne_bytes([slot[2], slot[3]]);
+        (cell_offset as usize, len as usize)
+    }
+
+    fn set_slot(&mut self, index: usize, cell_offset: usize, len: usize) {
+        let offset = Self::slot_offset(index);
+        let slot = &mut self.bytes[offset..offset + Self::SLOT_SIZE];
+        slot[..2].copy_from_slice(&(cell_offset as u16).to_ne_bytes());
+        slot[2..].copy_from_slice(&(len as u16).to_ne_bytes());
+    }
+
+    /// Cell `index`, or `None` when the leaf has no such cell or its slot
+    /// points past the page.
+    pub fn cell(&self, index: usize) -> Option<&[u8]> {
+        if index >= self.num_cells() as usize {
+            return None;
         }
-        (key, values)
+        let (offset, len) = self.slot(index);
+        self.bytes.get(offset..offset + len)
     }
 
-    pub fn cell_mut(&mut self, index: usize, value_size: usize) -> &mut [u8] {
-        let cell_size = self.cell_size(value_size);
-        let offset = Self::HEADER_SIZE + index * cell_size;
-        &mut self.bytes[offset..offset + cell_size]
+    pub fn cell_mut(&mut self, index: usize) -> Option<&mut [u8]> {
+        if index >= self.num_cells() as usize {
+            return None;
+        }
+        let (offset, len) = self.slot(index);
+        self.bytes.get_mut(offset..offset + len)
     }
 
-    pub fn cell(&self, index: usize, value_size: usize) -> &[u8] {
-        let cell_size = self.cell_size(value_size);
-        let offset = Self::HEADER_SIZE + index * cell_size;
-        &self.bytes[offset..offset + cell_size]
+    // Cell `index` where the caller knows it is below `num_cells`
+    fn cell_at(&self, index: usize) -> &[u8] {
+        let (offset, len) = self.slot(index);
+        &self.bytes[offset..offset + len]
     }
 
-    pub fn copy_within(&mut self, value_size: usize, src: usize, dst: usize) {
-        let cell_size = self.cell_size(value_size);
-        let offset_src = Self::HEADER_SIZE + src * cell_size;
-        let offset_dst = Self::HEADER_SIZE + dst * cell_size;
-        self.bytes
-            .copy_within(offset_src..offset_src + cell_size, offset_dst)
+    /// The key of cell `index`, `None` as for `cell` or when the cell is too
+    /// short to hold a key.
+    pub fn key(&self, index: usize) -> Option<i64> {
+        let key = self.cell(index)?.get(..Self::KEY_SIZE)?;
+        Some(i64::from_ne_bytes(key.try_into().unwrap()))
     }
 
-    pub fn serialize_row(
-        &mut self,
-        index: usize,
+    fn key_at(&self, index: usize) -> i64 {
+        let key = &self.cell_at(index)[..Self::KEY_SIZE];
+        i64::from_ne_bytes(key.try_into().unwrap())
+    }
+
+    /// Bytes left for new cells and their slots, counting the gaps removed
+    /// cells left behind.
+    pub fn free_space(&self) -> usize {
+        let num_cells = self.num_cells() as usize;
+        let used: usize = (0..num_cells).map(|i| self.slot(i).1).sum();
+        Self::SPACE_FOR_CELLS.saturating_sub(num_cells * Self::SLOT_SIZE + used)
+    }
+
+    /// Whether a cell of `cell_size` bytes fits, along with its slot.
+    pub fn fits(&self, cell_size: usize) -> bool {
+        self.free_space() >= cell_size + Self::SLOT_SIZE
+    }
+
+    /// Bytes the cell for `values` takes: the key, the null bitmap and every
+    /// column. A compact number takes only its varint, and nothing when null.
+    /// A dictionary string takes the size of its id.
+    pub fn cell_size(schema: &Schema, values: &[ScalarValue]) -> usize {
+        let columns: usize = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| match value {
+                _ if !schema.is_compact(i) => schema.column_size(i),
+                ScalarValue::Number(x) => codec::varint_size(*x),
+                _ => 0,
+            })
+            .sum();
+        Self::KEY_SIZE + schema.null_bitmap_size() + columns
+    }
+
+    /// Encodes a cell: the key, a bitmap with a bit set for each null
+    /// column, then the columns in order. Dictionary strings must already be
+    /// in `dictionary`.
+    pub fn encode_cell(
         schema: &Schema,
-        key: u32,
+        dictionary: &Dictionary,
+        key: i64,
         values: &[ScalarValue],
-    ) {
-        let value_size = schema.row_size();
-        let cell = self.cell_mut(index, value_size);
-        cell[..Self::KEY_SIZE].copy_from_slice(&key.to_ne_bytes());
-        let mut cell_offset = Self::KEY_SIZE;
-
-        let mut values = values.into_iter();
-
-        for (_, ty) in &schema.feilds {
-            match ty {
-                DataType::String(size) => {
-                    let ScalarValue::String(value) = values.next().unwrap() else {
-                        panic!()
-                    };
-                    let bytes = &mut cell[cell_offset..cell_offset + size];
-                    bytes[0] = value.len() as u8;
-                    (&mut bytes[1..]).write(value.as_bytes()).unwrap();
-                    cell_offset += size
+    ) -> Vec<u8> {
+        let mut cell = key.to_ne_bytes().to_vec();
+        cell.resize(Self::KEY_SIZE + schema.null_bitmap_size(), 0);
+        for (i, ((_, ty), value)) in schema.feilds.iter().zip(values).enumerate() {
+            if *value == ScalarValue::Null {
+                cell[Self::KEY_SIZE + i / 8] |= 1 << (i % 8);
+            }
+            match value {
+                _ if schema.is_dictionary(i) => {
+                    let start = cell.len();
+                    cell.resize(start + codec::STRING_ID_SIZE, 0);
+                    codec::encode_string_id(value, ty, dictionary, &mut cell[start..]);
                 }
-                DataType::Number => {
-                    let ScalarValue::Number(value) = values.next().unwrap() else {
-                        panic!()
-                    };
-                    (&mut cell[cell_offset..])
-                        .write(&value.to_ne_bytes())
-                        .unwrap();
-                    cell_offset += 8
+                _ if !schema.is_compact(i) => {
+                    let start = cell.len();
+                    cell.resize(start + ty.size(), 0);
+                    codec::encode_value(value, ty, &mut cell[start..]);
                 }
+                ScalarValue::Number(x) => codec::encode_varint(*x, &mut cell),
+                _ => {}
+            }
+        }
+        cell
+    }
+
+    /// Decodes cell `index`, or returns `None` when there is no such cell.
+    pub fn read_row<'a>(
+        &self,
+        index: usize,
+        schema: &'a Schema,
+        dictionary: &Dictionary,
+    ) -> Option<Row<'a>> {
+        let cell = self.cell(index)?;
+        if cell.len() < Self::KEY_SIZE + schema.null_bitmap_size() {
+            return None;
+        }
+        Some(Self::decode_row(cell, schema, dictionary))
+    }
+
+    fn decode_row<'a>(cell: &[u8], schema: &'a Schema, dictionary: &Dictionary) -> Row<'a> {
+        let (key, cell) = cell.split_at(Self::KEY_SIZE);
+        let key = i64::from_ne_bytes(key.try_into().unwrap());
+        let (null_bitmap, mut values_bytes) = cell.split_at(schema.null_bitmap_size());
+        let mut values = Vec::new();
+
+        for (i, (_, ty)) in schema.feilds.iter().enumerate() {
+            let null = null_bitmap[i / 8] & (1 << (i % 8)) != 0;
+            let (value, size) = match (null, schema.is_compact(i)) {
+                (true, true) => (ScalarValue::Null, 0),
+                (true, false) => (ScalarValue::Null, schema.column_size(i)),
+                (false, true) => {
+                    let (x, size) = codec::decode_varint(values_bytes);
+                    (ScalarValue::Number(x), size)
+                }
+                (false, false) if schema.is_dictionary(i) => {
+                    codec::decode_string_id(values_bytes, dictionary)
+                }
+                (false, false) => codec::decode_value(values_bytes, ty),
             };
+            values_bytes = &values_bytes[size..];
+            values.push(value);
+        }
+        Row::new(key, values, schema)
+    }
+
+    /// Decodes every cell in order.
+    pub fn cells<'a>(
+        &'a self,
+        schema: &'a Schema,
+        dictionary: &'a Dictionary,
+    ) -> impl Iterator<Item = Row<'a>> + 'a {
+        (0..self.num_cells() as usize)
+            .map(move |index| Self::decode_row(self.cell_at(index), schema, dictionary))
+    }
+
+    /// The keys of every cell in order, leaving the values undecoded.
+    pub fn keys(&self) -> impl Iterator<Item = i64> + '_ {
+        (0..self.num_cells() as usize).map(|index| self.key_at(index))
+    }
+
+    // Copies `cell` to just below the cell area and gives it the slot after
+    // the last one. The caller has made sure there is room between the two.
+    fn push_cell(&mut self, cell: &[u8]) {
+        let num_cells = self.num_cells() as usize;
+        let start = self.cells_start() - cell.len();
+        self.bytes[start..start + cell.len()].copy_from_slice(cell);
+        self.set_cells_start(start);
+        self.set_slot(num_cells, start, cell.len());
+        self.set_num_cells(num_cells as u32 + 1);
+    }
+
+    // Packs the cells against the end of the page again, so the gaps left by
+    // removed cells join the free space between the slots and the cells
+    fn defragment(&mut self) {
+        let cells: Vec<Vec<u8>> = (0..self.num_cells() as usize)
+            .map(|i| self.cell_at(i).to_vec())
+            .collect();
+        self.set_num_cells(0);
+        self.set_cells_start(PAGE_SIZE);
+        for cell in &cells {
+            self.push_cell(cell);
+        }
+    }
+
+    // Inserts `cell` with slot `index`, moving the slots after it right. The
+    // caller has checked that it fits.
+    fn insert_cell(&mut self, index: usize, cell: &[u8]) {
+        let num_cells = self.num_cells() as usize;
+        let slots_end = Self::slot_offset(num_cells + 1);
+        if self.cells_start() < slots_end + cell.len() {
+            self.defragment();
         }
+        self.push_cell(cell);
+        let (offset, len) = self.slot(num_cells);
+        self.bytes.copy_within(
+            Self::slot_offset(index)..Self::slot_offset(num_cells),
+            Self::slot_offset(index + 1),
+        );
+        self.set_slot(index, offset, len);
     }
 
-    fn leaf_node_split_and_insert<'a>(
+    /// Inserts the row in key order, splitting the node when it is full. The
+    /// cells are then divided by size, with the new node taking the upper
+    /// half and returned.
+    pub fn leaf_node_split_and_insert(
         &mut self,
-        key: u32,
+        key: i64,
         values: Vec<ScalarValue>,
         schema: &Schema,
+        dictionary: &Dictionary,
     ) -> Option<LeafNode> {
-        let value_size = schema.row_size();
-        let max_cells = self.max_cells(value_size);
-        let index = match self.binary_search(key, value_size) {
-            Some(i) => i,
-            None => 0,
-        };
-
-        let num_cells = self.num_cells();
-        if num_cells < max_cells as u32 {
-            for i in (index..self.num_cells() as usize).rev() {
-                self.copy_within(value_size, i, i + 1);
-            }
-            self.serialize_row(index, schema, key, &values);
-            self.set_num_cells(num_cells + 1);
+        let cell = Self::encode_cell(schema, dictionary, key, &values);
+        let (Ok(index) | Err(index)) = self.binary_search(key);
+        if self.fits(cell.len()) {
+            self.insert_cell(index, &cell);
             return None;
         }
 
+        // The caller links this node into the leaf chain and its parent once
+        // it has a page index.
         let mut new_node = LeafNode::new();
         new_node.set_parent(self.parent());
         new_node.set_next_leaf(self.next_leaf());
-        // todo move this outside
-        //self.set_next_leaf(page_index as u32);
-        let leaf_node_right_split_count: usize = (max_cells + 1) / 2;
-        let leaf_node_left_split_count = (max_cells + 1) - leaf_node_right_split_count;
-
-        // Since there is one extra key in the keys that are to be places
-        // We have max_cells + 1 keys .. but only max_cells - 1 real indexes in old page
-        for i in 0..leaf_node_left_split_count {
-            let index_within_node = i % leaf_node_left_split_count;
-            if i == index {
-                self.serialize_row(index_within_node, schema, key, &values);
-            } else if i > index {
-                // Copy cell at i - 1 to account for extra key
-                self.copy_within(value_size, i - 1, index_within_node)
-            } else {
-                // Insert value has been serialized by now
-                self.copy_within(value_size, i, index_within_node)
-            }
-        }
 
-        for i in leaf_node_left_split_count..=max_cells {
-            let index_within_node = i % leaf_node_left_split_count;
-            if i == index {
-                new_node.serialize_row(index_within_node, schema, key, &values);
-            } else if i > index {
-                // Copy cell at i - 1 to account for extra key
-                new_node
-                    .cell_mut(index_within_node, value_size)
-                    .copy_from_slice(self.cell(i - 1, value_size));
-            } else {
-                // Insert value has been serialized by now
-                new_node
-                    .cell_mut(index_within_node, value_size)
-                    .copy_from_slice(self.cell(i, value_size));
+        let mut cells: Vec<Vec<u8>> = (0..self.num_cells() as usize)
+            .map(|i| self.cell_at(i).to_vec())
+            .collect();
+        cells.insert(index, cell);
+        // Splits where the larger half is smallest. On a tie the left half
+        // takes the extra cell, as it does when every cell is the same size.
+        let total: usize = cells.iter().map(|cell| cell.len() + Self::SLOT_SIZE).sum();
+        let (mut left, mut best) = (0, (usize::MAX, 0));
+        for (i, cell) in cells[..cells.len() - 1].iter().enumerate() {
+            left += cell.len() + Self::SLOT_SIZE;
+            let larger = left.max(total - left);
+            if larger <= best.0 {
+                best = (larger, i + 1);
             }
         }
 
-        self.set_num_cells(leaf_node_left_split_count as u32);
-        new_node.set_num_cells(leaf_node_right_split_count as u32);
-
+        let (left, right) = cells.split_at(best.1);
+        self.set_num_cells(0);
+        self.set_cells_start(PAGE_SIZE);
+        for cell in left {
+            self.push_cell(cell);
+        }
+        for cell in right {
+            new_node.push_cell(cell);
+        }
         Some(new_node)
     }
 
-    pub fn binary_search(&self, key: u32, value_size: usize) -> Option<usize> {
+    /// Removes the cell at `index`, moving the slots after it left. Its bytes
+    /// are reclaimed the next time the node runs out of room.
+    pub fn remove_cell(&mut self, index: usize) {
+        let num_cells = self.num_cells() as usize;
+        let (offset, len) = self.slot(index);
+        if offset == self.cells_start() {
+            self.set_cells_start(offset + len);
+        }
+        self.bytes.copy_within(
+            Self::slot_offset(index + 1)..Self::slot_offset(num_cells),
+            Self::slot_offset(index),
+        );
+        self.set_num_cells(num_cells as u32 - 1);
+    }
+
+    /// Like `slice::binary_search`: `Ok` holds the index of `key`, `Err` the
+    /// index it would have to be inserted at to keep the cells sorted.
+    pub fn binary_search(&self, key: i64) -> Result<usize, usize> {
         let mut left = 0;
         let mut right = self.num_cells() as usize;
 
         while left < right {
             let mid = left + (right - left) / 2;
-            match self.key(mid, value_size).cmp(&key) {
+            match self.key_at(mid).cmp(&key) {
                 std::cmp::Ordering::Less => {
                     left = mid + 1;
                 }
                 std::cmp::Ordering::Equal => {
-                    return Some(mid);
+                    return Ok(mid);
                 }
                 std::cmp::Ordering::Greater => {
                     right = mid;
                 }
             }
         }
-        None
+        Err(left)
+    }
+
+    /// The largest key in the node, which is its last cell.
+    pub fn max_key(&self) -> Option<i64> {
+        let num_cells = self.num_cells() as usize;
+        (num_cells > 0).then(|| self.key_at(num_cells - 1))
+    }
+}
+
+impl Default for LeafNode {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -290,12 +449,12 @@ impl InternalNode {
     const NODE_NUM_KEYS_OFFSET: usize = COMMON_NODE_HEADER_SIZE;
     const NODE_RIGHT_CHILD_SIZE: usize = mem::size_of::<u32>();
     const NODE_RIGHT_CHILD_OFFSET: usize = Self::NODE_NUM_KEYS_OFFSET + Self::NODE_NUM_KEYS_SIZE;
-    const NODE_HEADER_SIZE: usize =
+    pub const NODE_HEADER_SIZE: usize =
         COMMON_NODE_HEADER_SIZE + Self::NODE_NUM_KEYS_SIZE + Self::NODE_RIGHT_CHILD_SIZE;
-    const NODE_KEY_SIZE: usize = mem::size_of::<u32>();
+    const NODE_KEY_SIZE: usize = mem::size_of::<i64>();
     const NODE_CHILD_SIZE: usize = mem::size_of::<u32>();
-    const NODE_CELL_SIZE: usize = Self::NODE_CHILD_SIZE + Self::NODE_KEY_SIZE;
-    const NODE_MAX_CELLS: usize = (4096 - Self::NODE_HEADER_SIZE) / Self::NODE_CELL_SIZE;
+    pub const NODE_CELL_SIZE: usize = Self::NODE_CHILD_SIZE + Self::NODE_KEY_SIZE;
+    pub const NODE_MAX_CELLS: usize = (4096 - Self::NODE_HEADER_SIZE) / Self::NODE_CELL_SIZE;
 
     pub fn set_root_node(&mut self) {
         self.bytes[IS_ROOT_OFFSET] = 1u8;
@@ -337,12 +496,12 @@ impl InternalNode {
         u32::from_ne_bytes(bytes)
     }
 
-    pub fn key(&self, index: usize) -> u32 {
+    pub fn key(&self, index: usize) -> i64 {
         let offset = Self::key_offset(index);
         let key_bytes = self.bytes[offset..offset + Self::NODE_KEY_SIZE]
             .try_into()
             .unwrap();
-        u32::from_ne_bytes(key_bytes)
+        i64::from_ne_bytes(key_bytes)
     }
 
     pub fn children(&self, mut index: usize, pos: Pos) -> u32 {
@@ -368,77 +527,557 @@ impl InternalNode {
     pub(crate) fn new(bytes: Box<[u8; 4096]>) -> InternalNode {
         Self { bytes }
     }
+
+    /// Marks the bytes as an internal node with no keys.
+    pub fn initialize(&mut self) {
+        self.bytes[NODE_TYPE_OFFSET] = 1;
+        self.set_num_keys(0);
+    }
+
+    pub fn parent(&self) -> u32 {
+        let bytes = self.bytes[PARENT_POINTER_OFFSET..PARENT_POINTER_OFFSET + PARENT_POINTER_SIZE]
+            .try_into()
+            .unwrap();
+        u32::from_ne_bytes(bytes)
+    }
+
+    pub fn set_parent(&mut self, val: u32) {
+        self.bytes[PARENT_POINTER_OFFSET..PARENT_POINTER_OFFSET + PARENT_POINTER_SIZE]
+            .copy_from_slice(&val.to_ne_bytes())
+    }
+
+    pub fn set_num_keys(&mut self, value: u32) {
+        self.bytes
+            [Self::NODE_NUM_KEYS_OFFSET..Self::NODE_NUM_KEYS_OFFSET + Self::NODE_NUM_KEYS_SIZE]
+            .copy_from_slice(&value.to_ne_bytes())
+    }
+
+    pub fn set_right_most_child(&mut self, value: u32) {
+        self.bytes[Self::NODE_RIGHT_CHILD_OFFSET
+            ..Self::NODE_RIGHT_CHILD_OFFSET + Self::NODE_RIGHT_CHILD_SIZE]
+            .copy_from_slice(&value.to_ne_bytes())
+    }
+
+    pub fn set_key(&mut self, index: usize, key: i64) {
+        let offset = Self::key_offset(index);
+        self.bytes[offset..offset + Self::NODE_KEY_SIZE].copy_from_slice(&key.to_ne_bytes())
+    }
+
+    /// Points child slot `index` at `child`. Slot `num_keys` is the right
+    /// most child.
+    pub fn set_child(&mut self, index: usize, child: u32) {
+        if index == self.num_keys() as usize {
+            return self.set_right_most_child(child);
+        }
+        let offset = Self::cell_offset(index);
+        self.bytes[offset..offset + Self::NODE_CHILD_SIZE].copy_from_slice(&child.to_ne_bytes())
+    }
+
+    /// The slot of the child whose subtree holds `key`: the first one whose
+    /// key is not smaller, or the right most child.
+    pub fn find_child(&self, key: i64) -> usize {
+        let mut left = 0;
+        let mut right = self.num_keys() as usize;
+
+        while left < right {
+            let mid = left + (right - left) / 2;
+            if self.key(mid) < key {
+                left = mid + 1;
+            } else {
+                right = mid;
+            }
+        }
+        left
+    }
+
+    /// Inserts a `(child, key)` cell at `index`, shifting the cells after it.
+    pub fn insert_cell(&mut self, index: usize, child: u32, key: i64) {
+        let num_keys = self.num_keys() as usize;
+        let start = Self::cell_offset(index);
+        let end = Self::cell_offset(num_keys);
+        self.bytes
+            .copy_within(start..end, start + Self::NODE_CELL_SIZE);
+        self.set_num_keys(num_keys as u32 + 1);
+        self.set_child(index, child);
+        self.set_key(index, key);
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use std::{env::temp_dir, fs::OpenOptions};
-
     use crate::{
+        codec::MAX_VARINT_SIZE,
         datatype::{DataType, ScalarValue, Schema},
-        table::Pager,
+        dictionary::Dictionary,
     };
 
     use super::LeafNode;
 
+    // xorshift64, so the round trip test is repeatable without extra crates
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    fn random_schema(rng: &mut Rng) -> Schema {
+        // Past 8 columns the null bitmap takes a second byte
+        let columns = 1 + rng.below(12);
+        let feilds = (0..columns)
+            .map(|i| {
+                let ty = match rng.below(3) {
+                    0 => DataType::Number,
+                    1 => DataType::String(rng.below(40)),
+                    _ => DataType::Char(rng.below(40)),
+                };
+                (format!("c{i}"), ty)
+            })
+            .collect();
+        let mut schema = Schema::new(feilds);
+        for options in &mut schema.options {
+            options.compact = rng.below(2) == 0;
+        }
+        schema
+    }
+
+    // Returns the value to store along with what reading it back should give
+    fn random_value(rng: &mut Rng, ty: &DataType) -> (ScalarValue, ScalarValue) {
+        if rng.below(5) == 0 {
+            return (ScalarValue::Null, ScalarValue::Null);
+        }
+        match ty {
+            DataType::Number => {
+                let x = match rng.below(4) {
+                    0 => i64::MIN,
+                    1 => i64::MAX,
+                    _ => rng.next() as i64,
+                };
+                (ScalarValue::Number(x), ScalarValue::Number(x))
+            }
+            DataType::String(size) => {
+                let len = rng.below(size + 8);
+                let value: String = (0..len)
+                    .map(|_| ['a', 'Z', '0', ' ', '"', 'é', '€'][rng.below(7)])
+                    .collect();
+                // Strings are cut to the column, less its length byte,
+                // without splitting a character
+                let mut fit = String::new();
+                for c in value.chars() {
+                    if fit.len() + c.len_utf8() >= *size {
+                        break;
+                    }
+                    fit.push(c);
+                }
+                (ScalarValue::String(value), ScalarValue::String(fit))
+            }
+            DataType::Char(size) => {
+                let len = rng.below(size + 8);
+                let value: String = (0..len)
+                    .map(|_| ['a', 'Z', '0', ' ', '"', 'é', '€'][rng.below(7)])
+                    .collect();
+                // Char strings are cut to the column and read back without
+                // trailing spaces
+                let mut fit = String::new();
+                for c in value.chars() {
+                    if fit.len() + c.len_utf8() > *size {
+                        break;
+                    }
+                    fit.push(c);
+                }
+                let fit = fit.trim_end_matches(' ').to_string();
+                (ScalarValue::String(value), ScalarValue::String(fit))
+            }
+            DataType::Custom(_) => unreachable!("random schemas have no custom types"),
+        }
+    }
+
+    #[test]
+    fn serialize_read_round_trip() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..500 {
+            let schema = random_schema(&mut rng);
+            let value_size = schema.row_size();
+            let mut page = LeafNode::new();
+            let cells = 1 + rng.below(LeafNode::capacity(value_size).min(8));
+            let mut expected = Vec::new();
+            for _ in 0..cells {
+                let (values, read): (Vec<_>, Vec<_>) = schema
+                    .feilds
+                    .iter()
+                    .map(|(_, ty)| random_value(&mut rng, ty))
+                    .unzip();
+                let key = rng.next() as i64;
+                assert_eq!(
+                    LeafNode::encode_cell(&schema, &Dictionary::default(), key, &values).len(),
+                    LeafNode::cell_size(&schema, &values)
+                );
+                assert!(page
+                    .leaf_node_split_and_insert(key, values, &schema, &Dictionary::default())
+                    .is_none());
+                expected.push((key, read));
+            }
+            expected.sort_by_key(|(key, _)| *key);
+
+            // Read back after every cell is written, so a cell spilling into
+            // its neighbour shows up too
+            for (index, (key, values)) in expected.iter().enumerate() {
+                let row = page
+                    .read_row(index, &schema, &Dictionary::default())
+                    .unwrap();
+                assert_eq!(row.key(), *key, "schema {:?}", schema.feilds);
+                assert_eq!(row.values(), values, "schema {:?}", schema.feilds);
+            }
+        }
+    }
+
+    #[test]
+    fn cells_in_order() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut page = LeafNode::new();
+        for key in [5, 1, 3] {
+            page.leaf_node_split_and_insert(
+                key,
+                vec![ScalarValue::Number(key)],
+                &schema,
+                &Dictionary::default(),
+            );
+        }
+
+        let dictionary = Dictionary::default();
+        let cells: Vec<_> = page.cells(&schema, &dictionary).collect();
+        assert_eq!(cells.len(), 3);
+        for (index, cell) in cells.iter().enumerate() {
+            let row = page
+                .read_row(index, &schema, &Dictionary::default())
+                .unwrap();
+            assert_eq!((cell.key(), cell.values()), (row.key(), row.values()));
+        }
+        let keys: Vec<i64> = cells.iter().map(|row| row.key()).collect();
+        assert_eq!(keys, [1, 3, 5]);
+        assert_eq!(
+            LeafNode::new()
+                .cells(&schema, &Dictionary::default())
+                .count(),
+            0
+        );
+    }
+
     #[test]
     fn insert_one() {
-        let schema = Schema {
-            feilds: vec![("a".to_string(), DataType::Number)],
-        };
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
         let mut page = LeafNode::new();
         assert_eq!(page.num_cells(), 0);
-        page.leaf_node_split_and_insert(0, vec![ScalarValue::Number(1)], &schema);
+        page.leaf_node_split_and_insert(
+            0,
+            vec![ScalarValue::Number(1)],
+            &schema,
+            &Dictionary::default(),
+        );
         assert_eq!(page.num_cells(), 1);
-        let (_, val) = page.read_row(0, &schema);
-        assert_eq!(val, vec![ScalarValue::Number(1)])
+        let row = page.read_row(0, &schema, &Dictionary::default()).unwrap();
+        assert_eq!(row.values(), &[ScalarValue::Number(1)])
+    }
+
+    #[test]
+    fn reads_past_last_cell() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let dictionary = Dictionary::default();
+        let mut page = LeafNode::new();
+        assert!(page.read_row(0, &schema, &dictionary).is_none());
+        assert_eq!(page.cell(0), None);
+        assert_eq!(page.key(0), None);
+
+        for key in 0..3 {
+            let values = vec![ScalarValue::Number(key)];
+            page.leaf_node_split_and_insert(key, values, &schema, &dictionary);
+        }
+        let num_cells = page.num_cells() as usize;
+        assert_eq!(page.key(num_cells - 1), Some(2));
+        // The slot past the last one is zeroed, which would have read as an
+        // empty cell at offset 0
+        assert!(page.read_row(num_cells, &schema, &dictionary).is_none());
+        assert_eq!(page.cell(num_cells), None);
+        assert!(page.cell_mut(num_cells).is_none());
+        assert_eq!(page.key(num_cells), None);
+        assert_eq!(page.key(usize::MAX), None);
     }
 
     #[test]
     fn insert_two() {
-        let schema = Schema {
-            feilds: vec![("a".to_string(), DataType::Number)],
-        };
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
         let mut page = LeafNode::new();
         assert_eq!(page.num_cells(), 0);
-        page.leaf_node_split_and_insert(1, vec![ScalarValue::Number(1)], &schema);
-        page.leaf_node_split_and_insert(0, vec![ScalarValue::Number(2)], &schema);
+        page.leaf_node_split_and_insert(
+            1,
+            vec![ScalarValue::Number(1)],
+            &schema,
+            &Dictionary::default(),
+        );
+        page.leaf_node_split_and_insert(
+            0,
+            vec![ScalarValue::Number(2)],
+            &schema,
+            &Dictionary::default(),
+        );
         assert_eq!(page.num_cells(), 2);
-        let (_, val) = page.read_row(0, &schema);
-        assert_eq!(val, vec![ScalarValue::Number(2)]);
-        let (_, val) = page.read_row(1, &schema);
-        assert_eq!(val, vec![ScalarValue::Number(1)]);
+        let row = page.read_row(0, &schema, &Dictionary::default()).unwrap();
+        assert_eq!(row.values(), &[ScalarValue::Number(2)]);
+        let row = page.read_row(1, &schema, &Dictionary::default()).unwrap();
+        assert_eq!(row.values(), &[ScalarValue::Number(1)]);
+    }
+
+    #[test]
+    fn read_row_by_name() {
+        let schema = Schema::new(vec![
+            ("a".to_string(), DataType::Number),
+            ("b".to_string(), DataType::String(10)),
+        ]);
+        let mut page = LeafNode::new();
+        page.leaf_node_split_and_insert(
+            3,
+            vec![
+                ScalarValue::Number(7),
+                ScalarValue::String("xyz".to_string()),
+            ],
+            &schema,
+            &Dictionary::default(),
+        );
+        let row = page.read_row(0, &schema, &Dictionary::default()).unwrap();
+        assert_eq!(row.key(), 3);
+        assert_eq!(row.get("a"), Some(&ScalarValue::Number(7)));
+        assert_eq!(row.get("b"), Some(&ScalarValue::String("xyz".to_string())));
+        assert_eq!(row.get("c"), None);
+        assert_eq!(row["b"], row[1]);
     }
 
     #[test]
     fn fill_and_split() {
-        let schema = Schema {
-            feilds: vec![("a".to_string(), DataType::Number)],
-        };
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
         let mut page = LeafNode::new();
         assert_eq!(page.num_cells(), 0);
-        let value_size = schema.row_size();
-        let max_cell = page.max_cells(value_size);
+        let max_cell = LeafNode::capacity(schema.row_size());
 
         for key in (0..max_cell).rev() {
             page.leaf_node_split_and_insert(
-                key as u32,
+                key as i64,
                 vec![ScalarValue::Number(key as i64)],
                 &schema,
+                &Dictionary::default(),
             );
-            assert!(page.binary_search(key as u32, value_size).is_some());
+            assert!(page.binary_search(key as i64).is_ok());
             assert_eq!(page.num_cells(), (max_cell - key) as u32);
         }
 
         let new_node = page
             .leaf_node_split_and_insert(
-                max_cell as u32,
+                max_cell as i64,
                 vec![ScalarValue::Number(max_cell as i64)],
                 &schema,
+                &Dictionary::default(),
             )
             .unwrap();
 
-        assert_eq!(new_node.num_cells(), (max_cell as u32 + 1) / 2);
+        assert_eq!(new_node.num_cells(), (max_cell as u32).div_ceil(2));
         assert_eq!(new_node.parent(), page.parent());
     }
+
+    #[test]
+    fn split_on_new_maximum() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let dictionary = Dictionary::default();
+        let mut page = LeafNode::new();
+        let filled = fill(&mut page, &schema, ScalarValue::Number) as i64;
+
+        // Appending past the largest key is the common case for rising keys
+        let max = filled + 100;
+        let new_node = page
+            .leaf_node_split_and_insert(max, vec![ScalarValue::Number(max)], &schema, &dictionary)
+            .unwrap();
+
+        let rows = |node: &LeafNode| -> Vec<(i64, ScalarValue)> {
+            (0..node.num_cells() as usize)
+                .map(|i| {
+                    let row = node.read_row(i, &schema, &dictionary).unwrap();
+                    (row.key(), row["a"].clone())
+                })
+                .collect()
+        };
+        let (left, right) = (rows(&page), rows(&new_node));
+        assert_eq!(left.len(), (filled as usize + 1).div_ceil(2));
+        assert_eq!(left.len() + right.len(), filled as usize + 1);
+
+        // Each half holds a contiguous run of the keys, in slot order, with
+        // every value still beside its own key
+        let keys: Vec<i64> = left.iter().chain(&right).map(|(key, _)| *key).collect();
+        let expected: Vec<i64> = (0..filled).chain([max]).collect();
+        assert_eq!(keys, expected);
+        for (key, value) in left.iter().chain(&right) {
+            assert_eq!(*value, ScalarValue::Number(*key));
+        }
+        assert_eq!(right.last().unwrap().0, max);
+        assert_eq!(new_node.max_key(), Some(max));
+        assert_eq!(page.max_key(), Some(left.last().unwrap().0));
+        assert_eq!(new_node.binary_search(max), Ok(right.len() - 1));
+    }
+
+    #[test]
+    fn insert_out_of_order() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut page = LeafNode::new();
+        for key in [5, 1, 3] {
+            page.leaf_node_split_and_insert(
+                key,
+                vec![ScalarValue::Number(key)],
+                &schema,
+                &Dictionary::default(),
+            );
+        }
+
+        let keys: Vec<i64> = (0..3).map(|i| page.key(i).unwrap()).collect();
+        assert_eq!(keys, vec![1, 3, 5]);
+        assert_eq!(page.binary_search(3), Ok(1));
+        assert_eq!(page.binary_search(4), Err(2));
+        assert_eq!(page.binary_search(9), Err(3));
+    }
+
+    #[test]
+    fn insert_keeps_keys_sorted() {
+        // Regression: missing keys used to be inserted at index 0
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut page = LeafNode::new();
+        for key in [2, 0, 1] {
+            page.leaf_node_split_and_insert(
+                key,
+                vec![ScalarValue::Number(key * 10)],
+                &schema,
+                &Dictionary::default(),
+            );
+        }
+
+        for i in 0..3 {
+            let row = page.read_row(i, &schema, &Dictionary::default()).unwrap();
+            assert_eq!(row.key(), i as i64);
+            assert_eq!(row["a"], ScalarValue::Number(i as i64 * 10));
+            assert_eq!(page.binary_search(i as i64), Ok(i));
+        }
+    }
+
+    // Inserts rows with keys from 0 up for as long as they fit, returning
+    // how many did
+    fn fill(page: &mut LeafNode, schema: &Schema, value: impl Fn(i64) -> ScalarValue) -> usize {
+        let mut key = 0;
+        while page.fits(LeafNode::cell_size(schema, &[value(key)])) {
+            let new_node = page.leaf_node_split_and_insert(
+                key,
+                vec![value(key)],
+                schema,
+                &Dictionary::default(),
+            );
+            assert!(new_node.is_none());
+            key += 1;
+        }
+        key as usize
+    }
+
+    #[test]
+    fn compact_numbers_pack_more_rows() {
+        let fixed = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut compact = fixed.clone();
+        compact.options[0].compact = true;
+        assert_eq!(compact.row_size(), 1 + MAX_VARINT_SIZE);
+
+        let small = |key: i64| ScalarValue::Number(key % 50 - 25);
+        let fixed_cells = fill(&mut LeafNode::new(), &fixed, small);
+        assert_eq!(fixed_cells, LeafNode::capacity(fixed.row_size()));
+        // Each small number takes one byte rather than eight
+        let mut page = LeafNode::new();
+        let compact_cells = fill(&mut page, &compact, small);
+        let cell_size = LeafNode::KEY_SIZE + 1 + 1;
+        assert_eq!(
+            compact_cells,
+            LeafNode::SPACE_FOR_CELLS / (LeafNode::SLOT_SIZE + cell_size)
+        );
+        assert!(compact_cells > fixed_cells * 4 / 3);
+        for (i, row) in page.cells(&compact, &Dictionary::default()).enumerate() {
+            assert_eq!(row.key(), i as i64);
+            assert_eq!(row[0], small(i as i64));
+        }
+
+        // Nulls take no bytes at all, and the extremes the most
+        let mut page = LeafNode::new();
+        for (key, (value, size)) in [
+            (ScalarValue::Null, 0),
+            (ScalarValue::Number(i64::MIN), MAX_VARINT_SIZE),
+            (ScalarValue::Number(i64::MAX), MAX_VARINT_SIZE),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let values = vec![value.clone()];
+            assert_eq!(
+                LeafNode::cell_size(&compact, &values),
+                LeafNode::KEY_SIZE + 1 + size
+            );
+            page.leaf_node_split_and_insert(key as i64, values, &compact, &Dictionary::default());
+            assert_eq!(
+                page.read_row(key, &compact, &Dictionary::default())
+                    .unwrap()[0],
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn removed_cells_make_room() {
+        let schema = Schema::new(vec![("a".to_string(), DataType::Number)]);
+        let mut page = LeafNode::new();
+        let capacity = LeafNode::capacity(schema.row_size());
+        for key in 0..capacity as i64 {
+            page.leaf_node_split_and_insert(
+                key,
+                vec![ScalarValue::Number(key)],
+                &schema,
+                &Dictionary::default(),
+            );
+        }
+        assert!(!page.fits(LeafNode::cell_size(&schema, &[ScalarValue::Number(0)])));
+
+        // Every other cell goes, leaving gaps between the rest
+        for index in (0..capacity / 2).rev() {
+            page.remove_cell(index * 2);
+        }
+        let odd = capacity / 2;
+        assert_eq!(page.num_cells() as usize, capacity - odd);
+        let keys: Vec<i64> = (0..page.num_cells() as usize)
+            .map(|i| page.key(i).unwrap())
+            .collect();
+        assert!(keys
+            .iter()
+            .all(|key| key % 2 == 1 || *key as usize == capacity - 1));
+
+        // The gaps are gathered up again once the free run in the middle is used
+        for index in 0..odd {
+            let key = capacity as i64 + index as i64;
+            let new_node = page.leaf_node_split_and_insert(
+                key,
+                vec![ScalarValue::Number(key)],
+                &schema,
+                &Dictionary::default(),
+            );
+            assert!(new_node.is_none());
+        }
+        assert_eq!(page.num_cells() as usize, capacity);
+        for (i, row) in page.cells(&schema, &Dictionary::default()).enumerate() {
+            assert_eq!(row[0], ScalarValue::Number(row.key()), "cell {i}");
+        }
+    }
 }